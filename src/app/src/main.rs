@@ -1,33 +1,461 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
 
-use interfaces::Item;
+use clap::{Args, Parser, Subcommand};
+
+use interfaces::{Item, UndefinedMacros, SETTINGS_UNDEFINED_MACROS};
 use parser::ScriptParser;
 use plugin_manager::PluginManager;
 use reader::ScriptReader;
-use runner::ScriptRunner;
-use validator::ScriptValidator;
+use runner::{RunLimits, ScriptRunner, SETTINGS_UNDEFINED_CONDITION};
+use utils::ini_parser::IniParserEx;
+use validator::{ScriptValidator, ValidateError, SETTINGS_ALLOW_PRIVILEGED};
 
 const SCRIPT_PATHNAME: &str = "script.txt";
 const INI_PATHNAME: &str = "settings.ini";
-const PLUGINS_PATH: &str = "target/debug";
+const INI_COMMON_SECTION: &str = "COMMON";
+const INI_SEARCH_DEPTH: usize = 5;
+
+// Process exit codes, one per failure class (2 is also used by clap for usage errors)
+const EXIT_USAGE: u8 = 2;
+const EXIT_PARSE: u8 = 3;
+const EXIT_VALIDATE: u8 = 4;
+const EXIT_PLUGIN_LOAD: u8 = 5;
+const EXIT_RUNTIME: u8 = 6;
+
+#[derive(Parser)]
+#[command(version, about = "uScript interpreter")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate the script and execute it
+    Run(ScriptArgs),
+    /// Parse and validate the script without executing it
+    Check(ScriptArgs),
+    /// Validate the script and execute it in parameter validation mode only
+    DryRun(ScriptArgs),
+    /// List the plugins found in the plugin directories
+    ListPlugins(PluginArgs),
+}
+
+#[derive(Args)]
+struct ScriptArgs {
+    /// Script file to process
+    #[arg(short, long, default_value = SCRIPT_PATHNAME)]
+    script: PathBuf,
+
+    /// Directory searched for INCLUDE files not found next to the
+    /// including script (can be repeated)
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Report every validation problem instead of stopping at the first one
+    #[arg(long)]
+    collect_all: bool,
+
+    /// Pre-define a constant macro, overriding a `:=` in the script (can be repeated)
+    #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, String)>,
+
+    /// How conditions that are empty or use undefined macros are handled
+    #[arg(long, value_name = "MODE", value_parser = ["error", "false"])]
+    undefined_condition: Option<String>,
 
-fn main() -> Result<(), Box<dyn Error>> {
+    /// How references to undefined macros are handled: warn and pass
+    /// `$NAME` through, or reject the script
+    #[arg(long, value_name = "MODE", value_parser = ["warn", "strict"])]
+    undefined_macros: Option<String>,
+
+    /// Allow the script to use a privileged plugin, in addition to the
+    /// [COMMON] ALLOW_PRIVILEGED ones (can be repeated)
+    #[arg(long = "allow-privileged", value_name = "PLUGIN")]
+    allow_privileged: Vec<String>,
+
+    #[command(flatten)]
+    limits: LimitArgs,
+
+    #[command(flatten)]
+    plugins: PluginArgs,
+}
+
+/// Watchdog limits, overriding the [COMMON] settings
+#[derive(Args)]
+struct LimitArgs {
+    /// Abort after executing this many statements
+    #[arg(long, value_name = "N")]
+    max_statements: Option<u64>,
+
+    /// Abort when any label is executed more than this many times
+    #[arg(long, value_name = "N")]
+    max_label_iterations: Option<u64>,
+
+    /// Iteration cap for a single label (can be repeated)
+    #[arg(long = "label-limit", value_name = "LABEL=N", value_parser = parse_label_limit)]
+    label_limits: Vec<(String, u64)>,
+
+    /// Abort when the script runs longer than this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Abort when CALL statements nest deeper than this
+    #[arg(long, value_name = "N")]
+    max_call_depth: Option<u64>,
+}
+
+#[derive(Args)]
+struct PluginArgs {
+    /// Settings file with the plugins configuration
+    #[arg(short, long, default_value = INI_PATHNAME)]
+    ini: PathBuf,
+
+    /// Directory to search for plugins (can be repeated, searched in order before
+    /// [COMMON] PLUGINS_DIRS and URUSTSCRIPT_PLUGINS_PATH; target/debug if none is set)
+    #[arg(short = 'p', long = "plugins-dir", value_name = "DIR")]
+    plugins_dirs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Run,
+    Check,
+    DryRun,
+}
+
+fn parse_define(input: &str) -> Result<(String, String), String> {
+    let (name, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{}`", input))?;
+    let name = name.trim();
+    let valid_name = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("invalid macro name `{}`", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_label_limit(input: &str) -> Result<(String, u64), String> {
+    let (label, limit) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected LABEL=N, got `{}`", input))?;
+    let limit = limit
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("invalid iteration count `{}`", limit))?;
+    Ok((label.trim().to_string(), limit))
+}
+
+/// The resolved [COMMON] section of the settings file, empty if there is none.
+fn common_settings(inipathname: &Path) -> HashMap<String, String> {
+    let mut iniparser = IniParserEx::default();
+    // a missing settings file is reported when loading the plugins
+    if !iniparser.load(inipathname) {
+        return HashMap::new();
+    }
+    iniparser
+        .get_resolved_section(INI_COMMON_SECTION, INI_SEARCH_DEPTH)
+        .unwrap_or_default()
+}
+
+/// A mode given on the command line, or else by the [COMMON] `key`.
+fn mode_setting<T>(
+    cli: Option<&String>,
+    common: &HashMap<String, String>,
+    key: &str,
+) -> Result<T, String>
+where
+    T: FromStr<Err = String> + Default,
+{
+    match cli.or_else(|| common.get(key)) {
+        Some(mode) => mode.parse(),
+        None => Ok(T::default()),
+    }
+}
+
+/// Apply the [COMMON] settings to the runner, overridden by the command line.
+fn configure_runner(
+    runner: &mut ScriptRunner,
+    args: &ScriptArgs,
+    common: &HashMap<String, String>,
+) -> Result<(), String> {
+    let mut limits = RunLimits::from_settings(common)?;
+    limits.max_statements = args.limits.max_statements.or(limits.max_statements);
+    limits.max_label_iterations = args
+        .limits
+        .max_label_iterations
+        .or(limits.max_label_iterations);
+    limits.timeout = args
+        .limits
+        .timeout
+        .map(Duration::from_secs)
+        .or(limits.timeout);
+    limits.max_call_depth = args.limits.max_call_depth.or(limits.max_call_depth);
+    limits
+        .label_limits
+        .extend(args.limits.label_limits.iter().cloned());
+    runner.set_limits(limits);
+
+    runner.set_undefined_condition(mode_setting(
+        args.undefined_condition.as_ref(),
+        common,
+        SETTINGS_UNDEFINED_CONDITION,
+    )?);
+    Ok(())
+}
+
+fn fail(code: u8, err: &dyn Error) -> ExitCode {
+    eprintln!("❌ {}", err);
+    ExitCode::from(code)
+}
+
+fn validate_exit_code(err: &ValidateError) -> u8 {
+    let loading_failed = err
+        .errors()
+        .iter()
+        .any(|e| matches!(e, ValidateError::PluginLoadingFailed { .. }));
+    if loading_failed {
+        EXIT_PLUGIN_LOAD
+    } else {
+        EXIT_VALIDATE
+    }
+}
+
+fn process_script(args: ScriptArgs, mode: Mode) -> ExitCode {
     let mut items = Vec::<Item>::new();
 
-    let reader = ScriptReader::new(SCRIPT_PATHNAME);
+    let mut reader = ScriptReader::new(&args.script);
     let mut parser = ScriptParser::new();
-    let validator = ScriptValidator::new();
+    let mut validator = ScriptValidator::new();
     let mut runner = ScriptRunner::new();
-    let mut plugin_manager = PluginManager::new(PLUGINS_PATH, INI_PATHNAME);
 
-    reader.read_script(&mut items)?;
-    parser.parse_script(&mut items)?;
-    validator.validate_script(&mut items, &mut plugin_manager)?;
-    runner.run_script(&mut items, &mut plugin_manager)?;
+    let common = common_settings(&args.plugins.ini);
+    let configured = configure_runner(&mut runner, &args, &common).and_then(|()| {
+        mode_setting::<UndefinedMacros>(
+            args.undefined_macros.as_ref(),
+            &common,
+            SETTINGS_UNDEFINED_MACROS,
+        )
+    });
+    match configured {
+        Ok(undefined_macros) => {
+            validator.set_undefined_macros(undefined_macros);
+            runner.set_undefined_macros(undefined_macros);
+        }
+        Err(message) => {
+            eprintln!("❌ {}", message);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    let mut plugin_manager = PluginManager::new(args.plugins.plugins_dirs, args.plugins.ini);
+
+    for dir in &args.include_dirs {
+        reader.add_include_dir(dir);
+    }
+    for (name, value) in &args.defines {
+        parser.add_macro(name, value);
+        validator.add_constant(name);
+    }
+    validator.set_collect_all(args.collect_all);
+    let allowed_privileged = common
+        .get(SETTINGS_ALLOW_PRIVILEGED)
+        .map(String::as_str)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .chain(args.allow_privileged.iter().map(String::as_str))
+        .filter(|plugin| !plugin.is_empty());
+    for plugin in allowed_privileged {
+        validator.allow_privileged(plugin);
+    }
+
+    if let Err(err) = reader.read_script(&mut items) {
+        return fail(EXIT_PARSE, &err);
+    }
+    if let Err(err) = parser.parse_script(&mut items) {
+        return fail(EXIT_PARSE, &err);
+    }
+    runner.set_symbols(parser.symbols().clone());
+    if let Err(err) = validator.validate_script(&mut items, &mut plugin_manager) {
+        return fail(validate_exit_code(&err), &err);
+    }
 
-    for item in items {
-        println!("{:?}", item);
+    let result = match mode {
+        Mode::Check => Ok(()),
+        Mode::DryRun => runner.dry_run_script(&items, &mut plugin_manager),
+        Mode::Run => runner.run_script(&items, &mut plugin_manager),
+    };
+    if let Err(err) = result {
+        return fail(EXIT_RUNTIME, &err);
     }
 
-    Ok(())
+    ExitCode::SUCCESS
+}
+
+/// Exit code `EXIT_PLUGIN_LOAD` when any plugin found could not be read.
+fn list_plugins(args: PluginArgs) -> ExitCode {
+    let plugin_manager = PluginManager::new(args.plugins_dirs, args.ini);
+    let mut code = ExitCode::SUCCESS;
+    for plugin in plugin_manager.discover() {
+        match plugin {
+            Ok(info) => {
+                println!(
+                    "{:<16} {:<10} {}",
+                    info.name,
+                    info.version.as_deref().unwrap_or("-"),
+                    info.path.display()
+                );
+                println!("{:<16} {}", "", info.commands.join(" "));
+            }
+            Err(err) => code = fail(EXIT_PLUGIN_LOAD, &err),
+        }
+    }
+    code
+}
+
+fn main() -> ExitCode {
+    match Cli::parse().command {
+        Command::Run(args) => process_script(args, Mode::Run),
+        Command::Check(args) => process_script(args, Mode::Check),
+        Command::DryRun(args) => process_script(args, Mode::DryRun),
+        Command::ListPlugins(args) => list_plugins(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+
+    fn script_args(args: &[&str]) -> ScriptArgs {
+        let cli = Cli::try_parse_from(["app"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Run(args) | Command::Check(args) | Command::DryRun(args) => args,
+            Command::ListPlugins(_) => panic!("not a script command"),
+        }
+    }
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn script_commands_have_defaults() {
+        let args = script_args(&["check"]);
+        assert_eq!(args.script, Path::new(SCRIPT_PATHNAME));
+        assert_eq!(args.plugins.ini, Path::new(INI_PATHNAME));
+        assert!(args.plugins.plugins_dirs.is_empty());
+        assert!(!args.collect_all);
+
+        let args = script_args(&[
+            "dry-run",
+            "-s",
+            "other.txt",
+            "--ini",
+            "other.ini",
+            "-p",
+            "a",
+            "--plugins-dir",
+            "b",
+            "-D",
+            "NAME=some value",
+        ]);
+        assert_eq!(args.script, Path::new("other.txt"));
+        assert_eq!(args.plugins.ini, Path::new("other.ini"));
+        assert_eq!(args.plugins.plugins_dirs, [Path::new("a"), Path::new("b")]);
+        assert_eq!(
+            args.defines,
+            [("NAME".to_string(), "some value".to_string())]
+        );
+    }
+
+    #[test]
+    fn usage_errors_are_reported() {
+        let error = |args: &[&str]| {
+            Cli::try_parse_from(["app"].iter().chain(args))
+                .err()
+                .map(|err| err.kind())
+        };
+        assert_eq!(
+            error(&[]),
+            Some(ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand)
+        );
+        assert_eq!(error(&["execute"]), Some(ErrorKind::InvalidSubcommand));
+        assert_eq!(
+            error(&["run", "-D", "1X=2"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(error(&["run", "-D", "X"]), Some(ErrorKind::ValueValidation));
+        assert_eq!(
+            error(&["list-plugins", "--script", "x"]),
+            Some(ErrorKind::UnknownArgument)
+        );
+        assert_eq!(error(&["list-plugins", "-p", "dir"]), None);
+    }
+
+    #[test]
+    fn limits_are_parsed() {
+        let args = script_args(&[
+            "run",
+            "--max-statements",
+            "100",
+            "--label-limit",
+            "LOOP = 5",
+            "--label-limit",
+            "AGAIN=0",
+            "--timeout",
+            "2",
+        ]);
+        assert_eq!(args.limits.max_statements, Some(100));
+        assert_eq!(args.limits.max_label_iterations, None);
+        assert_eq!(
+            args.limits.label_limits,
+            [("LOOP".to_string(), 5), ("AGAIN".to_string(), 0)]
+        );
+        assert_eq!(args.limits.timeout, Some(2));
+
+        assert!(parse_label_limit("LOOP").is_err());
+        assert!(parse_label_limit("LOOP=-1").is_err());
+    }
+
+    #[test]
+    fn unreadable_plugins_fail_the_listing() {
+        let dir = std::env::temp_dir().join(format!("app_list_plugins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ini = dir.join("settings.ini");
+        std::fs::write(&ini, "[COMMON]\n").unwrap();
+        let list = || {
+            let cli = Cli::try_parse_from([
+                "app".as_ref(),
+                "list-plugins".as_ref(),
+                "--ini".as_ref(),
+                ini.as_os_str(),
+                "-p".as_ref(),
+                dir.as_os_str(),
+            ])
+            .unwrap();
+            match cli.command {
+                Command::ListPlugins(args) => list_plugins(args),
+                _ => panic!("not list-plugins"),
+            }
+        };
+
+        assert_eq!(list(), ExitCode::SUCCESS);
+        std::fs::write(dir.join("junk_plugin.wasm"), "not a module").unwrap();
+        assert_eq!(list(), ExitCode::from(EXIT_PLUGIN_LOAD));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,7 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Position of a statement in the script source.
+/// `line` and the columns are 1-based byte positions, `end_column` is exclusive.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize,
+    pub source: String,
+    /// The `INCLUDE` statement that pulled in `file`, if any
+    pub included_from: Option<Box<Location>>,
+}
+
+impl Location {
+    /// Narrow the span to `text` inside it, preferring an occurrence that is
+    /// not part of a longer identifier, or return the whole span if `text`
+    /// is not found verbatim.
+    pub fn span_of(&self, text: &str) -> Location {
+        let start = self.column.saturating_sub(1);
+        let end = self.end_column.saturating_sub(1).min(self.source.len());
+        let Some(span) = self.source.get(start..end).filter(|_| !text.is_empty()) else {
+            return self.clone();
+        };
+
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut occurrences = span.match_indices(text).map(|(offset, _)| offset);
+        let whole_word = span
+            .match_indices(text)
+            .map(|(offset, _)| offset)
+            .find(|&offset| {
+                !span[..offset].chars().next_back().is_some_and(is_ident)
+                    && !span[offset + text.len()..]
+                        .chars()
+                        .next()
+                        .is_some_and(is_ident)
+            });
+
+        match whole_word.or_else(|| occurrences.next()) {
+            Some(offset) => Location {
+                column: self.column + offset,
+                end_column: self.column + offset + text.len(),
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// rustc-style rendering of the source line with the span underlined.
+    pub fn snippet(&self) -> String {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let source = self.source.trim_end();
+        let start = self.column.saturating_sub(1).min(source.len());
+        let end = self.end_column.saturating_sub(1).clamp(start, source.len());
+        let indent: String = source
+            .get(..start)
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source.get(start..end).map_or(0, |s| s.chars().count());
+        let carets = "^".repeat(width.max(1));
+
+        let mut snippet = format!(
+            "{gutter}--> {self}\n{gutter} |\n{number} | {source}\n{gutter} | {indent}{carets}"
+        );
+        let mut including = self.included_from.as_deref();
+        while let Some(location) = including {
+            snippet.push_str(&format!("\n{gutter} = note: included from {location}"));
+            including = location.included_from.as_deref();
+        }
+        snippet
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Item {
     pub line: String,
     pub token_type: TokenType,
+    pub location: Location,
 }
 
 #[derive(Debug, Default)]
@@ -28,6 +113,21 @@ pub enum TokenType {
         command: String,
         args: String,
     },
+    Assignment {
+        vmacro: String,
+        expression: String,
+    },
+    /// `LOCAL NAME = expression`, a variable dropped at the end of its block
+    Local {
+        vmacro: String,
+        expression: String,
+    },
+    Unset {
+        vmacro: String,
+    },
+    Goto {
+        label: String,
+    },
     IfGoTo {
         condition: String,
         label: String,
@@ -35,4 +135,126 @@ pub enum TokenType {
     Label {
         label: String,
     },
+    If {
+        condition: String,
+    },
+    ElseIf {
+        condition: String,
+    },
+    Else,
+    EndIf,
+    While {
+        condition: String,
+    },
+    EndWhile,
+    Repeat {
+        count: String,
+    },
+    EndRepeat,
+    /// `FOREACH NAME IN list`, `list` is a list value or whitespace separated words
+    Foreach {
+        vmacro: String,
+        list: String,
+    },
+    EndForeach,
+    Break,
+    Continue,
+    Function {
+        name: String,
+        params: Vec<String>,
+    },
+    EndFunction,
+    Return {
+        value: String,
+    },
+    /// `CALL NAME args`, or `VMACRO ?= CALL NAME args` to keep the returned value
+    Call {
+        function: String,
+        args: String,
+        vmacro: Option<String>,
+    },
+}
+
+/// [COMMON] settings key for [`UndefinedMacros`]
+pub const SETTINGS_UNDEFINED_MACROS: &str = "UNDEFINED_MACROS";
+
+/// How `$NAME` references to macros that are not defined are handled by the
+/// validator and the runner.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UndefinedMacros {
+    /// Report them as warnings and pass `$NAME` through verbatim,
+    /// for scripts that deliberately hand a literal `$` to a plugin
+    #[default]
+    Warn,
+    /// Reject the script before it runs, and abort if one slips through
+    Strict,
+}
+
+impl FromStr for UndefinedMacros {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "WARN" => Ok(UndefinedMacros::Warn),
+            "STRICT" => Ok(UndefinedMacros::Strict),
+            _ => Err(format!(
+                "Invalid value for: {} -> {}",
+                SETTINGS_UNDEFINED_MACROS, s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(source: &str) -> Location {
+        let column = source.len() - source.trim_start().len() + 1;
+        Location {
+            file: "script.txt".to_string(),
+            line: 7,
+            column,
+            end_column: column + source.trim().len(),
+            source: source.to_string(),
+            included_from: None,
+        }
+    }
+
+    #[test]
+    fn span_of_prefers_a_whole_word() {
+        let location = location("  X = XY + X");
+        let span = location.span_of("X");
+        assert_eq!((span.column, span.end_column), (3, 4));
+        let span = location.span_of("XY");
+        assert_eq!((span.column, span.end_column), (7, 9));
+    }
+
+    #[test]
+    fn span_of_missing_text_is_the_whole_statement() {
+        let location = location("  X = 1");
+        assert_eq!(location.span_of("Y"), location);
+        assert_eq!(location.span_of(""), location);
+    }
+
+    #[test]
+    fn snippet_underlines_the_span() {
+        let span = location("  IF $X == 1").span_of("$X");
+        assert_eq!(
+            span.snippet(),
+            " --> script.txt:7:6\n  |\n7 |   IF $X == 1\n  |      ^^"
+        );
+    }
+
+    #[test]
+    fn snippet_notes_the_include_chain() {
+        let mut span = location("X = 1");
+        span.included_from = Some(Box::new(Location {
+            file: "main.txt".to_string(),
+            ..location("INCLUDE lib.txt")
+        }));
+        assert!(span
+            .snippet()
+            .ends_with("\n  = note: included from main.txt:7:1"));
+    }
 }
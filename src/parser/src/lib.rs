@@ -3,42 +3,99 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 
-use interfaces::{Item, TokenType};
+use interfaces::{Item, Location, TokenType};
 use utils::string_utils;
+use utils::symbols::SymbolTable;
 
 const RE_LOAD_PLUGIN: &str =
     r#"^LOAD_PLUGIN\s+([A-Z0-9_]+)(?:\s*(<=|<|>=|>|==)\s*(v\d+\.\d+\.\d+\.\d+))?$"#;
 const RE_CONST_MACRO: &str = r#"^([A-Za-z_][A-Za-z0-9_]*)\s*:=\s*(.+)$"#;
 const RE_VAR_MACRO: &str =
     r#"^([A-Za-z_][A-Za-z0-9_]*)\s*\?=\s*([A-Z0-9_]+)\.([A-Z]+[A-Z0-9_]*)(?:\s+(.*))?$"#;
+const RE_ASSIGNMENT: &str = r#"^([A-Za-z_][A-Za-z0-9_]*)\s*=\s*([^=].*)$"#;
 const RE_COMMAND: &str = r#"^([A-Z0-9_]+)\.([A-Z]+[A-Z0-9_]*)(?:\s+(.*))?$"#;
-const RE_IF_GOTO_OR_GOTO: &str = r#"^(?:IF\s+(.*?)\s+)?GOTO\s+([A-Za-z0-9_]*)\s*$"#;
+const RE_GOTO: &str = r#"^GOTO\s+([A-Za-z0-9_]*)\s*$"#;
+const RE_IF_GOTO: &str = r#"^IF\s+(.*?)\s+GOTO\s+([A-Za-z0-9_]*)\s*$"#;
 const RE_LABEL: &str = r#"^LABEL\s+([A-Za-z0-9_]*)$"#;
+const RE_BLOCK_START: &str = r#"^(IF|ELSEIF|WHILE|REPEAT)\s+(.+?)\s*$"#;
+const RE_BLOCK_KEYWORD: &str =
+    r#"^(ELSE|ENDIF|ENDWHILE|ENDREPEAT|ENDFOREACH|BREAK|CONTINUE|ENDFUNCTION)$"#;
+const RE_FOREACH: &str = r#"^FOREACH\s+([A-Za-z_][A-Za-z0-9_]*)\s+IN\s+(.+?)\s*$"#;
+const RE_FUNCTION: &str =
+    r#"^FUNCTION\s+([A-Za-z_][A-Za-z0-9_]*)((?:\s+[A-Za-z_][A-Za-z0-9_]*)*)\s*$"#;
+const RE_CALL: &str =
+    r#"^(?:([A-Za-z_][A-Za-z0-9_]*)\s*\?=\s*)?CALL\s+([A-Za-z_][A-Za-z0-9_]*)(?:\s+(.*))?$"#;
+const RE_RETURN: &str = r#"^RETURN(?:\s+(.*?))?\s*$"#;
+const RE_LOCAL: &str = r#"^LOCAL\s+([A-Za-z_][A-Za-z0-9_]*)\s*=\s*([^=].*)$"#;
+const RE_UNSET: &str = r#"^UNSET\s+([A-Za-z_][A-Za-z0-9_]*)\s*$"#;
 
 #[derive(Debug)]
-enum ParseError {
-    InvalidStatement,
+#[non_exhaustive]
+pub enum ParseError {
+    InvalidStatement {
+        statement: String,
+        location: Box<Location>,
+    },
+    /// Plugin arguments with an opening `"` that is never closed
+    UnterminatedString { location: Box<Location> },
+}
+
+impl ParseError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ParseError::InvalidStatement { location, .. }
+            | ParseError::UnterminatedString { location } => Some(location),
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ParseError::InvalidStatement {
+                statement,
+                location,
+            } => {
+                write!(
+                    f,
+                    "invalid statement `{}`\n{}",
+                    statement,
+                    location.snippet()
+                )
+            }
+            ParseError::UnterminatedString { location } => {
+                write!(f, "unterminated quoted string\n{}", location.snippet())
+            }
+        }
     }
 }
 
 impl Error for ParseError {}
 
 pub struct ScriptParser {
-    macros: HashMap<String, String>,
+    defines: HashMap<String, String>,
+    symbols: SymbolTable,
 }
 
 impl ScriptParser {
     pub fn new() -> Self {
         ScriptParser {
-            macros: HashMap::new(),
+            defines: HashMap::new(),
+            symbols: SymbolTable::new(),
         }
     }
 
+    /// Pre-define a constant macro, it takes precedence over a `name := value`
+    /// in the script.
+    pub fn add_macro(&mut self, name: &str, value: &str) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    /// The constants of the script, the initial symbols of the runner.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
     fn is_load_plugin(&self, item: &mut Item) -> bool {
         let re = Regex::new(RE_LOAD_PLUGIN).unwrap();
 
@@ -69,7 +126,24 @@ impl ScriptParser {
         false
     }
 
-    fn is_const_macro(&mut self, item: &mut Item) -> bool {
+    /// Define the constants of the whole script before parsing it, a constant
+    /// can be used above its `:=` line. Redefinitions are left to the validator.
+    fn hoist_constants(&mut self, items: &[Item]) {
+        let re = Regex::new(RE_CONST_MACRO).unwrap();
+        self.symbols = SymbolTable::new();
+
+        for (name, value) in &self.defines {
+            let _ = self.symbols.define_constant(name, value);
+        }
+        for item in items {
+            if let Some(caps) = re.captures(&item.line) {
+                let _ = self.symbols.define_constant(&caps[1], &caps[2]);
+            }
+        }
+        self.symbols.resolve_constants();
+    }
+
+    fn is_const_macro(&self, item: &mut Item) -> bool {
         let re = Regex::new(RE_CONST_MACRO).unwrap();
         if let Some(caps) = re.captures(&item.line) {
             let cmacro = caps
@@ -81,7 +155,6 @@ impl ScriptParser {
                 .map(|m| m.as_str().to_string())
                 .unwrap_or_default();
 
-            self.macros.insert(cmacro.clone(), value.clone());
             item.token_type = TokenType::ConstantMacro { cmacro, value };
             return true;
         }
@@ -119,6 +192,56 @@ impl ScriptParser {
         false
     }
 
+    fn is_assignment(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_ASSIGNMENT).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let vmacro = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            let expression = caps
+                .get(2)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Assignment { vmacro, expression };
+            return true;
+        }
+        false
+    }
+
+    fn is_local(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_LOCAL).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let vmacro = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            let expression = caps
+                .get(2)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Local { vmacro, expression };
+            return true;
+        }
+        false
+    }
+
+    fn is_unset(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_UNSET).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let vmacro = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Unset { vmacro };
+            return true;
+        }
+        false
+    }
+
     fn is_command(&self, item: &mut Item) -> bool {
         let re = Regex::new(RE_COMMAND).unwrap();
         if let Some(caps) = re.captures(&item.line) {
@@ -145,8 +268,22 @@ impl ScriptParser {
         false
     }
 
+    fn is_goto(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_GOTO).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let label = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Goto { label };
+            return true;
+        }
+        false
+    }
+
     fn is_if_cond_goto(&self, item: &mut Item) -> bool {
-        let re = Regex::new(RE_IF_GOTO_OR_GOTO).unwrap();
+        let re = Regex::new(RE_IF_GOTO).unwrap();
         if let Some(caps) = re.captures(&item.line) {
             let condition = caps
                 .get(1)
@@ -177,15 +314,135 @@ impl ScriptParser {
         false
     }
 
-    fn parse_item(&mut self, item: &mut Item) -> bool {
+    // must be tried after is_if_cond_goto, IF ... GOTO is not a block
+    fn is_block_start(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_BLOCK_START).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let operand = caps
+                .get(2)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = match &caps[1] {
+                "IF" => TokenType::If { condition: operand },
+                "ELSEIF" => TokenType::ElseIf { condition: operand },
+                "WHILE" => TokenType::While { condition: operand },
+                _ => TokenType::Repeat { count: operand },
+            };
+            return true;
+        }
+        false
+    }
+
+    fn is_block_keyword(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_BLOCK_KEYWORD).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            item.token_type = match &caps[1] {
+                "ELSE" => TokenType::Else,
+                "ENDIF" => TokenType::EndIf,
+                "ENDWHILE" => TokenType::EndWhile,
+                "ENDREPEAT" => TokenType::EndRepeat,
+                "ENDFOREACH" => TokenType::EndForeach,
+                "BREAK" => TokenType::Break,
+                "CONTINUE" => TokenType::Continue,
+                _ => TokenType::EndFunction,
+            };
+            return true;
+        }
+        false
+    }
+
+    fn is_foreach(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_FOREACH).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let vmacro = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            let list = caps
+                .get(2)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Foreach { vmacro, list };
+            return true;
+        }
+        false
+    }
+
+    fn is_function(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_FUNCTION).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let name = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            let params = caps
+                .get(2)
+                .map(|m| m.as_str().split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Function { name, params };
+            return true;
+        }
+        false
+    }
+
+    fn is_call(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_CALL).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let vmacro = caps.get(1).map(|m| m.as_str().to_string());
+            let function = caps
+                .get(2)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+            let args = caps
+                .get(3)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Call {
+                function,
+                args,
+                vmacro,
+            };
+            return true;
+        }
+        false
+    }
+
+    fn is_return(&self, item: &mut Item) -> bool {
+        let re = Regex::new(RE_RETURN).unwrap();
+        if let Some(caps) = re.captures(&item.line) {
+            let value = caps
+                .get(1)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default();
+
+            item.token_type = TokenType::Return { value };
+            return true;
+        }
+        false
+    }
+
+    fn parse_item(&self, item: &mut Item) -> bool {
         if !self.is_load_plugin(item)
             && !self.is_const_macro(item)
             && !self.is_var_macro(item)
+            && !self.is_assignment(item)
+            && !self.is_local(item)
+            && !self.is_unset(item)
             && !self.is_command(item)
+            && !self.is_goto(item)
             && !self.is_if_cond_goto(item)
             && !self.is_label(item)
+            && !self.is_block_start(item)
+            && !self.is_block_keyword(item)
+            && !self.is_foreach(item)
+            && !self.is_function(item)
+            && !self.is_call(item)
+            && !self.is_return(item)
         {
-            println!("Invalid item [{:?}]", item);
             return false;
         }
         // destroy the line and free the memory
@@ -193,12 +450,99 @@ impl ScriptParser {
         true
     }
 
-    pub fn parse_script(&mut self, items: &mut Vec<Item>) -> Result<(), Box<dyn Error>> {
+    /// Put back the unsubstituted expression or plugin arguments of `item`,
+    /// parsed from `raw`. The runner resolves the constants when evaluating
+    /// them, pasting their text could split a value like `hello world`.
+    fn keep_raw_expression(&self, mut raw: Item, item: &mut Item) {
+        if !self.parse_item(&mut raw) {
+            return;
+        }
+        match (&mut item.token_type, raw.token_type) {
+            (
+                TokenType::Assignment { expression, .. },
+                TokenType::Assignment {
+                    expression: raw, ..
+                },
+            )
+            | (
+                TokenType::Local { expression, .. },
+                TokenType::Local {
+                    expression: raw, ..
+                },
+            )
+            | (
+                TokenType::If {
+                    condition: expression,
+                },
+                TokenType::If { condition: raw },
+            )
+            | (
+                TokenType::ElseIf {
+                    condition: expression,
+                },
+                TokenType::ElseIf { condition: raw },
+            )
+            | (
+                TokenType::While {
+                    condition: expression,
+                },
+                TokenType::While { condition: raw },
+            )
+            | (
+                TokenType::IfGoTo {
+                    condition: expression,
+                    ..
+                },
+                TokenType::IfGoTo { condition: raw, .. },
+            )
+            | (
+                TokenType::VariableMacro {
+                    args: expression, ..
+                },
+                TokenType::VariableMacro { args: raw, .. },
+            )
+            | (
+                TokenType::Command {
+                    args: expression, ..
+                },
+                TokenType::Command { args: raw, .. },
+            )
+            | (
+                TokenType::Call {
+                    args: expression, ..
+                },
+                TokenType::Call { args: raw, .. },
+            ) => *expression = raw,
+            _ => {}
+        }
+    }
+
+    pub fn parse_script(&mut self, items: &mut Vec<Item>) -> Result<(), ParseError> {
         println!("Parsing script ...");
+        self.hoist_constants(items);
         for item in items {
-            string_utils::replace_macros(&mut item.line, &self.macros);
+            let raw = Item {
+                line: item.line.clone(),
+                ..Item::default()
+            };
+            self.symbols.substitute(&mut item.line);
             if !self.parse_item(item) {
-                return Err(Box::new(ParseError::InvalidStatement));
+                return Err(ParseError::InvalidStatement {
+                    statement: item.line.clone(),
+                    location: Box::new(item.location.clone()),
+                });
+            }
+            self.keep_raw_expression(raw, item);
+            // quotes group the arguments as written, not the constant values
+            if let TokenType::VariableMacro { args, .. }
+            | TokenType::Command { args, .. }
+            | TokenType::Call { args, .. } = &item.token_type
+            {
+                if string_utils::split_args(args).is_none() {
+                    return Err(ParseError::UnterminatedString {
+                        location: Box::new(item.location.span_of(args)),
+                    });
+                }
             }
         }
         Ok(())
@@ -210,3 +554,134 @@ impl Default for ScriptParser {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> (ScriptParser, Vec<Item>) {
+        let mut parser = ScriptParser::new();
+        let mut items: Vec<Item> = lines
+            .iter()
+            .map(|line| Item {
+                line: line.to_string(),
+                ..Item::default()
+            })
+            .collect();
+        parser.parse_script(&mut items).unwrap();
+        (parser, items)
+    }
+
+    #[test]
+    fn constant_with_spaces_stays_a_reference_in_conditions() {
+        let (parser, items) = parse(&[
+            "NAME := hello world",
+            r#"IF $NAME == "hello world""#,
+            "X = $NAME",
+            "ENDIF",
+        ]);
+        assert!(matches!(
+            &items[1].token_type,
+            TokenType::If { condition } if condition == r#"$NAME == "hello world""#
+        ));
+        assert!(matches!(
+            &items[2].token_type,
+            TokenType::Assignment { expression, .. } if expression == "$NAME"
+        ));
+        assert_eq!(
+            parser.symbols().value("NAME").map(ToString::to_string),
+            Some("hello world".to_string())
+        );
+    }
+
+    #[test]
+    fn constants_are_substituted_outside_expressions() {
+        let (_, items) = parse(&[
+            "PLUGIN := MATH",
+            "TARGET := done",
+            "LOAD_PLUGIN $PLUGIN",
+            "IF 1 == 1 GOTO $TARGET",
+            "LABEL $TARGET",
+        ]);
+        assert!(matches!(
+            &items[2].token_type,
+            TokenType::LoadPlugin { plugin, .. } if plugin == "MATH"
+        ));
+        assert!(matches!(
+            &items[3].token_type,
+            TokenType::IfGoTo { condition, label } if condition == "1 == 1" && label == "done"
+        ));
+        assert!(matches!(&items[4].token_type, TokenType::Label { label } if label == "done"));
+    }
+
+    #[test]
+    fn invalid_statement_reports_its_location() {
+        let location = Location {
+            file: "script.txt".to_string(),
+            line: 4,
+            column: 1,
+            end_column: 11,
+            source: "not a stmt".to_string(),
+            included_from: None,
+        };
+        let mut items = vec![Item {
+            line: "not a stmt".to_string(),
+            token_type: TokenType::None,
+            location: location.clone(),
+        }];
+        let err = ScriptParser::new().parse_script(&mut items).unwrap_err();
+        assert!(matches!(
+            &err,
+            ParseError::InvalidStatement { statement, .. } if statement == "not a stmt"
+        ));
+        assert_eq!(err.location(), Some(&location));
+        assert!(err.to_string().ends_with(&location.snippet()));
+    }
+
+    #[test]
+    fn goto_is_unconditional_and_if_goto_keeps_its_condition() {
+        let (_, items) = parse(&["GOTO DONE", "IF $X GOTO DONE", "LABEL DONE"]);
+        assert!(matches!(&items[0].token_type, TokenType::Goto { label } if label == "DONE"));
+        assert!(matches!(
+            &items[1].token_type,
+            TokenType::IfGoTo { condition, label } if condition == "$X" && label == "DONE"
+        ));
+    }
+
+    #[test]
+    fn unterminated_quote_in_arguments_is_rejected() {
+        let mut items = vec![Item {
+            line: r#"UTILS.UPRINT "open"#.to_string(),
+            ..Item::default()
+        }];
+        assert!(matches!(
+            ScriptParser::new().parse_script(&mut items),
+            Err(ParseError::UnterminatedString { .. })
+        ));
+    }
+
+    #[test]
+    fn functions_and_calls() {
+        let (_, items) = parse(&[
+            "FUNCTION ADD A B",
+            "RETURN $A",
+            "ENDFUNCTION",
+            "SUM ?= CALL ADD 1 2",
+            "CALL ADD",
+        ]);
+        assert!(matches!(
+            &items[0].token_type,
+            TokenType::Function { name, params } if name == "ADD" && params == &["A", "B"]
+        ));
+        assert!(matches!(&items[1].token_type, TokenType::Return { value } if value == "$A"));
+        assert!(matches!(
+            &items[3].token_type,
+            TokenType::Call { function, args, vmacro }
+                if function == "ADD" && args == "1 2" && vmacro.as_deref() == Some("SUM")
+        ));
+        assert!(matches!(
+            &items[4].token_type,
+            TokenType::Call { args, vmacro: None, .. } if args.is_empty()
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * C view of the uRustScript plugin ABI, mirrors plugin/plugin_api/src.
+ *
+ * A plugin is a shared library named lib<name>_plugin.so (.dll, .dylib)
+ * exporting two functions:
+ *
+ *   uint32_t     plugin_abi_version(void);   must return PLUGIN_ABI_VERSION
+ *   PluginHandle plugin_create(void);
+ *
+ * Ownership rules:
+ *   - Strings and arrays passed to the plugin (command, arguments, settings)
+ *     are owned by the host and only valid during the call, copy them.
+ *   - Values returned by get_data and get_params are allocated by the plugin
+ *     and stay owned by it. The host copies them and hands them back to
+ *     free_data and free_params, which release them with the allocator that
+ *     made them. The host never frees plugin memory itself.
+ *   - destroy releases the plugin instance behind PluginHandle.ptr.
+ *
+ * Keep this file in sync with PLUGIN_ABI_VERSION in plugin_api.
+ */
+
+#ifndef URUSTSCRIPT_PLUGIN_API_H
+#define URUSTSCRIPT_PLUGIN_API_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PLUGIN_ABI_VERSION 2
+
+/* marks the two exported entry points */
+#if defined(_WIN32)
+#define PLUGIN_EXPORT __declspec(dllexport)
+#else
+#define PLUGIN_EXPORT __attribute__((visibility("default")))
+#endif
+
+/* keys of the capabilities reported by get_params */
+#define PARAMS_GET_CMDS_KEY "cmds"
+#define PARAMS_GET_VERS_KEY "vers"
+/* optional, commands needing the host authorisation of privileged plugins */
+#define PARAMS_GET_PRIV_CMDS_KEY "privcmds"
+
+/* settings handed to set_params from the plugin section of settings.ini */
+#define PARAMS_FAULT_TOLERANT "FAULT_TOLERANT"
+#define PARAMS_PRIVILEGED "PRIVILEGED"
+
+/* type of an FfiValue, selects the field of its payload */
+typedef enum FfiValueTag {
+    FfiValueTag_Bool,
+    FfiValueTag_Int,
+    FfiValueTag_Float,
+    FfiValueTag_Str,   /* UTF-8 text in buffer, not NUL terminated */
+    FfiValueTag_Bytes, /* bytes in buffer */
+    FfiValueTag_List,  /* FfiValue elements in buffer */
+} FfiValueTag;
+
+/* len elements at ptr, allocated by the plugin */
+typedef struct FfiBuffer {
+    void *ptr;
+    size_t len;
+} FfiBuffer;
+
+typedef union FfiPayload {
+    bool boolean;
+    int64_t integer;
+    double floating;
+    FfiBuffer buffer;
+} FfiPayload;
+
+/* result of the last command */
+typedef struct FfiValue {
+    FfiValueTag tag;
+    FfiPayload payload;
+} FfiValue;
+
+/* one key = value setting, NUL terminated UTF-8 owned by the host */
+typedef struct FfiParam {
+    const char *key;
+    const char *value;
+} FfiParam;
+
+/* one capability, e.g. "cmds" with the command names */
+typedef struct FfiCapability {
+    char *key;
+    char **values;
+    size_t count;
+} FfiCapability;
+
+/* len capabilities at ptr, allocated by the plugin */
+typedef struct FfiCapabilities {
+    FfiCapability *ptr;
+    size_t len;
+} FfiCapabilities;
+
+/* every entry receives ptr as its first argument */
+typedef struct PluginHandle {
+    void *ptr;
+    void (*destroy)(void *ptr);
+    void (*do_init)(void *ptr);
+    void (*do_enable)(void *ptr);
+    bool (*do_dispatch)(void *ptr, const char *cmd, size_t argc, const char *const *argv);
+    void (*do_cleanup)(void *ptr);
+    bool (*set_params)(void *ptr, const FfiParam *params, size_t len);
+    FfiCapabilities (*get_params)(void *ptr);
+    void (*free_params)(void *ptr, FfiCapabilities params);
+    FfiValue (*get_data)(void *ptr);
+    void (*free_data)(void *ptr, FfiValue data);
+    void (*reset_data)(void *ptr);
+    bool (*is_initialized)(void *ptr);
+    bool (*is_enabled)(void *ptr);
+    bool (*is_privileged)(void *ptr);
+    bool (*is_fault_tolerant)(void *ptr);
+    /* true once a call failed fatally (a Rust panic), every call is refused afterwards */
+    bool (*is_poisoned)(void *ptr);
+    /* FfiValueTag_Str message of that failure, handed back to free_data */
+    FfiValue (*get_panic)(void *ptr);
+} PluginHandle;
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* URUSTSCRIPT_PLUGIN_API_H */
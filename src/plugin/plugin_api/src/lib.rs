@@ -1,19 +1,35 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+mod params;
+mod value;
+
+pub use params::{FfiCapabilities, FfiCapability, FfiParam, FfiParams};
+pub use value::{FfiBuffer, FfiPayload, FfiValue, FfiValueTag, PluginValue};
 
 // ---------------------------
 // Shared constants
 // ---------------------------
 pub const PARAMS_GET_CMDS_KEY: &str = "cmds";
 pub const PARAMS_GET_VERS_KEY: &str = "vers";
+/// Commands needing the host authorisation, see `#[privileged]` in `plugin_macros`
+pub const PARAMS_GET_PRIV_CMDS_KEY: &str = "privcmds";
 pub const PARAMS_FAULT_TOLERANT: &str = "FAULT_TOLERANT";
 pub const PARAMS_PRIVILEGED: &str = "PRIVILEGED";
 
+/// Version of [`PluginHandle`] and the FFI types it uses, returned by the
+/// `plugin_abi_version` symbol of every plugin. Bump it on any layout change,
+/// together with `include/plugin_api.h`.
+pub const PLUGIN_ABI_VERSION: u32 = 2;
+
 // ---------------------------
 // Shared type definitions
 // ---------------------------
 pub type ParamsSet = HashMap<String, String>;
-pub type ParamsGet = HashMap<String, Vec<&'static str>>;
+pub type ParamsGet = HashMap<String, Vec<String>>;
+pub type PluginAbiVersionFn = unsafe extern "C" fn() -> u32;
 pub type PluginCreateFn = unsafe extern "C" fn() -> PluginHandle;
 
 // ---------------------------
@@ -22,11 +38,12 @@ pub type PluginCreateFn = unsafe extern "C" fn() -> PluginHandle;
 pub trait PluginInterface {
     fn do_init(&mut self);
     fn do_enable(&mut self);
-    fn do_dispatch(&mut self, cmd: &str, args: &str) -> bool;
+    fn do_dispatch(&mut self, cmd: &str, args: &[&str]) -> bool;
     fn do_cleanup(&mut self);
     fn set_params(&mut self, params: &ParamsSet) -> bool;
     fn get_params(&self, params: &mut ParamsGet);
-    fn get_data(&self) -> &str;
+    /// Result of the last command
+    fn get_data(&self) -> &PluginValue;
     fn reset_data(&mut self);
     fn is_initialized(&self) -> bool;
     fn is_enabled(&self) -> bool;
@@ -43,104 +60,186 @@ pub struct PluginHandle {
     pub destroy: unsafe extern "C" fn(*mut c_void),
     pub do_init: unsafe extern "C" fn(*mut c_void),
     pub do_enable: unsafe extern "C" fn(*mut c_void),
-    pub do_dispatch: unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char) -> bool,
+    /// command, argument count, argument vector
+    pub do_dispatch:
+        unsafe extern "C" fn(*mut c_void, *const c_char, usize, *const *const c_char) -> bool,
     pub do_cleanup: unsafe extern "C" fn(*mut c_void),
-    pub set_params: unsafe extern "C" fn(*mut c_void, *const ParamsSet) -> bool,
-    pub get_params: unsafe extern "C" fn(*mut c_void, *mut ParamsGet),
-    pub get_data: unsafe extern "C" fn(*mut c_void) -> *const c_char,
+    /// settings array and its length, owned by the host
+    pub set_params: unsafe extern "C" fn(*mut c_void, *const FfiParam, usize) -> bool,
+    /// The capabilities are owned by the plugin, hand them back to `free_params` once copied
+    pub get_params: unsafe extern "C" fn(*mut c_void) -> FfiCapabilities,
+    pub free_params: unsafe extern "C" fn(*mut c_void, FfiCapabilities),
+    /// The value is owned by the plugin, hand it back to `free_data` once copied
+    pub get_data: unsafe extern "C" fn(*mut c_void) -> FfiValue,
+    pub free_data: unsafe extern "C" fn(*mut c_void, FfiValue),
     pub reset_data: unsafe extern "C" fn(*mut c_void),
     pub is_initialized: unsafe extern "C" fn(*mut c_void) -> bool,
     pub is_enabled: unsafe extern "C" fn(*mut c_void) -> bool,
     pub is_privileged: unsafe extern "C" fn(*mut c_void) -> bool,
     pub is_fault_tolerant: unsafe extern "C" fn(*mut c_void) -> bool,
+    /// True once a call panicked, the plugin refuses every call afterwards
+    pub is_poisoned: unsafe extern "C" fn(*mut c_void) -> bool,
+    /// Message of the panic as a `Str` value, handed back to `free_data` once copied
+    pub get_panic: unsafe extern "C" fn(*mut c_void) -> FfiValue,
 }
 
 // ---------------------------
 // Generic FFI handle builder
 // ---------------------------
+
+/// A plugin behind its handle. Unwinding out of an `extern "C"` function is
+/// undefined behaviour, so every call is caught here and the first panic
+/// poisons the plugin.
+struct Guarded<T> {
+    plugin: T,
+    panic: Option<String>,
+}
+
+impl<T> Guarded<T> {
+    /// Run `f` on the plugin, `None` if it panics or is already poisoned.
+    fn call<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if self.panic.is_some() {
+            return None;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut self.plugin))) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                self.panic = Some(panic_message(payload.as_ref()));
+                None
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
 pub fn make_handle<T: PluginInterface + 'static>(plugin: T) -> PluginHandle {
     // --- Generic FFI glue ---
+    unsafe fn guarded<'a, T>(ptr: *mut c_void) -> &'a mut Guarded<T> {
+        debug_assert!(!ptr.is_null());
+        &mut *ptr.cast::<Guarded<T>>()
+    }
+
     unsafe extern "C" fn destroy<T: PluginInterface>(ptr: *mut c_void) {
         if !ptr.is_null() {
-            drop(Box::from_raw(ptr.cast::<T>()));
+            let plugin = Box::from_raw(ptr.cast::<Guarded<T>>());
+            // a panicking Drop must not unwind into the host either
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(plugin)));
         }
     }
 
     unsafe extern "C" fn do_init<T: PluginInterface>(ptr: *mut c_void) {
-        debug_assert!(!ptr.is_null());
-        (&mut *ptr.cast::<T>()).do_init();
+        guarded::<T>(ptr).call(|plugin| plugin.do_init());
     }
 
     unsafe extern "C" fn do_enable<T: PluginInterface>(ptr: *mut c_void) {
-        debug_assert!(!ptr.is_null());
-        (&mut *ptr.cast::<T>()).do_enable();
+        guarded::<T>(ptr).call(|plugin| plugin.do_enable());
     }
 
     unsafe extern "C" fn do_dispatch<T: PluginInterface>(
         ptr: *mut c_void,
         cmd: *const c_char,
-        args: *const c_char,
+        argc: usize,
+        argv: *const *const c_char,
     ) -> bool {
-        debug_assert!(!ptr.is_null());
-        let plugin = &mut *ptr.cast::<T>();
         let cmd_str = CStr::from_ptr(cmd).to_str().unwrap_or_default();
-        let args_str = CStr::from_ptr(args).to_str().unwrap_or_default();
-        plugin.do_dispatch(cmd_str, args_str)
+        let args: Vec<&str> = (0..argc)
+            .map(|i| CStr::from_ptr(*argv.add(i)).to_str().unwrap_or_default())
+            .collect();
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.do_dispatch(cmd_str, &args))
+            .unwrap_or(false)
     }
 
     unsafe extern "C" fn do_cleanup<T: PluginInterface>(ptr: *mut c_void) {
-        debug_assert!(!ptr.is_null());
-        (&mut *ptr.cast::<T>()).do_cleanup();
+        guarded::<T>(ptr).call(|plugin| plugin.do_cleanup());
     }
 
     unsafe extern "C" fn set_params<T: PluginInterface>(
         ptr: *mut c_void,
-        params: *const ParamsSet,
+        params: *const FfiParam,
+        len: usize,
     ) -> bool {
-        debug_assert!(!ptr.is_null());
-        (&mut *ptr.cast::<T>()).set_params(&*params)
+        let params = FfiParams::to_params(params, len);
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.set_params(&params))
+            .unwrap_or(false)
+    }
+
+    unsafe extern "C" fn get_params<T: PluginInterface>(ptr: *mut c_void) -> FfiCapabilities {
+        let mut params = ParamsGet::new();
+        guarded::<T>(ptr).call(|plugin| plugin.get_params(&mut params));
+        FfiCapabilities::new(&params)
     }
 
-    unsafe extern "C" fn get_params<T: PluginInterface>(ptr: *mut c_void, params: *mut ParamsGet) {
+    // same as free_data, released by the allocator that made them
+    unsafe extern "C" fn free_params(ptr: *mut c_void, params: FfiCapabilities) {
         debug_assert!(!ptr.is_null());
-        (&*ptr.cast::<T>()).get_params(&mut *params);
+        params.release();
     }
 
-    unsafe extern "C" fn get_data<T: PluginInterface>(ptr: *mut c_void) -> *const c_char {
+    unsafe extern "C" fn get_data<T: PluginInterface>(ptr: *mut c_void) -> FfiValue {
+        guarded::<T>(ptr)
+            .call(|plugin| FfiValue::new(plugin.get_data()))
+            .unwrap_or_else(|| FfiValue::new(&PluginValue::default()))
+    }
+
+    // compiled into the plugin, so the buffers go back to the allocator that made them
+    unsafe extern "C" fn free_data(ptr: *mut c_void, data: FfiValue) {
         debug_assert!(!ptr.is_null());
-        let plugin = &*ptr.cast::<T>();
-        CString::new(plugin.get_data())
-            .unwrap_or_default()
-            .into_raw()
+        data.release();
     }
 
     unsafe extern "C" fn reset_data<T: PluginInterface>(ptr: *mut c_void) {
-        debug_assert!(!ptr.is_null());
-        (&mut *ptr.cast::<T>()).reset_data();
+        guarded::<T>(ptr).call(|plugin| plugin.reset_data());
     }
 
     unsafe extern "C" fn is_initialized<T: PluginInterface>(ptr: *mut c_void) -> bool {
-        debug_assert!(!ptr.is_null());
-        (&*ptr.cast::<T>()).is_initialized()
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.is_initialized())
+            .unwrap_or(false)
     }
 
     unsafe extern "C" fn is_enabled<T: PluginInterface>(ptr: *mut c_void) -> bool {
-        debug_assert!(!ptr.is_null());
-        (&*ptr.cast::<T>()).is_enabled()
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.is_enabled())
+            .unwrap_or(false)
     }
 
     unsafe extern "C" fn is_privileged<T: PluginInterface>(ptr: *mut c_void) -> bool {
-        debug_assert!(!ptr.is_null());
-        (&*ptr.cast::<T>()).is_privileged()
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.is_privileged())
+            .unwrap_or(false)
     }
 
     unsafe extern "C" fn is_fault_tolerant<T: PluginInterface>(ptr: *mut c_void) -> bool {
-        debug_assert!(!ptr.is_null());
-        (&*ptr.cast::<T>()).is_fault_tolerant()
+        guarded::<T>(ptr)
+            .call(|plugin| plugin.is_fault_tolerant())
+            .unwrap_or(false)
+    }
+
+    unsafe extern "C" fn is_poisoned<T: PluginInterface>(ptr: *mut c_void) -> bool {
+        guarded::<T>(ptr).panic.is_some()
+    }
+
+    unsafe extern "C" fn get_panic<T: PluginInterface>(ptr: *mut c_void) -> FfiValue {
+        let message = guarded::<T>(ptr).panic.clone().unwrap_or_default();
+        FfiValue::new(&PluginValue::Str(message))
     }
 
     // --- Allocate and return handle ---
-    let boxed = Box::new(plugin);
+    let boxed = Box::new(Guarded {
+        plugin,
+        panic: None,
+    });
 
     PluginHandle {
         ptr: Box::into_raw(boxed).cast::<c_void>(),
@@ -151,12 +250,16 @@ pub fn make_handle<T: PluginInterface + 'static>(plugin: T) -> PluginHandle {
         do_cleanup: do_cleanup::<T>,
         set_params: set_params::<T>,
         get_params: get_params::<T>,
+        free_params,
         get_data: get_data::<T>,
+        free_data,
         reset_data: reset_data::<T>,
         is_initialized: is_initialized::<T>,
         is_enabled: is_enabled::<T>,
         is_privileged: is_privileged::<T>,
         is_fault_tolerant: is_fault_tolerant::<T>,
+        is_poisoned: is_poisoned::<T>,
+        get_panic: get_panic::<T>,
     }
 }
 
@@ -164,39 +267,295 @@ pub fn make_handle<T: PluginInterface + 'static>(plugin: T) -> PluginHandle {
 // Plugin function wrappers
 // ---------------------------
 
+// The lifecycle of a plugin is create -> set_params -> init -> enable ->
+// dispatch -> cleanup -> destroy, the wrappers refuse the calls made out of
+// order. Dispatching before enable is the parameter validation (dry) mode.
+
+/// Why [`plugin_do_dispatch`] did not execute a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The command failed and the plugin is not fault tolerant, or the
+    /// plugin is not initialized
+    Failed,
+    /// The plugin panicked while executing the command
+    Panicked(String),
+    /// Refused, the plugin panicked in an earlier call
+    Poisoned(String),
+}
+
 /// # Safety
 /// The caller must ensure `handle` points to a valid [`PluginHandle`].
-#[allow(clippy::unnecessary_map_or)]
-pub unsafe fn plugin_do_dispatch(handle: *mut PluginHandle, cmd: &str, args: &str) -> bool {
-    handle.as_mut().map_or(false, |plugin| {
-        let c_cmd = CString::new(cmd).expect("invalid cmd");
-        let c_args = CString::new(args).expect("invalid args");
+pub unsafe fn plugin_do_dispatch(
+    handle: *mut PluginHandle,
+    cmd: &str,
+    args: &[String],
+) -> Result<(), DispatchError> {
+    let Some(plugin) = handle.as_mut() else {
+        return Err(DispatchError::Failed);
+    };
+    if let Some(message) = plugin_panic(plugin) {
+        return Err(DispatchError::Poisoned(message));
+    }
+    if !(plugin.is_initialized)(plugin.ptr) {
+        return Err(DispatchError::Failed);
+    }
 
-        let success = (plugin.do_dispatch)(plugin.ptr, c_cmd.as_ptr(), c_args.as_ptr());
-        let is_fault_tolerant = (plugin.is_fault_tolerant)(plugin.ptr);
+    let c_cmd = CString::new(cmd).expect("invalid cmd");
+    let c_args: Vec<CString> = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).expect("invalid args"))
+        .collect();
+    let argv: Vec<*const c_char> = c_args.iter().map(|arg| arg.as_ptr()).collect();
 
-        success || is_fault_tolerant
+    let success = (plugin.do_dispatch)(plugin.ptr, c_cmd.as_ptr(), argv.len(), argv.as_ptr());
+    // a panic is never excused by fault tolerance
+    if let Some(message) = plugin_panic(plugin) {
+        return Err(DispatchError::Panicked(message));
+    }
+    let is_fault_tolerant = (plugin.is_fault_tolerant)(plugin.ptr);
+
+    if success || is_fault_tolerant {
+        Ok(())
+    } else {
+        Err(DispatchError::Failed)
+    }
+}
+
+/// Message of the panic that poisoned the plugin, if any.
+///
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_panic(handle: *mut PluginHandle) -> Option<String> {
+    let plugin = handle.as_mut()?;
+    if !(plugin.is_poisoned)(plugin.ptr) {
+        return None;
+    }
+    let data = (plugin.get_panic)(plugin.ptr);
+    let message = data.to_value();
+    (plugin.free_data)(plugin.ptr, data);
+    match message {
+        PluginValue::Str(message) => Some(message),
+        other => Some(format!("{:?}", other)),
+    }
+}
+
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_set_params(handle: *mut PluginHandle, params: &ParamsSet) -> bool {
+    handle.as_mut().is_some_and(|plugin| {
+        let params = FfiParams::new(params);
+        (plugin.set_params)(plugin.ptr, params.as_ptr(), params.len())
+    })
+}
+
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_get_params(handle: *mut PluginHandle) -> ParamsGet {
+    handle.as_mut().map_or_else(ParamsGet::new, |plugin| {
+        let capabilities = (plugin.get_params)(plugin.ptr);
+        let params = capabilities.to_params();
+        // allocated by the plugin, so released by the plugin
+        (plugin.free_params)(plugin.ptr, capabilities);
+        params
+    })
+}
+
+/// Whether the plugin runs with `PRIVILEGED = TRUE`.
+///
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_is_privileged(handle: *mut PluginHandle) -> bool {
+    handle
+        .as_mut()
+        .is_some_and(|plugin| (plugin.is_privileged)(plugin.ptr))
+}
+
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_get_data(handle: *mut PluginHandle) -> PluginValue {
+    handle.as_mut().map_or_else(PluginValue::default, |plugin| {
+        let data = (plugin.get_data)(plugin.ptr);
+        let value = data.to_value();
+        // allocated by the plugin, so released by the plugin
+        (plugin.free_data)(plugin.ptr, data);
+        value
     })
 }
 
+/// Initialize a plugin once its parameters are set, false if it was already
+/// initialized or does not report being initialized.
+///
 /// # Safety
 /// The caller must ensure `handle` points to a valid [`PluginHandle`].
-pub unsafe fn plugin_get_data(handle: *mut PluginHandle) -> String {
-    handle.as_mut().map_or_else(String::new, |plugin| {
-        let c_str = (plugin.get_data)(plugin.ptr);
-        if c_str.is_null() {
-            String::new()
-        } else {
-            CStr::from_ptr(c_str).to_string_lossy().into_owned()
+pub unsafe fn plugin_do_init(handle: *mut PluginHandle) -> bool {
+    handle.as_mut().is_some_and(|plugin| {
+        if (plugin.is_initialized)(plugin.ptr) {
+            return false;
         }
+        (plugin.do_init)(plugin.ptr);
+        (plugin.is_initialized)(plugin.ptr)
     })
 }
 
+/// Enable an initialized plugin, false if it is not initialized or does not
+/// report being enabled.
+///
 /// # Safety
 /// The caller must ensure `handle` points to a valid [`PluginHandle`].
 pub unsafe fn plugin_do_enable(handle: *mut PluginHandle) -> bool {
     handle.as_mut().is_some_and(|plugin| {
+        if !(plugin.is_initialized)(plugin.ptr) {
+            return false;
+        }
         (plugin.do_enable)(plugin.ptr);
+        (plugin.is_enabled)(plugin.ptr)
+    })
+}
+
+/// Release what an initialized plugin holds, before `destroy`. False if the
+/// plugin is not initialized, so there is nothing to clean up.
+///
+/// # Safety
+/// The caller must ensure `handle` points to a valid [`PluginHandle`].
+pub unsafe fn plugin_do_cleanup(handle: *mut PluginHandle) -> bool {
+    handle.as_mut().is_some_and(|plugin| {
+        if !(plugin.is_initialized)(plugin.ptr) {
+            return false;
+        }
+        (plugin.do_cleanup)(plugin.ptr);
         true
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the lifecycle calls it receives.
+    #[derive(Default)]
+    struct LifecyclePlugin {
+        calls: Rc<RefCell<Vec<&'static str>>>,
+        initialized: bool,
+        enabled: bool,
+        data: PluginValue,
+    }
+
+    impl PluginInterface for LifecyclePlugin {
+        fn do_init(&mut self) {
+            self.calls.borrow_mut().push("init");
+            self.initialized = true;
+        }
+        fn do_enable(&mut self) {
+            self.calls.borrow_mut().push("enable");
+            self.enabled = true;
+        }
+        fn do_dispatch(&mut self, cmd: &str, _args: &[&str]) -> bool {
+            self.calls.borrow_mut().push("dispatch");
+            self.data = PluginValue::Bool(self.enabled);
+            if cmd == "PANIC" {
+                panic!("bad command");
+            }
+            cmd == "OK"
+        }
+        fn do_cleanup(&mut self) {
+            self.calls.borrow_mut().push("cleanup");
+            self.initialized = false;
+            self.enabled = false;
+        }
+        fn set_params(&mut self, _params: &ParamsSet) -> bool {
+            true
+        }
+        fn get_params(&self, _params: &mut ParamsGet) {}
+        fn get_data(&self) -> &PluginValue {
+            &self.data
+        }
+        fn reset_data(&mut self) {
+            self.data.clear();
+        }
+        fn is_initialized(&self) -> bool {
+            self.initialized
+        }
+        fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+        fn is_privileged(&self) -> bool {
+            false
+        }
+        fn is_fault_tolerant(&self) -> bool {
+            false
+        }
+    }
+
+    fn handle() -> (PluginHandle, Rc<RefCell<Vec<&'static str>>>) {
+        let plugin = LifecyclePlugin::default();
+        let calls = plugin.calls.clone();
+        (make_handle(plugin), calls)
+    }
+
+    #[test]
+    fn calls_before_init_are_refused() {
+        let (mut handle, calls) = handle();
+        unsafe {
+            assert!(!plugin_do_enable(&mut handle));
+            assert!(!plugin_do_cleanup(&mut handle));
+            assert_eq!(
+                plugin_do_dispatch(&mut handle, "OK", &[]),
+                Err(DispatchError::Failed)
+            );
+            assert!(calls.borrow().is_empty());
+            (handle.destroy)(handle.ptr);
+        }
+    }
+
+    #[test]
+    fn lifecycle_runs_in_order() {
+        let (mut handle, calls) = handle();
+        unsafe {
+            assert!(plugin_do_init(&mut handle));
+            assert!(!plugin_do_init(&mut handle));
+            // dispatching before enable validates the parameters
+            plugin_do_dispatch(&mut handle, "OK", &[]).unwrap();
+            assert_eq!(plugin_get_data(&mut handle), PluginValue::Bool(false));
+            assert!(plugin_do_enable(&mut handle));
+            plugin_do_dispatch(&mut handle, "OK", &[]).unwrap();
+            assert_eq!(plugin_get_data(&mut handle), PluginValue::Bool(true));
+            assert_eq!(
+                plugin_do_dispatch(&mut handle, "FAIL", &[]),
+                Err(DispatchError::Failed)
+            );
+            assert!(plugin_do_cleanup(&mut handle));
+            assert!(!plugin_do_cleanup(&mut handle));
+            (handle.destroy)(handle.ptr);
+        }
+        assert_eq!(
+            *calls.borrow(),
+            ["init", "dispatch", "enable", "dispatch", "dispatch", "cleanup"]
+        );
+    }
+
+    #[test]
+    fn panic_poisons_the_plugin() {
+        let (mut handle, calls) = handle();
+        unsafe {
+            assert!(plugin_do_init(&mut handle));
+            assert_eq!(plugin_panic(&mut handle), None);
+            assert_eq!(
+                plugin_do_dispatch(&mut handle, "PANIC", &[]),
+                Err(DispatchError::Panicked("bad command".to_string()))
+            );
+            assert_eq!(plugin_panic(&mut handle).as_deref(), Some("bad command"));
+            assert_eq!(
+                plugin_do_dispatch(&mut handle, "OK", &[]),
+                Err(DispatchError::Poisoned("bad command".to_string()))
+            );
+            // a poisoned plugin is no longer called, and reports safe defaults
+            assert!(!plugin_do_enable(&mut handle));
+            assert!(!plugin_do_cleanup(&mut handle));
+            assert_eq!(plugin_get_data(&mut handle), PluginValue::default());
+            (handle.destroy)(handle.ptr);
+        }
+        assert_eq!(*calls.borrow(), ["init", "dispatch"]);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
+use std::{ptr, slice};
+
+use crate::{ParamsGet, ParamsSet};
+
+/// One `key = value` setting handed to `set_params`, both NUL-terminated
+/// UTF-8 owned by the host for the duration of the call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiParam {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+/// One capability reported by `get_params`, e.g. `cmds` with the command names.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiCapability {
+    pub key: *mut c_char,
+    pub values: *mut *mut c_char,
+    pub count: usize,
+}
+
+/// `len` capabilities at `ptr`, allocated by the plugin.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiCapabilities {
+    pub ptr: *mut FfiCapability,
+    pub len: usize,
+}
+
+/// Text as a C string, cut at an embedded NUL.
+fn c_string(text: &str) -> CString {
+    let text = text.split('\0').next().unwrap_or_default();
+    CString::new(text).unwrap_or_default()
+}
+
+/// # Safety
+/// `text` must be null or a valid NUL-terminated string.
+unsafe fn from_c_string(text: *const c_char) -> String {
+    if text.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(text).to_string_lossy().into_owned()
+    }
+}
+
+/// # Safety
+/// `ptr` must be null or point to `len` initialized elements.
+unsafe fn as_slice<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Take back a boxed slice handed out with `Box::into_raw`.
+///
+/// # Safety
+/// `ptr` and `len` must come from `Box::<[T]>::into_raw` in the same module.
+unsafe fn into_boxed<T>(ptr: *mut T, len: usize) -> Box<[T]> {
+    if ptr.is_null() {
+        Box::new([])
+    } else {
+        Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len))
+    }
+}
+
+/// Settings of a plugin in their C form, keeps the strings alive as long as
+/// the [`FfiParam`] array is in use.
+pub struct FfiParams {
+    _strings: Vec<(CString, CString)>,
+    params: Vec<FfiParam>,
+}
+
+impl FfiParams {
+    pub fn new(params: &ParamsSet) -> Self {
+        let strings: Vec<(CString, CString)> = params
+            .iter()
+            .map(|(key, value)| (c_string(key), c_string(value)))
+            .collect();
+        let params = strings
+            .iter()
+            .map(|(key, value)| FfiParam {
+                key: key.as_ptr(),
+                value: value.as_ptr(),
+            })
+            .collect();
+        FfiParams {
+            _strings: strings,
+            params,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const FfiParam {
+        self.params.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Copy `len` settings at `params` back into a map.
+    ///
+    /// # Safety
+    /// `params` must point to `len` [`FfiParam`] with valid strings.
+    pub unsafe fn to_params(params: *const FfiParam, len: usize) -> ParamsSet {
+        as_slice(params, len)
+            .iter()
+            .map(|param| (from_c_string(param.key), from_c_string(param.value)))
+            .collect()
+    }
+}
+
+impl FfiCapabilities {
+    /// Marshal `params`, the strings are copied into buffers owned by the
+    /// returned value.
+    pub fn new(params: &ParamsGet) -> Self {
+        let capabilities: Box<[FfiCapability]> = params
+            .iter()
+            .map(|(key, values)| {
+                let values: Box<[*mut c_char]> = values
+                    .iter()
+                    .map(|value| c_string(value).into_raw())
+                    .collect();
+                let count = values.len();
+                FfiCapability {
+                    key: c_string(key).into_raw(),
+                    values: Box::into_raw(values).cast::<*mut c_char>(),
+                    count,
+                }
+            })
+            .collect();
+        let len = capabilities.len();
+        FfiCapabilities {
+            ptr: Box::into_raw(capabilities).cast::<FfiCapability>(),
+            len,
+        }
+    }
+
+    /// Release the buffers allocated by [`FfiCapabilities::new`].
+    ///
+    /// # Safety
+    /// `self` must come from `FfiCapabilities::new` in the same module,
+    /// plugins release their capabilities through `PluginHandle::free_params`.
+    pub unsafe fn release(self) {
+        for capability in into_boxed(self.ptr, self.len).into_vec() {
+            drop(CString::from_raw(capability.key));
+            for value in into_boxed(capability.values, capability.count).into_vec() {
+                drop(CString::from_raw(value));
+            }
+        }
+    }
+
+    /// Copy the capabilities back into a map, invalid UTF-8 is replaced.
+    ///
+    /// # Safety
+    /// `ptr` must point to `len` [`FfiCapability`] with valid strings.
+    pub unsafe fn to_params(&self) -> ParamsGet {
+        let mut params = HashMap::new();
+        for capability in as_slice(self.ptr, self.len) {
+            let values = as_slice(capability.values, capability.count)
+                .iter()
+                .map(|&value| from_c_string(value))
+                .collect();
+            params.insert(from_c_string(capability.key), values);
+        }
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_the_ffi_form() {
+        let settings = ParamsSet::from([
+            ("PRIVILEGED".to_string(), "TRUE".to_string()),
+            ("EMPTY".to_string(), String::new()),
+            ("NAME".to_string(), "héllo wörld".to_string()),
+        ]);
+        let params = FfiParams::new(&settings);
+        assert_eq!(params.len(), 3);
+        assert_eq!(
+            unsafe { FfiParams::to_params(params.as_ptr(), params.len()) },
+            settings
+        );
+
+        let empty = FfiParams::new(&ParamsSet::new());
+        assert!(empty.is_empty());
+        assert!(unsafe { FfiParams::to_params(ptr::null(), 0) }.is_empty());
+    }
+
+    #[test]
+    fn embedded_nul_cuts_the_text() {
+        let settings = ParamsSet::from([("KEY\0rest".to_string(), "a\0b".to_string())]);
+        let params = FfiParams::new(&settings);
+        assert_eq!(
+            unsafe { FfiParams::to_params(params.as_ptr(), params.len()) },
+            ParamsSet::from([("KEY".to_string(), "a".to_string())])
+        );
+    }
+
+    #[test]
+    fn capabilities_survive_the_ffi_form() {
+        let params = ParamsGet::from([
+            (
+                "cmds".to_string(),
+                vec!["ECHO".to_string(), "PRINT".to_string()],
+            ),
+            ("vers".to_string(), vec!["1.0.0.0".to_string()]),
+            ("none".to_string(), Vec::new()),
+        ]);
+        let capabilities = FfiCapabilities::new(&params);
+        assert_eq!(capabilities.len, 3);
+        unsafe {
+            assert_eq!(capabilities.to_params(), params);
+            capabilities.release();
+        }
+
+        let empty = FfiCapabilities::new(&ParamsGet::new());
+        unsafe {
+            assert!(empty.to_params().is_empty());
+            empty.release();
+        }
+    }
+}
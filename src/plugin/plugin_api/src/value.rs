@@ -0,0 +1,271 @@
+use std::ffi::c_void;
+use std::{ptr, slice};
+
+/// Result of a plugin command, returned by [`crate::PluginInterface::get_data`].
+/// Text results are kept as text by the runner, like macro text they are only
+/// typed by the expressions using them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<PluginValue>),
+}
+
+impl PluginValue {
+    /// Back to the empty text result.
+    pub fn clear(&mut self) {
+        *self = PluginValue::default();
+    }
+}
+
+impl Default for PluginValue {
+    fn default() -> Self {
+        PluginValue::Str(String::new())
+    }
+}
+
+impl From<bool> for PluginValue {
+    fn from(value: bool) -> Self {
+        PluginValue::Bool(value)
+    }
+}
+
+impl From<i64> for PluginValue {
+    fn from(value: i64) -> Self {
+        PluginValue::Int(value)
+    }
+}
+
+impl From<f64> for PluginValue {
+    fn from(value: f64) -> Self {
+        PluginValue::Float(value)
+    }
+}
+
+impl From<&str> for PluginValue {
+    fn from(value: &str) -> Self {
+        PluginValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for PluginValue {
+    fn from(value: String) -> Self {
+        PluginValue::Str(value)
+    }
+}
+
+impl From<Vec<u8>> for PluginValue {
+    fn from(value: Vec<u8>) -> Self {
+        PluginValue::Bytes(value)
+    }
+}
+
+impl From<Vec<PluginValue>> for PluginValue {
+    fn from(value: Vec<PluginValue>) -> Self {
+        PluginValue::List(value)
+    }
+}
+
+// ---------------------------
+// FFI-compatible value
+// ---------------------------
+
+/// Type of an [`FfiValue`], selects the field of its payload.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiValueTag {
+    Bool,
+    Int,
+    Float,
+    /// UTF-8 text in `buffer`, not NUL terminated
+    Str,
+    Bytes,
+    /// [`FfiValue`] elements in `buffer`
+    List,
+}
+
+/// `len` elements at `ptr`, allocated by the plugin.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiBuffer {
+    pub ptr: *mut c_void,
+    pub len: usize,
+}
+
+impl FfiBuffer {
+    fn new<T>(elements: Box<[T]>) -> Self {
+        let len = elements.len();
+        FfiBuffer {
+            ptr: Box::into_raw(elements) as *mut c_void,
+            len,
+        }
+    }
+
+    /// Take back the elements allocated by [`FfiBuffer::new`].
+    ///
+    /// # Safety
+    /// The buffer must come from `FfiBuffer::new::<T>` in the same module
+    /// (same allocator) and must not be used afterwards.
+    unsafe fn into_boxed<T>(self) -> Box<[T]> {
+        if self.ptr.is_null() {
+            return Box::new([]);
+        }
+        Box::from_raw(ptr::slice_from_raw_parts_mut(
+            self.ptr.cast::<T>(),
+            self.len,
+        ))
+    }
+
+    /// # Safety
+    /// `ptr` must point to `len` initialized elements of type `T`.
+    unsafe fn as_slice<T>(&self) -> &[T] {
+        if self.ptr.is_null() || self.len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(self.ptr.cast::<T>(), self.len)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union FfiPayload {
+    pub boolean: bool,
+    pub int: i64,
+    pub float: f64,
+    pub buffer: FfiBuffer,
+}
+
+/// C-compatible form of a [`PluginValue`], the tag tells which payload field is set.
+#[repr(C)]
+pub struct FfiValue {
+    pub tag: FfiValueTag,
+    pub payload: FfiPayload,
+}
+
+impl FfiValue {
+    /// Marshal `value`, the text, bytes and list elements are copied into
+    /// buffers owned by the returned value.
+    pub fn new(value: &PluginValue) -> Self {
+        let (tag, payload) = match value {
+            PluginValue::Bool(boolean) => (FfiValueTag::Bool, FfiPayload { boolean: *boolean }),
+            PluginValue::Int(int) => (FfiValueTag::Int, FfiPayload { int: *int }),
+            PluginValue::Float(float) => (FfiValueTag::Float, FfiPayload { float: *float }),
+            PluginValue::Str(text) => (
+                FfiValueTag::Str,
+                FfiPayload {
+                    buffer: FfiBuffer::new::<u8>(text.as_bytes().into()),
+                },
+            ),
+            PluginValue::Bytes(bytes) => (
+                FfiValueTag::Bytes,
+                FfiPayload {
+                    buffer: FfiBuffer::new::<u8>(bytes.as_slice().into()),
+                },
+            ),
+            PluginValue::List(elements) => (
+                FfiValueTag::List,
+                FfiPayload {
+                    buffer: FfiBuffer::new(elements.iter().map(FfiValue::new).collect()),
+                },
+            ),
+        };
+        FfiValue { tag, payload }
+    }
+
+    /// Release the buffers allocated by [`FfiValue::new`].
+    ///
+    /// # Safety
+    /// `self` must come from `FfiValue::new` in the same module, plugins
+    /// release their values through `PluginHandle::free_data`.
+    pub unsafe fn release(self) {
+        match self.tag {
+            FfiValueTag::Str | FfiValueTag::Bytes => {
+                drop(self.payload.buffer.into_boxed::<u8>());
+            }
+            FfiValueTag::List => {
+                for element in self.payload.buffer.into_boxed::<FfiValue>().into_vec() {
+                    element.release();
+                }
+            }
+            FfiValueTag::Bool | FfiValueTag::Int | FfiValueTag::Float => {}
+        }
+    }
+
+    /// Copy the value back into a [`PluginValue`], invalid UTF-8 is replaced.
+    ///
+    /// # Safety
+    /// The payload field selected by `tag` must be set, and its buffer valid.
+    pub unsafe fn to_value(&self) -> PluginValue {
+        match self.tag {
+            FfiValueTag::Bool => PluginValue::Bool(self.payload.boolean),
+            FfiValueTag::Int => PluginValue::Int(self.payload.int),
+            FfiValueTag::Float => PluginValue::Float(self.payload.float),
+            FfiValueTag::Str => PluginValue::Str(
+                String::from_utf8_lossy(self.payload.buffer.as_slice::<u8>()).into_owned(),
+            ),
+            FfiValueTag::Bytes => PluginValue::Bytes(self.payload.buffer.as_slice::<u8>().to_vec()),
+            FfiValueTag::List => PluginValue::List(
+                self.payload
+                    .buffer
+                    .as_slice::<FfiValue>()
+                    .iter()
+                    .map(|element| element.to_value())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: PluginValue) {
+        let ffi = FfiValue::new(&value);
+        unsafe {
+            assert_eq!(ffi.to_value(), value);
+            ffi.release();
+        }
+    }
+
+    #[test]
+    fn values_survive_the_ffi_form() {
+        round_trip(true.into());
+        round_trip(i64::MIN.into());
+        round_trip(0.1.into());
+        round_trip("".into());
+        round_trip("héllo".into());
+        round_trip(vec![0u8, 255].into());
+        round_trip(Vec::<u8>::new().into());
+        round_trip(PluginValue::List(Vec::new()));
+        round_trip(PluginValue::List(vec![
+            1i64.into(),
+            "two".into(),
+            PluginValue::List(vec![false.into(), vec![3u8].into()]),
+        ]));
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let ffi = FfiValue::new(&PluginValue::Bytes(vec![b'a', 0xff]));
+        let text = FfiValue {
+            tag: FfiValueTag::Str,
+            payload: ffi.payload,
+        };
+        unsafe {
+            assert_eq!(text.to_value(), PluginValue::Str("a\u{fffd}".to_string()));
+            ffi.release();
+        }
+    }
+
+    #[test]
+    fn clear_returns_to_the_empty_text() {
+        let mut value = PluginValue::Int(1);
+        value.clear();
+        assert_eq!(value, PluginValue::Str(String::new()));
+    }
+}
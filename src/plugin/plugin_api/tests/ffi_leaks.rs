@@ -0,0 +1,134 @@
+//! Values and capabilities crossing the FFI boundary are allocated by the
+//! plugin and released through `free_data` / `free_params`: repeating the
+//! round trips must not leave allocations behind.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use plugin_api::{
+    make_handle, plugin_do_dispatch, plugin_do_enable, plugin_do_init, plugin_get_data,
+    plugin_get_params, ParamsGet, ParamsSet, PluginInterface, PluginValue, PARAMS_GET_CMDS_KEY,
+    PARAMS_GET_VERS_KEY,
+};
+
+/// Counts the allocations alive at any time, per thread so the test
+/// harness threads do not disturb the count.
+struct CountingAllocator;
+
+thread_local! {
+    static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
+}
+
+fn live_allocations() -> isize {
+    LIVE_ALLOCATIONS.with(Cell::get)
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let _ = LIVE_ALLOCATIONS.try_with(|live| live.set(live.get() + 1));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        let _ = LIVE_ALLOCATIONS.try_with(|live| live.set(live.get() - 1));
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns a value using every kind of payload, nested.
+#[derive(Default)]
+struct EchoPlugin {
+    initialized: bool,
+    enabled: bool,
+    result: PluginValue,
+}
+
+impl PluginInterface for EchoPlugin {
+    fn do_init(&mut self) {
+        self.initialized = true;
+    }
+    fn do_enable(&mut self) {
+        self.enabled = true;
+    }
+    fn do_dispatch(&mut self, _cmd: &str, args: &[&str]) -> bool {
+        self.result = PluginValue::List(vec![
+            PluginValue::Bool(true),
+            PluginValue::Int(42),
+            PluginValue::Float(1.5),
+            PluginValue::Str(args.join(" ")),
+            PluginValue::Bytes(args.concat().into_bytes()),
+            PluginValue::List(args.iter().map(|arg| (*arg).into()).collect()),
+        ]);
+        true
+    }
+    fn do_cleanup(&mut self) {}
+    fn set_params(&mut self, _params: &ParamsSet) -> bool {
+        true
+    }
+    fn get_params(&self, params: &mut ParamsGet) {
+        params.insert(
+            PARAMS_GET_CMDS_KEY.to_string(),
+            vec!["ECHO".to_string(), "PRINT".to_string()],
+        );
+        params.insert(PARAMS_GET_VERS_KEY.to_string(), vec!["1.0.0.0".to_string()]);
+    }
+    fn get_data(&self) -> &PluginValue {
+        &self.result
+    }
+    fn reset_data(&mut self) {
+        self.result.clear();
+    }
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    fn is_privileged(&self) -> bool {
+        false
+    }
+    fn is_fault_tolerant(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn round_trips_release_what_they_allocate() {
+    let mut handle = make_handle(EchoPlugin::default());
+    let args = vec!["hello".to_string(), "world".to_string()];
+
+    let round_trip = |handle: &mut _| unsafe {
+        plugin_do_dispatch(handle, "ECHO", &args).unwrap();
+        let data = plugin_get_data(handle);
+        assert!(matches!(&data, PluginValue::List(elements) if elements.len() == 6));
+        let params = plugin_get_params(handle);
+        assert_eq!(params[PARAMS_GET_CMDS_KEY].len(), 2);
+    };
+
+    unsafe {
+        assert!(plugin_do_init(&mut handle));
+        assert!(plugin_do_enable(&mut handle));
+    }
+    // the first calls may allocate for good, like the result kept by the plugin
+    for _ in 0..10 {
+        round_trip(&mut handle);
+    }
+    let before = live_allocations();
+    for _ in 0..1000 {
+        round_trip(&mut handle);
+    }
+    let after = live_allocations();
+    assert!(
+        after <= before,
+        "{} allocations leaked by 1000 round trips",
+        after - before
+    );
+
+    unsafe { (handle.destroy)(handle.ptr) };
+}
@@ -0,0 +1,314 @@
+/*
+ * Example plugin written in C, loaded as CDEMO (libcdemo_plugin.so).
+ *
+ *   LOAD_PLUGIN CDEMO
+ *   TEXT  ?= CDEMO.CECHO hello world
+ *   COUNT ?= CDEMO.CCOUNT a b c
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+#include "plugin_api.h"
+
+#define PLUGIN_VERS "1.0.0.0"
+
+static const char *const COMMANDS[] = {"CECHO", "CCOUNT", "CPRINT"};
+#define COMMAND_COUNT (sizeof(COMMANDS) / sizeof(COMMANDS[0]))
+
+typedef struct CDemoPlugin {
+    bool initialized;
+    bool enabled;
+    bool privileged;
+    bool fault_tolerant;
+    /* result of the last command, a copy is handed out by get_data */
+    FfiValueTag result_tag;
+    int64_t result_int;
+    char *result_text;
+} CDemoPlugin;
+
+static char *copy_string(const char *text, size_t len)
+{
+    char *copy = malloc(len + 1);
+    if (copy) {
+        memcpy(copy, text, len);
+        copy[len] = '\0';
+    }
+    return copy;
+}
+
+static void reset_result(CDemoPlugin *plugin)
+{
+    free(plugin->result_text);
+    plugin->result_text = NULL;
+    plugin->result_tag = FfiValueTag_Str;
+    plugin->result_int = 0;
+}
+
+static bool parse_bool(const char *text, bool *value)
+{
+    if (strcasecmp(text, "TRUE") == 0) {
+        *value = true;
+        return true;
+    }
+    if (strcasecmp(text, "FALSE") == 0) {
+        *value = false;
+        return true;
+    }
+    printf("Invalid boolean value: %s\n", text);
+    return false;
+}
+
+// ---------------------- Commands ----------------------
+
+static bool cmd_cecho(CDemoPlugin *plugin, size_t argc, const char *const *argv)
+{
+    size_t len = 0;
+    for (size_t i = 0; i < argc; i++) {
+        len += strlen(argv[i]) + 1;
+    }
+
+    char *text = calloc(len + 1, 1);
+    if (!text) {
+        return false;
+    }
+    for (size_t i = 0; i < argc; i++) {
+        if (i > 0) {
+            strcat(text, " ");
+        }
+        strcat(text, argv[i]);
+    }
+
+    reset_result(plugin);
+    plugin->result_text = text;
+    return true;
+}
+
+static bool cmd_ccount(CDemoPlugin *plugin, size_t argc, const char *const *argv)
+{
+    (void)argv;
+    reset_result(plugin);
+    plugin->result_tag = FfiValueTag_Int;
+    plugin->result_int = (int64_t)argc;
+    return true;
+}
+
+static bool cmd_cprint(CDemoPlugin *plugin, size_t argc, const char *const *argv)
+{
+    /* not enabled yet while the script is validated, only check the arguments */
+    if (!plugin->enabled) {
+        return true;
+    }
+    printf("Plugin CPRINT:");
+    for (size_t i = 0; i < argc; i++) {
+        printf(" %s", argv[i]);
+    }
+    printf("\n");
+    fflush(stdout);
+    return true;
+}
+
+// ---------------------- Handle entries ----------------------
+
+static void destroy(void *ptr)
+{
+    CDemoPlugin *plugin = ptr;
+    if (plugin) {
+        reset_result(plugin);
+        free(plugin);
+    }
+}
+
+static void do_init(void *ptr)
+{
+    ((CDemoPlugin *)ptr)->initialized = true;
+}
+
+static void do_enable(void *ptr)
+{
+    ((CDemoPlugin *)ptr)->enabled = true;
+}
+
+static bool do_dispatch(void *ptr, const char *cmd, size_t argc, const char *const *argv)
+{
+    CDemoPlugin *plugin = ptr;
+    if (strcmp(cmd, "CECHO") == 0) {
+        return cmd_cecho(plugin, argc, argv);
+    }
+    if (strcmp(cmd, "CCOUNT") == 0) {
+        return cmd_ccount(plugin, argc, argv);
+    }
+    if (strcmp(cmd, "CPRINT") == 0) {
+        return cmd_cprint(plugin, argc, argv);
+    }
+    return false;
+}
+
+/* called before destroy, release files, ports, ... here */
+static void do_cleanup(void *ptr)
+{
+    CDemoPlugin *plugin = ptr;
+    reset_result(plugin);
+    plugin->enabled = false;
+    plugin->initialized = false;
+}
+
+static bool set_params(void *ptr, const FfiParam *params, size_t len)
+{
+    CDemoPlugin *plugin = ptr;
+    for (size_t i = 0; i < len; i++) {
+        if (strcmp(params[i].key, PARAMS_FAULT_TOLERANT) == 0 &&
+            !parse_bool(params[i].value, &plugin->fault_tolerant)) {
+            return false;
+        }
+        if (strcmp(params[i].key, PARAMS_PRIVILEGED) == 0 &&
+            !parse_bool(params[i].value, &plugin->privileged)) {
+            return false;
+        }
+    }
+    return true;
+}
+
+static FfiCapabilities get_params(void *ptr)
+{
+    (void)ptr;
+    FfiCapabilities params = {calloc(2, sizeof(FfiCapability)), 0};
+    if (!params.ptr) {
+        return params;
+    }
+
+    FfiCapability *cmds = &params.ptr[params.len++];
+    cmds->key = copy_string(PARAMS_GET_CMDS_KEY, strlen(PARAMS_GET_CMDS_KEY));
+    cmds->values = calloc(COMMAND_COUNT, sizeof(char *));
+    cmds->count = cmds->values ? COMMAND_COUNT : 0;
+    for (size_t i = 0; i < cmds->count; i++) {
+        cmds->values[i] = copy_string(COMMANDS[i], strlen(COMMANDS[i]));
+    }
+
+    FfiCapability *vers = &params.ptr[params.len++];
+    vers->key = copy_string(PARAMS_GET_VERS_KEY, strlen(PARAMS_GET_VERS_KEY));
+    vers->values = calloc(1, sizeof(char *));
+    vers->count = vers->values ? 1 : 0;
+    if (vers->values) {
+        vers->values[0] = copy_string(PLUGIN_VERS, strlen(PLUGIN_VERS));
+    }
+    return params;
+}
+
+/* everything allocated by get_params comes back here */
+static void free_params(void *ptr, FfiCapabilities params)
+{
+    (void)ptr;
+    for (size_t i = 0; i < params.len; i++) {
+        for (size_t j = 0; j < params.ptr[i].count; j++) {
+            free(params.ptr[i].values[j]);
+        }
+        free(params.ptr[i].values);
+        free(params.ptr[i].key);
+    }
+    free(params.ptr);
+}
+
+/* a copy of the result, so the plugin may change it before free_data */
+static FfiValue get_data(void *ptr)
+{
+    CDemoPlugin *plugin = ptr;
+    FfiValue data;
+    data.tag = plugin->result_tag;
+
+    if (plugin->result_tag == FfiValueTag_Int) {
+        data.payload.integer = plugin->result_int;
+    } else {
+        const char *text = plugin->result_text ? plugin->result_text : "";
+        size_t len = strlen(text);
+        data.payload.buffer.ptr = len ? copy_string(text, len) : NULL;
+        data.payload.buffer.len = data.payload.buffer.ptr ? len : 0;
+    }
+    return data;
+}
+
+static void free_data(void *ptr, FfiValue data)
+{
+    (void)ptr;
+    if (data.tag == FfiValueTag_Str) {
+        free(data.payload.buffer.ptr);
+    }
+}
+
+static void reset_data(void *ptr)
+{
+    reset_result(ptr);
+}
+
+static bool is_initialized(void *ptr)
+{
+    return ((CDemoPlugin *)ptr)->initialized;
+}
+
+static bool is_enabled(void *ptr)
+{
+    return ((CDemoPlugin *)ptr)->enabled;
+}
+
+static bool is_privileged(void *ptr)
+{
+    return ((CDemoPlugin *)ptr)->privileged;
+}
+
+static bool is_fault_tolerant(void *ptr)
+{
+    return ((CDemoPlugin *)ptr)->fault_tolerant;
+}
+
+/* C code does not panic, the plugin is never poisoned */
+static bool is_poisoned(void *ptr)
+{
+    (void)ptr;
+    return false;
+}
+
+static FfiValue get_panic(void *ptr)
+{
+    (void)ptr;
+    FfiValue data = {FfiValueTag_Str, {.buffer = {NULL, 0}}};
+    return data;
+}
+
+// ---------------------- Exports ----------------------
+
+PLUGIN_EXPORT uint32_t plugin_abi_version(void)
+{
+    return PLUGIN_ABI_VERSION;
+}
+
+PLUGIN_EXPORT PluginHandle plugin_create(void)
+{
+    CDemoPlugin *plugin = calloc(1, sizeof(CDemoPlugin));
+    if (plugin) {
+        plugin->result_tag = FfiValueTag_Str;
+    }
+
+    PluginHandle handle = {
+        .ptr = plugin,
+        .destroy = destroy,
+        .do_init = do_init,
+        .do_enable = do_enable,
+        .do_dispatch = do_dispatch,
+        .do_cleanup = do_cleanup,
+        .set_params = set_params,
+        .get_params = get_params,
+        .free_params = free_params,
+        .get_data = get_data,
+        .free_data = free_data,
+        .reset_data = reset_data,
+        .is_initialized = is_initialized,
+        .is_enabled = is_enabled,
+        .is_privileged = is_privileged,
+        .is_fault_tolerant = is_fault_tolerant,
+        .is_poisoned = is_poisoned,
+        .get_panic = get_panic,
+    };
+    return handle;
+}
@@ -1,23 +1,23 @@
 use plugin_api::{
-    make_handle, ParamsGet, ParamsSet, PluginHandle, PluginInterface, PARAMS_FAULT_TOLERANT,
-    PARAMS_GET_CMDS_KEY, PARAMS_GET_VERS_KEY, PARAMS_PRIVILEGED,
+    make_handle, ParamsGet, ParamsSet, PluginHandle, PluginInterface, PluginValue,
+    PARAMS_FAULT_TOLERANT, PARAMS_GET_CMDS_KEY, PARAMS_GET_PRIV_CMDS_KEY, PARAMS_GET_VERS_KEY,
+    PARAMS_PRIVILEGED, PLUGIN_ABI_VERSION,
 };
 use plugin_macros::plugin_commands;
 use std::collections::HashMap;
 use utils::string_utils;
 
 const PLUGIN_VERS: &str = "1.0.0.0";
-type CommandFn<T> = Box<dyn Fn(&mut T, &str) -> bool>;
+type CommandFn<T> = Box<dyn Fn(&mut T, &[&str]) -> bool>;
 
 pub struct MathPlugin {
     initialized: bool,
     enabled: bool,
     privileged: bool,
     fault_tolerant: bool,
-    result: String,
+    result: PluginValue,
     commands: HashMap<String, CommandFn<Self>>,
     params_get: ParamsGet,
-    params_set: ParamsSet,
 }
 
 impl MathPlugin {
@@ -27,16 +27,33 @@ impl MathPlugin {
             enabled: false,
             privileged: false,
             fault_tolerant: false,
-            result: String::new(),
+            result: PluginValue::default(),
             commands: HashMap::new(),
             params_get: HashMap::new(),
-            params_set: HashMap::new(),
         };
 
         plugin.register_commands(); // procedural macro populates commands
         plugin.params_get.extend([
-            (PARAMS_GET_CMDS_KEY.to_string(), plugin.command_names()),
-            (PARAMS_GET_VERS_KEY.to_string(), vec![PLUGIN_VERS]),
+            (
+                PARAMS_GET_CMDS_KEY.to_string(),
+                plugin
+                    .command_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ),
+            (
+                PARAMS_GET_PRIV_CMDS_KEY.to_string(),
+                plugin
+                    .privileged_command_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ),
+            (
+                PARAMS_GET_VERS_KEY.to_string(),
+                vec![PLUGIN_VERS.to_string()],
+            ),
         ]);
         plugin
     }
@@ -47,28 +64,67 @@ impl MathPlugin {
 #[allow(non_snake_case)]
 #[plugin_commands]
 impl MathPlugin {
-    fn MECHO(&mut self, args: &str) -> bool {
+    fn MECHO(&mut self, args: &[&str]) -> bool {
         if !self.is_enabled() {
-            println!("NOT_ENABLED::Called MECHO with args: {}", args);
+            println!("NOT_ENABLED::Called MECHO with args: {:?}", args);
         } else {
-            println!("ENABLED::Called MECHO with args: {}", args);
+            println!("ENABLED::Called MECHO with args: {:?}", args);
         }
 
-        self.result = args.to_string();
+        self.result = args.join(" ").into();
         true
     }
 
-    fn MRESET(&mut self, _args: &str) -> bool {
+    fn MRESET(&mut self, _args: &[&str]) -> bool {
         self.result.clear();
         true
     }
 
-    fn MPRINT(&mut self, args: &str) -> bool {
-        println!("Plugin PRINT: {}", args);
+    fn MPRINT(&mut self, args: &[&str]) -> bool {
+        println!("Plugin PRINT: {}", args.join(" "));
         true
     }
 
-    // Add more commands here as needed
+    // integer sum, or float if any argument has a decimal point
+    fn MADD(&mut self, args: &[&str]) -> bool {
+        // validation passes the arguments before the macros are resolved
+        if !self.is_enabled() {
+            println!("NOT_ENABLED::Called MADD with args: {:?}", args);
+            return true;
+        }
+        if let Ok(numbers) = args
+            .iter()
+            .map(|arg| arg.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+        {
+            return match numbers.iter().try_fold(0i64, |a, n| a.checked_add(*n)) {
+                Some(sum) => {
+                    self.result = sum.into();
+                    true
+                }
+                None => {
+                    println!("MADD: integer overflow in {:?}", args);
+                    false
+                }
+            };
+        }
+        match args
+            .iter()
+            .map(|arg| arg.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(numbers) => {
+                self.result = numbers.iter().sum::<f64>().into();
+                true
+            }
+            Err(_) => {
+                println!("MADD: not a number in {:?}", args);
+                false
+            }
+        }
+    }
+
+    // Add more commands here as needed, mark the dangerous ones #[privileged]
 }
 
 // ---------------------- PluginInterface ----------------------
@@ -80,7 +136,7 @@ impl PluginInterface for MathPlugin {
     fn do_enable(&mut self) {
         self.enabled = true
     }
-    fn do_dispatch(&mut self, cmd: &str, args: &str) -> bool {
+    fn do_dispatch(&mut self, cmd: &str, args: &[&str]) -> bool {
         // avoid mutable/immutable borrow conflict
         if let Some(f) = self.commands.remove(cmd) {
             let result = f(self, args);
@@ -91,7 +147,9 @@ impl PluginInterface for MathPlugin {
         }
     }
     fn do_cleanup(&mut self) {
-        unimplemented!()
+        self.result.clear();
+        self.enabled = false;
+        self.initialized = false;
     }
     fn set_params(&mut self, params: &ParamsSet) -> bool {
         if let Some(fault_tolerant) = params.get(PARAMS_FAULT_TOLERANT) {
@@ -114,7 +172,7 @@ impl PluginInterface for MathPlugin {
     fn get_params(&self, params: &mut ParamsGet) {
         *params = self.params_get.clone();
     }
-    fn get_data(&self) -> &str {
+    fn get_data(&self) -> &PluginValue {
         &self.result
     }
     fn reset_data(&mut self) {
@@ -134,6 +192,11 @@ impl PluginInterface for MathPlugin {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
 #[no_mangle]
 pub extern "C" fn plugin_create() -> PluginHandle {
     make_handle(MathPlugin::new())
@@ -144,3 +207,38 @@ impl Default for MathPlugin {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> MathPlugin {
+        let mut plugin = MathPlugin::new();
+        plugin.do_init();
+        plugin.do_enable();
+        plugin
+    }
+
+    #[test]
+    fn madd_accepts_unresolved_args_before_enabling() {
+        let mut plugin = MathPlugin::new();
+        assert!(plugin.do_dispatch("MADD", &["$X", "2"]));
+    }
+
+    #[test]
+    fn madd_sums_integers_and_floats() {
+        let mut plugin = enabled();
+        assert!(plugin.do_dispatch("MADD", &["1", "2", "3"]));
+        assert_eq!(plugin.get_data(), &PluginValue::Int(6));
+        assert!(plugin.do_dispatch("MADD", &["1", "0.5"]));
+        assert_eq!(plugin.get_data(), &PluginValue::Float(1.5));
+        assert!(!plugin.do_dispatch("MADD", &["1", "one"]));
+    }
+
+    #[test]
+    fn madd_rejects_integer_overflow() {
+        let mut plugin = enabled();
+        let max = i64::MAX.to_string();
+        assert!(!plugin.do_dispatch("MADD", &[&max, "1"]));
+    }
+}
@@ -1,23 +1,23 @@
 use plugin_api::{
-    make_handle, ParamsGet, ParamsSet, PluginHandle, PluginInterface, PARAMS_FAULT_TOLERANT,
-    PARAMS_GET_CMDS_KEY, PARAMS_GET_VERS_KEY, PARAMS_PRIVILEGED,
+    make_handle, ParamsGet, ParamsSet, PluginHandle, PluginInterface, PluginValue,
+    PARAMS_FAULT_TOLERANT, PARAMS_GET_CMDS_KEY, PARAMS_GET_PRIV_CMDS_KEY, PARAMS_GET_VERS_KEY,
+    PARAMS_PRIVILEGED, PLUGIN_ABI_VERSION,
 };
 use plugin_macros::plugin_commands;
 use std::collections::HashMap;
 use utils::string_utils;
 
 const PLUGIN_VERS: &str = "1.0.0.0";
-type CommandFn<T> = Box<dyn Fn(&mut T, &str) -> bool>;
+type CommandFn<T> = Box<dyn Fn(&mut T, &[&str]) -> bool>;
 
 pub struct UtilsPlugin {
     initialized: bool,
     enabled: bool,
     privileged: bool,
     fault_tolerant: bool,
-    result: String,
+    result: PluginValue,
     commands: HashMap<String, CommandFn<Self>>,
     params_get: ParamsGet,
-    params_set: ParamsSet,
 }
 
 impl UtilsPlugin {
@@ -27,16 +27,33 @@ impl UtilsPlugin {
             enabled: false,
             privileged: false,
             fault_tolerant: false,
-            result: String::new(),
+            result: PluginValue::default(),
             commands: HashMap::new(),
             params_get: HashMap::new(),
-            params_set: HashMap::new(),
         };
 
         plugin.register_commands(); // procedural macro populates commands
         plugin.params_get.extend([
-            (PARAMS_GET_CMDS_KEY.to_string(), plugin.command_names()),
-            (PARAMS_GET_VERS_KEY.to_string(), vec![PLUGIN_VERS]),
+            (
+                PARAMS_GET_CMDS_KEY.to_string(),
+                plugin
+                    .command_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ),
+            (
+                PARAMS_GET_PRIV_CMDS_KEY.to_string(),
+                plugin
+                    .privileged_command_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            ),
+            (
+                PARAMS_GET_VERS_KEY.to_string(),
+                vec![PLUGIN_VERS.to_string()],
+            ),
         ]);
         plugin
     }
@@ -47,28 +64,34 @@ impl UtilsPlugin {
 #[allow(non_snake_case)]
 #[plugin_commands]
 impl UtilsPlugin {
-    fn UECHO(&mut self, args: &str) -> bool {
+    fn UECHO(&mut self, args: &[&str]) -> bool {
         if !self.is_enabled() {
-            println!("NOT_ENABLED::Called UECHO with args: {}", args);
+            println!("NOT_ENABLED::Called UECHO with args: {:?}", args);
         } else {
-            println!("ENABLED::Called UECHO with args: {}", args);
+            println!("ENABLED::Called UECHO with args: {:?}", args);
         }
 
-        self.result = args.to_string();
+        self.result = args.join(" ").into();
         true
     }
 
-    fn URESET(&mut self, _args: &str) -> bool {
+    fn URESET(&mut self, _args: &[&str]) -> bool {
         self.result.clear();
         true
     }
 
-    fn UPRINT(&mut self, args: &str) -> bool {
-        println!("Plugin PRINT: {}", args);
+    fn UPRINT(&mut self, args: &[&str]) -> bool {
+        println!("Plugin PRINT: {}", args.join(" "));
         true
     }
 
-    // Add more commands here as needed
+    // returns its arguments as a list
+    fn ULIST(&mut self, args: &[&str]) -> bool {
+        self.result = PluginValue::List(args.iter().map(|&arg| arg.into()).collect());
+        true
+    }
+
+    // Add more commands here as needed, mark the dangerous ones #[privileged]
 }
 
 // ---------------------- PluginInterface ----------------------
@@ -80,7 +103,7 @@ impl PluginInterface for UtilsPlugin {
     fn do_enable(&mut self) {
         self.enabled = true
     }
-    fn do_dispatch(&mut self, cmd: &str, args: &str) -> bool {
+    fn do_dispatch(&mut self, cmd: &str, args: &[&str]) -> bool {
         // avoid mutable/immutable borrow conflict
         if let Some(f) = self.commands.remove(cmd) {
             let result = f(self, args);
@@ -91,7 +114,9 @@ impl PluginInterface for UtilsPlugin {
         }
     }
     fn do_cleanup(&mut self) {
-        unimplemented!()
+        self.result.clear();
+        self.enabled = false;
+        self.initialized = false;
     }
     fn set_params(&mut self, params: &ParamsSet) -> bool {
         if let Some(fault_tolerant) = params.get(PARAMS_FAULT_TOLERANT) {
@@ -114,7 +139,7 @@ impl PluginInterface for UtilsPlugin {
     fn get_params(&self, params: &mut ParamsGet) {
         *params = self.params_get.clone();
     }
-    fn get_data(&self) -> &str {
+    fn get_data(&self) -> &PluginValue {
         &self.result
     }
     fn reset_data(&mut self) {
@@ -134,6 +159,11 @@ impl PluginInterface for UtilsPlugin {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
 #[no_mangle]
 pub extern "C" fn plugin_create() -> PluginHandle {
     make_handle(UtilsPlugin::new())
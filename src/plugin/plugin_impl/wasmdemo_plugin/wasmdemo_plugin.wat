@@ -0,0 +1,154 @@
+;; Example WebAssembly plugin, loaded as WASMDEMO through the LIBRARY of its
+;; settings section (or compiled to wasmdemo_plugin.wasm in a plugin directory).
+;;
+;;   LOAD_PLUGIN WASMDEMO
+;;   TEXT ?= WASMDEMO.WECHO hello world
+;;   NOW  ?= WASMDEMO.WTIME
+;;   DATA ?= WASMDEMO.WREAD notes.txt
+;;
+;; WTIME needs the TIME capability, WREAD the FS one (PRIVILEGED = TRUE) and
+;; is reported as privileged command, so the host must authorise WASMDEMO.
+
+(module
+  (import "env" "log" (func $log (param i32 i32)))
+  (import "env" "time_ms" (func $time_ms (result i64)))
+  (import "env" "fs_read" (func $fs_read (param i32 i32 i32 i32) (result i32)))
+
+  (memory (export "memory") 1)
+
+  ;; get_params text, command names, log messages
+  (data (i32.const 16) "cmds=WECHO WTIME WREAD\0avers=1.0.0.0\0aprivcmds=WREAD\0a")
+  (data (i32.const 128) "WECHO")
+  (data (i32.const 136) "WTIME")
+  (data (i32.const 144) "WREAD")
+  (data (i32.const 160) "enabled")
+
+  ;; buffers handed to the host live in [1024, 32768), the result above
+  (global $heap (mut i32) (i32.const 1024))
+  (global $result_ptr (mut i32) (i32.const 32768))
+  (global $result_len (mut i32) (i32.const 0))
+  (global $enabled (mut i32) (i32.const 0))
+
+  (func (export "plugin_abi_version") (result i32)
+    (i32.const 1))
+
+  ;; bump allocator, reset once the call using the buffers is done
+  (func (export "plugin_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (if (i32.gt_u (i32.add (local.get $ptr) (local.get $len)) (i32.const 32768))
+      (then (unreachable)))
+    (global.set $heap (i32.add (local.get $ptr) (local.get $len)))
+    (local.get $ptr))
+
+  (func (export "plugin_init"))
+
+  (func (export "plugin_enable")
+    (global.set $enabled (i32.const 1))
+    (call $log (i32.const 160) (i32.const 7)))
+
+  (func (export "plugin_cleanup")
+    (global.set $enabled (i32.const 0))
+    (global.set $result_len (i32.const 0)))
+
+  ;; settings are not used, FAULT_TOLERANT and PRIVILEGED are handled by the host
+  (func (export "plugin_set_params") (param $ptr i32) (param $len i32) (result i32)
+    (global.set $heap (i32.const 1024))
+    (i32.const 1))
+
+  ;; 16 << 32 | 51
+  (func (export "plugin_get_params") (result i64)
+    (i64.const 68719476787))
+
+  (func (export "plugin_get_data") (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (global.get $result_ptr)) (i64.const 32))
+      (i64.extend_i32_u (global.get $result_len))))
+
+  (func $equals (param $a i32) (param $a_len i32) (param $b i32) (param $b_len i32) (result i32)
+    (local $i i32)
+    (if (i32.ne (local.get $a_len) (local.get $b_len))
+      (then (return (i32.const 0))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $a_len)))
+        (if (i32.ne (i32.load8_u (i32.add (local.get $a) (local.get $i)))
+                    (i32.load8_u (i32.add (local.get $b) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+
+  ;; the arguments joined by spaces
+  (func $wecho (param $args i32) (param $args_len i32) (result i32)
+    (local $i i32)
+    (local $byte i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $args_len)))
+        (local.set $byte (i32.load8_u (i32.add (local.get $args) (local.get $i))))
+        (if (i32.eqz (local.get $byte))
+          (then (local.set $byte (i32.const 32))))
+        (i32.store8 (i32.add (i32.const 32768) (local.get $i)) (local.get $byte))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.set $result_len (local.get $args_len))
+    (i32.const 1))
+
+  ;; milliseconds since the Unix epoch, as decimal text
+  (func $wtime (result i32)
+    (local $value i64)
+    (local $pos i32)
+    (local.set $value (call $time_ms))
+    (local.set $pos (i32.const 32788))
+    (loop $digit
+      (local.set $pos (i32.sub (local.get $pos) (i32.const 1)))
+      (i64.store8 (local.get $pos)
+        (i64.add (i64.const 48) (i64.rem_u (local.get $value) (i64.const 10))))
+      (local.set $value (i64.div_u (local.get $value) (i64.const 10)))
+      (br_if $digit (i64.ne (local.get $value) (i64.const 0))))
+    (global.set $result_ptr (local.get $pos))
+    (global.set $result_len (i32.sub (i32.const 32788) (local.get $pos)))
+    (i32.const 1))
+
+  ;; contents of a file of the FS_DIR directory
+  (func $wread (param $args i32) (param $args_len i32) (result i32)
+    (local $read i32)
+    (local.set $read
+      (call $fs_read (local.get $args) (local.get $args_len) (i32.const 32768) (i32.const 32768)))
+    (if (i32.lt_s (local.get $read) (i32.const 0))
+      (then (return (i32.const 0))))
+    (global.set $result_len (local.get $read))
+    (i32.const 1))
+
+  (func $dispatch (param $cmd i32) (param $cmd_len i32) (param $args i32) (param $args_len i32) (result i32)
+    (global.set $result_ptr (i32.const 32768))
+    (global.set $result_len (i32.const 0))
+
+    (if (call $equals (local.get $cmd) (local.get $cmd_len) (i32.const 128) (i32.const 5))
+      (then (return (call $wecho (local.get $args) (local.get $args_len)))))
+
+    (if (call $equals (local.get $cmd) (local.get $cmd_len) (i32.const 136) (i32.const 5))
+      (then
+        ;; not enabled yet while the script is validated, nothing to check
+        (if (i32.eqz (global.get $enabled))
+          (then (return (i32.const 1))))
+        (return (call $wtime))))
+
+    (if (call $equals (local.get $cmd) (local.get $cmd_len) (i32.const 144) (i32.const 5))
+      (then
+        (if (i32.eqz (local.get $args_len))
+          (then (return (i32.const 0))))
+        (if (i32.eqz (global.get $enabled))
+          (then (return (i32.const 1))))
+        (return (call $wread (local.get $args) (local.get $args_len)))))
+
+    (i32.const 0))
+
+  (func (export "plugin_dispatch") (param $cmd i32) (param $cmd_len i32) (param $args i32) (param $args_len i32) (result i32)
+    (local $success i32)
+    (local.set $success
+      (call $dispatch (local.get $cmd) (local.get $cmd_len) (local.get $args) (local.get $args_len)))
+    (global.set $heap (i32.const 1024))
+    (local.get $success))
+)
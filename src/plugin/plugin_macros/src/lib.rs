@@ -2,15 +2,20 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, ImplItem, ItemImpl};
 
+/// Marks a command of a `#[plugin_commands]` impl that needs the host
+/// authorisation of privileged plugins, reported by `privileged_command_names()`.
+const PRIVILEGED_ATTR: &str = "privileged";
+
 #[proc_macro_attribute]
 pub fn plugin_commands(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemImpl);
-    let struct_name = &input.self_ty;
+    let mut input = parse_macro_input!(item as ItemImpl);
+    let struct_name = input.self_ty.clone();
 
     let mut method_names = Vec::new();
+    let mut privileged_names = Vec::new();
     let mut command_inserts = Vec::new();
 
-    for impl_item in &input.items {
+    for impl_item in &mut input.items {
         if let ImplItem::Fn(meth) = impl_item {
             let name_ident = &meth.sig.ident;
             let name_str = name_ident.to_string();
@@ -19,12 +24,20 @@ pub fn plugin_commands(_attr: TokenStream, item: TokenStream) -> TokenStream {
             command_inserts.push(quote! {
                 self.commands.insert(
                     #name_str.to_string(),
-                    Box::new(|s: &mut _, args: &str| s.#name_ident(args))
+                    Box::new(|s: &mut _, args: &[&str]| s.#name_ident(args))
                 );
             });
 
             // method names for command_names()
             method_names.push(quote! { #name_str });
+
+            // #[privileged] is only a marker, it must not reach the compiler
+            let attrs_count = meth.attrs.len();
+            meth.attrs
+                .retain(|attr| !attr.path().is_ident(PRIVILEGED_ATTR));
+            if meth.attrs.len() != attrs_count {
+                privileged_names.push(quote! { #name_str });
+            }
         }
     }
 
@@ -34,6 +47,10 @@ pub fn plugin_commands(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 vec![#(#method_names),*]
             }
 
+            pub fn privileged_command_names(&self) -> Vec<&'static str> {
+                vec![#(#privileged_names),*]
+            }
+
             pub fn register_commands(&mut self) {
                 #(#command_inserts)*
             }
@@ -0,0 +1,54 @@
+//! Expands `#[plugin_commands]` on a small impl and checks the generated
+//! command lists and dispatch table.
+
+use plugin_macros::plugin_commands;
+use std::collections::HashMap;
+
+type CommandFn<T> = Box<dyn Fn(&mut T, &[&str]) -> bool>;
+
+#[derive(Default)]
+struct Demo {
+    calls: Vec<String>,
+    commands: HashMap<String, CommandFn<Self>>,
+}
+
+#[allow(non_snake_case)]
+#[plugin_commands]
+impl Demo {
+    fn READ(&mut self, args: &[&str]) -> bool {
+        self.calls.push(format!("READ {}", args.join(" ")));
+        true
+    }
+
+    #[privileged]
+    fn WRITE(&mut self, _args: &[&str]) -> bool {
+        self.calls.push("WRITE".to_string());
+        true
+    }
+
+    #[privileged]
+    #[inline]
+    fn DELETE(&mut self, _args: &[&str]) -> bool {
+        false
+    }
+}
+
+#[test]
+fn privileged_commands_are_listed_apart() {
+    let demo = Demo::default();
+    assert_eq!(demo.command_names(), ["READ", "WRITE", "DELETE"]);
+    assert_eq!(demo.privileged_command_names(), ["WRITE", "DELETE"]);
+}
+
+#[test]
+fn privileged_commands_are_registered_as_usual() {
+    let mut demo = Demo::default();
+    demo.register_commands();
+    assert_eq!(demo.commands.len(), 3);
+
+    let commands = std::mem::take(&mut demo.commands);
+    assert!(commands["READ"](&mut demo, &["a", "b"]));
+    assert!(commands["WRITE"](&mut demo, &[]));
+    assert!(!commands["DELETE"](&mut demo, &[]));
+    assert_eq!(demo.calls, ["READ a b", "WRITE"]);
+}
@@ -0,0 +1,21 @@
+//! Child process running one plugin for the app, see `HOST = PROCESS`.
+//!
+//! Usage: plugin_host <library>
+
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let Some(library) = env::args_os().nth(1).map(PathBuf::from) else {
+        eprintln!("usage: plugin_host <library>");
+        return ExitCode::from(2);
+    };
+    match plugin_manager::serve_plugin(&library) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ plugin_host {:?}: {}", library, err);
+            ExitCode::FAILURE
+        }
+    }
+}
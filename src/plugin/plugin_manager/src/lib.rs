@@ -1,9 +1,26 @@
-use libloading::{Library, Symbol};
+use libloading::Library;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use plugin_api::{plugin_do_enable, PluginCreateFn, PluginHandle};
+use plugin_api::{
+    make_handle, plugin_do_cleanup, plugin_do_enable, plugin_do_init, plugin_get_params,
+    plugin_set_params, ParamsGet, ParamsSet, PluginAbiVersionFn, PluginCreateFn, PluginHandle,
+    PluginInterface, PARAMS_GET_CMDS_KEY, PARAMS_GET_VERS_KEY, PARAMS_PRIVILEGED,
+    PLUGIN_ABI_VERSION,
+};
 use utils::ini_parser::IniParserEx;
+use utils::string_utils;
+
+mod remote;
+mod wasm;
+
+pub use remote::{serve_plugin, HostSettings, RemotePlugin, PLUGIN_HOST_PROGRAM};
+pub use wasm::{WasmPlugin, SETTINGS_WASM_CAPABILITIES, SETTINGS_WASM_FS_DIR, WASM_ABI_VERSION};
 
 #[cfg(target_os = "windows")]
 const LIB_EXT: &str = "dll";
@@ -15,85 +32,620 @@ const LIB_EXT: &str = "so";
 const LIB_EXT: &str = "dylib";
 
 const INI_SEARCH_DEPTH: usize = 5;
+const INI_COMMON_SECTION: &str = "COMMON";
+
+/// [COMMON] key with comma separated plugin directories, searched after the CLI ones
+pub const SETTINGS_PLUGINS_DIRS: &str = "PLUGINS_DIRS";
+/// Key of a plugin section giving the path of its library, bypassing the search
+pub const SETTINGS_PLUGIN_LIBRARY: &str = "LIBRARY";
+/// Environment variable with plugin directories (`PATH` syntax), searched last
+pub const PLUGINS_PATH_ENV: &str = "URUSTSCRIPT_PLUGINS_PATH";
+/// Searched when no directory is configured anywhere
+pub const DEFAULT_PLUGINS_DIR: &str = "target/debug";
+/// Key of a plugin section: `PROCESS` runs the plugin in a `plugin_host`
+/// child process, `LIBRARY` (the default) loads it into the app
+pub const SETTINGS_PLUGIN_HOST: &str = "HOST";
+/// Key of a plugin section: automatic restarts of a crashed `PROCESS` plugin
+pub const SETTINGS_PLUGIN_RESTARTS: &str = "RESTARTS";
+/// Key of a plugin section: seconds a `PROCESS` plugin may take to answer
+/// before it is killed as wedged, `0` waits forever
+pub const SETTINGS_PLUGIN_TIMEOUT: &str = "TIMEOUT";
+/// Used when the section of a `PROCESS` plugin has no `TIMEOUT`
+pub const DEFAULT_PLUGIN_TIMEOUT: &str = "60";
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PluginLoadError {
+    SettingsNotLoaded {
+        path: PathBuf,
+    },
+    LibraryNotFound {
+        plugin: String,
+        path: PathBuf,
+    },
+    /// The file exists but is not a loadable library
+    LibraryInvalid {
+        plugin: String,
+        path: PathBuf,
+        reason: String,
+    },
+    SymbolMissing {
+        plugin: String,
+        path: PathBuf,
+        symbol: &'static str,
+    },
+    AbiMismatch {
+        plugin: String,
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
+    ParamsRejected {
+        plugin: String,
+        path: PathBuf,
+    },
+    /// `do_init` did not leave the plugin initialized
+    InitFailed {
+        plugin: String,
+        path: PathBuf,
+    },
+    /// `do_enable` did not leave the plugin enabled
+    EnableFailed {
+        plugin: String,
+        path: PathBuf,
+    },
+    /// The `plugin_host` child process did not start or load the library
+    HostFailed {
+        plugin: String,
+        path: PathBuf,
+        reason: String,
+    },
+    /// Unknown `HOST`, or an invalid value for another key of the plugin
+    /// section
+    InvalidHostSettings {
+        plugin: String,
+        key: &'static str,
+        value: String,
+    },
+    /// The WebAssembly module did not compile, instantiate or answer
+    WasmFailed {
+        plugin: String,
+        path: PathBuf,
+        reason: String,
+    },
+    /// A privileged capability listed for a plugin without `PRIVILEGED = TRUE`
+    CapabilityDenied {
+        plugin: String,
+        capability: &'static str,
+    },
+    /// The WebAssembly module imports a host function it was not granted
+    CapabilityNotGranted {
+        plugin: String,
+        path: PathBuf,
+        import: String,
+        capability: &'static str,
+    },
+}
+
+impl fmt::Display for PluginLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginLoadError::SettingsNotLoaded { path } => {
+                write!(f, "failed loading settings from {:?}", path)
+            }
+            PluginLoadError::LibraryNotFound { plugin, path } => {
+                write!(f, "plugin `{}`: library {:?} not found", plugin, path)
+            }
+            PluginLoadError::LibraryInvalid {
+                plugin,
+                path,
+                reason,
+            } => write!(
+                f,
+                "plugin `{}`: cannot load library {:?}: {}",
+                plugin, path, reason
+            ),
+            PluginLoadError::SymbolMissing {
+                plugin,
+                path,
+                symbol,
+            } => write!(
+                f,
+                "plugin `{}`: library {:?} does not export `{}`",
+                plugin, path, symbol
+            ),
+            PluginLoadError::AbiMismatch {
+                plugin,
+                path,
+                found,
+                expected,
+            } => write!(
+                f,
+                "plugin `{}`: library {:?} was built for plugin ABI version {}, expected version {}",
+                plugin, path, found, expected
+            ),
+            PluginLoadError::ParamsRejected { plugin, path } => write!(
+                f,
+                "plugin `{}` ({:?}) rejected its settings from section [{}]",
+                plugin, path, plugin
+            ),
+            PluginLoadError::InitFailed { plugin, path } => {
+                write!(f, "plugin `{}` ({:?}) failed to initialize", plugin, path)
+            }
+            PluginLoadError::EnableFailed { plugin, path } => {
+                write!(f, "plugin `{}` ({:?}) failed to enable", plugin, path)
+            }
+            PluginLoadError::HostFailed {
+                plugin,
+                path,
+                reason,
+            } => write!(
+                f,
+                "plugin `{}`: plugin host process for {:?} failed: {}",
+                plugin, path, reason
+            ),
+            PluginLoadError::InvalidHostSettings { plugin, key, value } => write!(
+                f,
+                "plugin `{}`: invalid value for {} -> {}",
+                plugin, key, value
+            ),
+            PluginLoadError::WasmFailed {
+                plugin,
+                path,
+                reason,
+            } => write!(
+                f,
+                "plugin `{}`: wasm module {:?} failed: {}",
+                plugin, path, reason
+            ),
+            PluginLoadError::CapabilityDenied { plugin, capability } => write!(
+                f,
+                "plugin `{}`: capability {} requires PRIVILEGED = TRUE",
+                plugin, capability
+            ),
+            PluginLoadError::CapabilityNotGranted {
+                plugin,
+                path,
+                import,
+                capability,
+            } => write!(
+                f,
+                "plugin `{}`: wasm module {:?} imports `{}` without capability {}",
+                plugin, path, import, capability
+            ),
+        }
+    }
+}
+
+impl Error for PluginLoadError {}
+
+/// Metadata of a plugin found by [`PluginManager::discover`].
+#[derive(Debug, Clone)]
+pub struct PluginInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub version: Option<String>,
+    pub commands: Vec<String>,
+}
 
 pub struct PluginDescriptor {
     pub handle: *mut PluginHandle,
-    pub _lib: Library, // underscore means “used to hold lifetime”
+    pub path: PathBuf,
+    /// `PRIVILEGED = TRUE` in the plugin section, whatever the plugin reports
+    pub privileged: bool,
+    /// `None` for a plugin running in a `plugin_host` process or in the wasm runtime
+    pub _lib: Option<Library>, // underscore means “used to hold lifetime”
 }
 
 pub struct PluginManager {
-    pluginsdirpath: &'static str,
-    inipathname: &'static str,
+    pluginsdirpaths: Vec<PathBuf>,
+    inipathname: PathBuf,
     iniparser: IniParserEx,
+    ini_loaded: bool,
     pub plugins: HashMap<String, PluginDescriptor>,
 }
 
 impl PluginManager {
-    pub fn new(pluginsdirpath: &'static str, inipathname: &'static str) -> Self {
-        Self {
-            pluginsdirpath,
+    /// `pluginsdirpaths` are searched first, then the [COMMON] `PLUGINS_DIRS`
+    /// of the settings file, then the `URUSTSCRIPT_PLUGINS_PATH` directories.
+    pub fn new(pluginsdirpaths: Vec<PathBuf>, inipathname: impl Into<PathBuf>) -> Self {
+        let inipathname = inipathname.into();
+        let mut iniparser = IniParserEx::default();
+        let ini_loaded = iniparser.load(&inipathname);
+
+        let mut manager = Self {
+            pluginsdirpaths,
             inipathname,
-            iniparser: IniParserEx::default(),
+            iniparser,
+            ini_loaded,
             plugins: HashMap::new(),
+        };
+        manager.pluginsdirpaths = manager.search_dirs();
+        manager
+    }
+
+    /// The plugin directories in search order, without duplicates.
+    fn search_dirs(&self) -> Vec<PathBuf> {
+        let settings_dirs = self
+            .iniparser
+            .get_value(
+                INI_COMMON_SECTION,
+                SETTINGS_PLUGINS_DIRS,
+                "",
+                INI_SEARCH_DEPTH,
+            )
+            .split(',')
+            .map(str::trim)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let env_dirs = env::var_os(PLUGINS_PATH_ENV)
+            .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for dir in self
+            .pluginsdirpaths
+            .iter()
+            .cloned()
+            .chain(settings_dirs)
+            .chain(env_dirs)
+        {
+            if !dir.as_os_str().is_empty() && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        if dirs.is_empty() {
+            dirs.push(PathBuf::from(DEFAULT_PLUGINS_DIR));
         }
+        dirs
     }
 
-    pub fn load_plugins(&mut self, plugin_names: &HashSet<String>) -> bool {
-        if !self.iniparser.load(self.inipathname) {
-            println!("❌ Failed loading inifile from {:?}", self.inipathname);
-            return false;
+    /// The plugin directories in search order.
+    pub fn plugins_dirs(&self) -> &[PathBuf] {
+        &self.pluginsdirpaths
+    }
+
+    /// Library file name of a plugin, e.g. `UTILS` -> `libutils_plugin.so`.
+    fn plugin_lib_name(name: &str) -> String {
+        format!("lib{}_plugin.{}", name.to_lowercase(), LIB_EXT)
+    }
+
+    /// WebAssembly module name of a plugin, e.g. `UTILS` -> `utils_plugin.wasm`.
+    fn plugin_wasm_name(name: &str) -> String {
+        format!("{}_plugin.wasm", name.to_lowercase())
+    }
+
+    /// Plugins in `.wasm` modules, or `.wat` text set as `LIBRARY`, run in
+    /// the embedded wasm runtime.
+    fn is_wasm(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| ext == "wasm" || ext == "wat")
+    }
+
+    /// Settings of the plugin section, empty if there is none.
+    fn plugin_section(&self, name: &str) -> ParamsSet {
+        self.iniparser
+            .get_resolved_section(name, INI_SEARCH_DEPTH)
+            .unwrap_or_default()
+    }
+
+    /// The `LIBRARY` of the plugin section if set, else the first existing
+    /// library or wasm module for `name` in the plugin directories, in order.
+    /// Falls back to the first directory so the loading error names a real path.
+    fn plugin_path(&self, name: &str) -> PathBuf {
+        let library = self
+            .iniparser
+            .get_value(name, SETTINGS_PLUGIN_LIBRARY, "", INI_SEARCH_DEPTH);
+        if !library.is_empty() {
+            return PathBuf::from(library);
         }
 
-        for name in plugin_names {
-            let lib_name = format!("lib{}_plugin.{}", name.to_lowercase(), LIB_EXT);
-            let path = Path::new(self.pluginsdirpath).join(lib_name);
-            println!("Loading plugin: {:?}", path);
+        let lib_name = Self::plugin_lib_name(name);
+        let wasm_name = Self::plugin_wasm_name(name);
+        self.pluginsdirpaths
+            .iter()
+            .flat_map(|dir| [dir.join(&lib_name), dir.join(&wasm_name)])
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| {
+                self.pluginsdirpaths
+                    .first()
+                    .cloned()
+                    .unwrap_or_default()
+                    .join(&lib_name)
+            })
+    }
 
-            unsafe {
-                let library = Library::new(&path).unwrap();
-                let create: Symbol<PluginCreateFn> = library.get(b"plugin_create").unwrap();
-                let handle = create(); // type PluginHandle
-
-                // retrieve data from inifile and send to it to plugin
-                if let Some(section) = self.iniparser.get_resolved_section(name, INI_SEARCH_DEPTH) {
-                    if !(handle.set_params)(handle.ptr, &section) {
-                        return false;
+    /// Plugins found in the plugin directories as `(NAME, path)`, sorted by name.
+    /// A name found in several directories is reported once, from the first one,
+    /// a plugin section with a `LIBRARY` takes precedence over the directories.
+    pub fn available_plugins(&self) -> Vec<(String, PathBuf)> {
+        let prefix = "lib";
+        let suffix = format!("_plugin.{}", LIB_EXT);
+        let wasm_suffix = "_plugin.wasm";
+        let mut found: Vec<(String, PathBuf)> = Vec::new();
+
+        for dir in &self.pluginsdirpaths {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.map_while(Result::ok) {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if let Some(name) = file_name
+                    .strip_prefix(prefix)
+                    .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                    .or_else(|| file_name.strip_suffix(wasm_suffix))
+                {
+                    let name = name.to_uppercase();
+                    if !found.iter().any(|(known, _)| *known == name) {
+                        found.push((name, entry.path()));
                     }
                 }
+            }
+        }
+
+        for name in self.iniparser.sections() {
+            if self
+                .iniparser
+                .get_value(name, SETTINGS_PLUGIN_LIBRARY, "", 1)
+                .is_empty()
+            {
+                continue;
+            }
+            let path = self.plugin_path(name);
+            match found.iter_mut().find(|(known, _)| known == name) {
+                Some(entry) => entry.1 = path,
+                None => found.push((name.clone(), path)),
+            }
+        }
+
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    /// Catalogue of the available plugins: each library is loaded just long
+    /// enough to read its version and commands, without settings or init.
+    /// `PROCESS` plugins are loaded in a `plugin_host` child, like when run.
+    pub fn discover(&self) -> Vec<Result<PluginInfo, PluginLoadError>> {
+        self.available_plugins()
+            .into_iter()
+            .map(|(name, path)| {
+                let mut params = ParamsGet::new();
+                if Self::is_wasm(&path) {
+                    WasmPlugin::load(&name, &path, &self.plugin_section(&name))?
+                        .get_params(&mut params);
+                } else if let Some(settings) = self.plugin_host(&name)? {
+                    RemotePlugin::spawn(&name, &path, settings)?.get_params(&mut params);
+                } else {
+                    let (library, mut handle) = Self::open_plugin(&name, &path)?;
+                    params = unsafe { plugin_get_params(&mut handle) };
+                    unsafe { (handle.destroy)(handle.ptr) };
+                    drop(library);
+                }
 
-                // Box it and store as raw pointer
-                let boxed_handle = Box::new(handle);
-                let handle_ptr = Box::into_raw(boxed_handle);
+                Ok(PluginInfo {
+                    version: params
+                        .remove(PARAMS_GET_VERS_KEY)
+                        .and_then(|version| version.into_iter().next()),
+                    commands: params.remove(PARAMS_GET_CMDS_KEY).unwrap_or_default(),
+                    name,
+                    path,
+                })
+            })
+            .collect()
+    }
 
-                self.plugins.insert(
-                    name.clone(),
-                    PluginDescriptor {
-                        handle: handle_ptr,
-                        _lib: library,
-                    },
-                );
+    /// How plugin `name` is supervised if it runs in a `plugin_host`
+    /// process, `None` if it is loaded into the app.
+    fn plugin_host(&self, name: &str) -> Result<Option<HostSettings>, PluginLoadError> {
+        let host =
+            self.iniparser
+                .get_value(name, SETTINGS_PLUGIN_HOST, "LIBRARY", INI_SEARCH_DEPTH);
+        match host.to_uppercase().as_str() {
+            "LIBRARY" => return Ok(None),
+            "PROCESS" => {}
+            _ => {
+                return Err(PluginLoadError::InvalidHostSettings {
+                    plugin: name.to_string(),
+                    key: SETTINGS_PLUGIN_HOST,
+                    value: host,
+                })
             }
         }
-        true
+
+        let setting = |key: &'static str, default: &str| {
+            let value = self
+                .iniparser
+                .get_value(name, key, default, INI_SEARCH_DEPTH);
+            value
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| PluginLoadError::InvalidHostSettings {
+                    plugin: name.to_string(),
+                    key,
+                    value,
+                })
+        };
+        let restarts = setting(SETTINGS_PLUGIN_RESTARTS, "0")?;
+        let timeout = setting(SETTINGS_PLUGIN_TIMEOUT, DEFAULT_PLUGIN_TIMEOUT)?;
+        Ok(Some(HostSettings {
+            restarts,
+            timeout: (timeout > 0).then(|| Duration::from_secs(timeout.into())),
+        }))
     }
 
-    pub fn enable_plugins(&mut self) -> bool {
-        println!("Enabling plugins");
-        for descriptor in self.plugins.values() {
-            unsafe {
-                let handle: &mut PluginHandle = &mut *descriptor.handle;
-                if !plugin_do_enable(handle) {
-                    return false;
+    /// `PRIVILEGED` of the plugin section, read by the host so a plugin
+    /// cannot drop its own privileges.
+    fn plugin_privileged(&self, name: &str) -> Result<bool, PluginLoadError> {
+        let value = self
+            .iniparser
+            .get_value(name, PARAMS_PRIVILEGED, "FALSE", INI_SEARCH_DEPTH);
+        let mut privileged = false;
+        if !string_utils::string_to_bool(&value, &mut privileged) {
+            return Err(PluginLoadError::InvalidHostSettings {
+                plugin: name.to_string(),
+                key: PARAMS_PRIVILEGED,
+                value,
+            });
+        }
+        Ok(privileged)
+    }
+
+    /// Open the library of plugin `name`, check its ABI version and create
+    /// the plugin.
+    pub(crate) fn open_plugin(
+        name: &str,
+        path: &Path,
+    ) -> Result<(Library, PluginHandle), PluginLoadError> {
+        if !path.is_file() {
+            return Err(PluginLoadError::LibraryNotFound {
+                plugin: name.to_string(),
+                path: path.to_path_buf(),
+            });
+        }
+
+        unsafe {
+            let library = Library::new(path).map_err(|err| PluginLoadError::LibraryInvalid {
+                plugin: name.to_string(),
+                path: path.to_path_buf(),
+                reason: err.to_string(),
+            })?;
+
+            // the handle layout is only known to match for the same ABI version
+            let Ok(abi_version) = library.get::<PluginAbiVersionFn>(b"plugin_abi_version") else {
+                return Err(PluginLoadError::SymbolMissing {
+                    plugin: name.to_string(),
+                    path: path.to_path_buf(),
+                    symbol: "plugin_abi_version",
+                });
+            };
+            let version = abi_version();
+            if version != PLUGIN_ABI_VERSION {
+                return Err(PluginLoadError::AbiMismatch {
+                    plugin: name.to_string(),
+                    path: path.to_path_buf(),
+                    found: version,
+                    expected: PLUGIN_ABI_VERSION,
+                });
+            }
+
+            let Ok(create) = library.get::<PluginCreateFn>(b"plugin_create") else {
+                return Err(PluginLoadError::SymbolMissing {
+                    plugin: name.to_string(),
+                    path: path.to_path_buf(),
+                    symbol: "plugin_create",
+                });
+            };
+            let handle = create(); // type PluginHandle
+            Ok((library, handle))
+        }
+    }
+
+    /// Load every plugin of the batch, or none of them: on error the plugins
+    /// already loaded by this call are unloaded again.
+    pub fn load_plugins(&mut self, plugin_names: &HashSet<String>) -> Result<(), PluginLoadError> {
+        if !self.ini_loaded {
+            return Err(PluginLoadError::SettingsNotLoaded {
+                path: self.inipathname.clone(),
+            });
+        }
+
+        let mut names: Vec<&String> = plugin_names.iter().collect();
+        names.sort();
+
+        let mut loaded = Vec::new();
+        for name in names {
+            if self.plugins.contains_key(name) {
+                continue;
+            }
+            if let Err(err) = self.load_plugin(name) {
+                for name in loaded {
+                    self.unload_plugin(name);
                 }
+                return Err(err);
             }
+            loaded.push(name);
         }
-        true
+        Ok(())
     }
 
+    fn load_plugin(&mut self, name: &str) -> Result<(), PluginLoadError> {
+        let path = self.plugin_path(name);
+        let privileged = self.plugin_privileged(name)?;
+        println!("Loading plugin: {:?}", path);
+
+        // wasm modules are already isolated from the app, `HOST` does not apply
+        let (library, handle) = if Self::is_wasm(&path) {
+            let section = self.plugin_section(name);
+            (None, make_handle(WasmPlugin::load(name, &path, &section)?))
+        } else {
+            match self.plugin_host(name)? {
+                Some(settings) => (
+                    None,
+                    make_handle(RemotePlugin::spawn(name, &path, settings)?),
+                ),
+                None => {
+                    let (library, handle) = Self::open_plugin(name, &path)?;
+                    (Some(library), handle)
+                }
+            }
+        };
+
+        // Box it and store as raw pointer
+        let boxed_handle = Box::new(handle);
+        let handle_ptr = Box::into_raw(boxed_handle);
+
+        self.plugins.insert(
+            name.to_string(),
+            PluginDescriptor {
+                handle: handle_ptr,
+                path: path.clone(),
+                privileged,
+                _lib: library,
+            },
+        );
+
+        // retrieve data from inifile and send to it to plugin
+        if let Some(section) = self.iniparser.get_resolved_section(name, INI_SEARCH_DEPTH) {
+            if !unsafe { plugin_set_params(handle_ptr, &section) } {
+                self.unload_plugin(name);
+                return Err(PluginLoadError::ParamsRejected {
+                    plugin: name.to_string(),
+                    path,
+                });
+            }
+        }
+
+        if !unsafe { plugin_do_init(handle_ptr) } {
+            self.unload_plugin(name);
+            return Err(PluginLoadError::InitFailed {
+                plugin: name.to_string(),
+                path,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn enable_plugins(&mut self) -> Result<(), PluginLoadError> {
+        println!("Enabling plugins");
+        for (name, descriptor) in &self.plugins {
+            if !unsafe { plugin_do_enable(descriptor.handle) } {
+                return Err(PluginLoadError::EnableFailed {
+                    plugin: name.clone(),
+                    path: descriptor.path.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Clean up and destroy a plugin, also when the script was aborted.
     fn unload_plugin(&mut self, name: &str) {
         if let Some(descriptor) = self.plugins.remove(name) {
             unsafe {
                 if !descriptor.handle.is_null() {
+                    // initialized plugins release their resources first
+                    plugin_do_cleanup(descriptor.handle);
                     // Call destroy
                     ((*descriptor.handle).destroy)((*descriptor.handle).ptr);
                     // Drop boxed handle
@@ -117,3 +669,183 @@ impl Drop for PluginManager {
         self.unload_plugins();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WASMDEMO: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../plugin_impl/wasmdemo_plugin/wasmdemo_plugin.wat"
+    );
+
+    /// A fresh plugin directory `name` with a settings file holding `ini`.
+    fn manager(name: &str, ini: &str) -> (PluginManager, PathBuf) {
+        let dir = env::temp_dir().join(format!("plugin_manager_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let inipathname = dir.join("settings.ini");
+        fs::write(&inipathname, ini).unwrap();
+        (PluginManager::new(vec![dir.clone()], inipathname), dir)
+    }
+
+    /// Settings section loading the wasm demo plugin as `name`.
+    fn wasmdemo(name: &str) -> String {
+        format!(
+            "[{}]\nLIBRARY = {}\nPRIVILEGED = TRUE\nCAPABILITIES = LOG, TIME, FS\nFS_DIR = {}\n",
+            name,
+            WASMDEMO,
+            env::temp_dir().display()
+        )
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn settings_file_is_needed() {
+        let mut manager = PluginManager::new(Vec::new(), "no/such/settings.ini");
+        assert!(matches!(
+            manager.load_plugins(&names(&["UTILS"])),
+            Err(PluginLoadError::SettingsNotLoaded { path }) if path == Path::new("no/such/settings.ini")
+        ));
+    }
+
+    #[test]
+    fn missing_and_invalid_libraries_are_reported() {
+        let (mut manager, dir) = manager("libraries", "[COMMON]\n");
+        let result = manager.load_plugins(&names(&["ABSENT"]));
+        assert!(matches!(
+            result,
+            Err(PluginLoadError::LibraryNotFound { plugin, path })
+                if plugin == "ABSENT" && path == dir.join(PluginManager::plugin_lib_name("ABSENT"))
+        ));
+
+        fs::write(
+            dir.join(PluginManager::plugin_lib_name("JUNK")),
+            "not a library",
+        )
+        .unwrap();
+        let result = manager.load_plugins(&names(&["JUNK"]));
+        assert!(matches!(
+            result,
+            Err(PluginLoadError::LibraryInvalid { plugin, .. }) if plugin == "JUNK"
+        ));
+        assert!(manager.plugins.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_batch_unloads_its_plugins_only() {
+        let ini = format!("[COMMON]\n{}{}", wasmdemo("AWASM"), wasmdemo("BWASM"));
+        let (mut manager, dir) = manager("batch", &ini);
+
+        manager.load_plugins(&names(&["AWASM"])).unwrap();
+        let result = manager.load_plugins(&names(&["AWASM", "BWASM", "CMISSING"]));
+        assert!(matches!(
+            result,
+            Err(PluginLoadError::LibraryNotFound { plugin, .. }) if plugin == "CMISSING"
+        ));
+        // loaded by the earlier call, so kept
+        assert_eq!(manager.plugins.keys().collect::<Vec<_>>(), vec!["AWASM"]);
+
+        manager.load_plugins(&names(&["BWASM"])).unwrap();
+        assert_eq!(manager.plugins.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn privileges_come_from_the_settings() {
+        let ini = format!(
+            "[COMMON]\n{}[ODD]\nLIBRARY = {}\nPRIVILEGED = sometimes\n",
+            wasmdemo("AWASM"),
+            WASMDEMO
+        );
+        let (mut manager, dir) = manager("privileges", &ini);
+
+        manager.load_plugins(&names(&["AWASM"])).unwrap();
+        assert!(manager.plugins["AWASM"].privileged);
+        assert!(matches!(
+            manager.load_plugins(&names(&["ODD"])),
+            Err(PluginLoadError::InvalidHostSettings { plugin, key, value })
+                if plugin == "ODD" && key == PARAMS_PRIVILEGED && value == "sometimes"
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn search_dirs_keep_their_order_once() {
+        let (_, dir) = manager("dirs", "");
+        let ini = dir.join("settings.ini");
+        fs::write(&ini, "[COMMON]\nPLUGINS_DIRS = b, , a, c\n").unwrap();
+        let manager = PluginManager::new(vec![PathBuf::from("a"), PathBuf::from("b")], &ini);
+        assert!(manager.plugins_dirs().starts_with(&[
+            PathBuf::from("a"),
+            PathBuf::from("b"),
+            PathBuf::from("c")
+        ]));
+        let a_dirs = manager
+            .plugins_dirs()
+            .iter()
+            .filter(|dir| *dir == Path::new("a"));
+        assert_eq!(a_dirs.count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn available_plugins_come_from_the_first_dir_or_library() {
+        let (_, first) = manager("available_first", "");
+        let (_, second) = manager("available_second", "");
+        let ini = second.join("settings.ini");
+        fs::write(&ini, format!("[COMMON]\n{}", wasmdemo("FOO"))).unwrap();
+        for dir in [&first, &second] {
+            fs::write(dir.join(PluginManager::plugin_lib_name("BAR")), "").unwrap();
+            fs::write(dir.join(PluginManager::plugin_lib_name("FOO")), "").unwrap();
+        }
+        fs::write(second.join("wasm_plugin.wasm"), "").unwrap();
+        fs::write(second.join("notes_plugin.txt"), "").unwrap();
+
+        let manager = PluginManager::new(vec![first.clone(), second.clone()], &ini);
+        let available = manager.available_plugins();
+        assert_eq!(
+            &available[..3],
+            [
+                (
+                    "BAR".to_string(),
+                    first.join(PluginManager::plugin_lib_name("BAR"))
+                ),
+                ("FOO".to_string(), PathBuf::from(WASMDEMO)),
+                ("WASM".to_string(), second.join("wasm_plugin.wasm")),
+            ]
+        );
+
+        let discovered = manager.discover();
+        assert!(matches!(
+            &discovered[0],
+            Err(PluginLoadError::LibraryInvalid { plugin, .. }) if plugin == "BAR"
+        ));
+        let foo = discovered[1].as_ref().unwrap();
+        assert_eq!(foo.version.as_deref(), Some("1.0.0.0"));
+        assert_eq!(foo.commands, ["WECHO", "WTIME", "WREAD"]);
+        assert!(discovered[2].is_err());
+
+        fs::remove_dir_all(first).unwrap();
+        fs::remove_dir_all(second).unwrap();
+    }
+
+    #[test]
+    fn loaded_plugins_are_initialized_then_enabled() {
+        let ini = format!("[COMMON]\n{}", wasmdemo("WASMDEMO"));
+        let (mut manager, dir) = manager("lifecycle", &ini);
+        manager.load_plugins(&names(&["WASMDEMO"])).unwrap();
+        let handle = manager.plugins["WASMDEMO"].handle;
+        let handle = unsafe { &*handle };
+        unsafe {
+            assert!((handle.is_initialized)(handle.ptr));
+            assert!(!(handle.is_enabled)(handle.ptr));
+        }
+        manager.enable_plugins().unwrap();
+        unsafe { assert!((handle.is_enabled)(handle.ptr)) };
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
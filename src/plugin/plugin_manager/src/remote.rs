@@ -0,0 +1,708 @@
+//! Out-of-process plugins: the library is loaded by a `plugin_host` child
+//! process, a crash there cannot take the script down with it.
+//!
+//! The host sends one JSON [`Request`] per line on the child's stdin, the
+//! child answers one JSON [`Response`] per line on its stdout, marked with
+//! [`RESPONSE_MARKER`]. Any other output is from the plugin and is
+//! forwarded to the stdout of the host. A child that does not answer
+//! within the configured timeout is killed like a crashed one.
+
+use std::env;
+use std::ffi::c_void;
+use std::io::{self, BufRead, BufReader, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use plugin_api::{
+    plugin_do_cleanup, plugin_do_dispatch, plugin_do_enable, plugin_do_init, plugin_get_data,
+    plugin_get_params, plugin_panic, plugin_set_params, DispatchError, ParamsGet, ParamsSet,
+    PluginHandle, PluginInterface, PluginValue,
+};
+
+use crate::{PluginLoadError, PluginManager};
+
+/// Name of the child process executable, built next to the app.
+pub const PLUGIN_HOST_PROGRAM: &str = "plugin_host";
+
+/// Starts the responses of the child, plugin output never contains it.
+/// Output without a final newline can precede it on the same line.
+const RESPONSE_MARKER: char = '\u{1e}';
+
+/// How a `PROCESS` plugin is supervised, from its settings section.
+#[derive(Debug, Clone, Copy)]
+pub struct HostSettings {
+    /// automatic restarts after a crash
+    pub restarts: u32,
+    /// longest wait for an answer of the child, `None` waits forever
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    SetParams(ParamsSet),
+    Init,
+    Enable,
+    Dispatch { command: String, args: Vec<String> },
+    Cleanup,
+    ResetData,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Response {
+    /// The library is loaded, sent once at startup
+    Ready { params: ParamsGet, state: State },
+    Done {
+        success: bool,
+        data: WireValue,
+        state: State,
+    },
+    /// The library could not be loaded, or the plugin panicked
+    Failed(String),
+}
+
+/// Plugin state as reported after every request, so queries stay local.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct State {
+    initialized: bool,
+    enabled: bool,
+    privileged: bool,
+    fault_tolerant: bool,
+}
+
+/// [`PluginValue`] on the wire.
+#[derive(Debug, Serialize, Deserialize)]
+enum WireValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<WireValue>),
+}
+
+impl From<PluginValue> for WireValue {
+    fn from(value: PluginValue) -> Self {
+        match value {
+            PluginValue::Bool(boolean) => WireValue::Bool(boolean),
+            PluginValue::Int(int) => WireValue::Int(int),
+            PluginValue::Float(float) => WireValue::Float(float),
+            PluginValue::Str(text) => WireValue::Str(text),
+            PluginValue::Bytes(bytes) => WireValue::Bytes(bytes),
+            PluginValue::List(elements) => {
+                WireValue::List(elements.into_iter().map(WireValue::from).collect())
+            }
+        }
+    }
+}
+
+impl From<WireValue> for PluginValue {
+    fn from(value: WireValue) -> Self {
+        match value {
+            WireValue::Bool(boolean) => PluginValue::Bool(boolean),
+            WireValue::Int(int) => PluginValue::Int(int),
+            WireValue::Float(float) => PluginValue::Float(float),
+            WireValue::Str(text) => PluginValue::Str(text),
+            WireValue::Bytes(bytes) => PluginValue::Bytes(bytes),
+            WireValue::List(elements) => {
+                PluginValue::List(elements.into_iter().map(PluginValue::from).collect())
+            }
+        }
+    }
+}
+
+struct HostProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// stdout lines of the child, read by a thread so waits can time out
+    lines: Receiver<io::Result<String>>,
+    timeout: Option<Duration>,
+}
+
+impl HostProcess {
+    fn spawn(program: &Path, library: &Path, timeout: Option<Duration>) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .arg(library)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let mut stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+        let (sender, lines) = mpsc::channel();
+        // ends at the end of the output of the child, or when the host is gone
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            let read = match stdout.read_line(&mut line) {
+                Ok(0) => return,
+                Ok(_) => Ok(line),
+                Err(err) => Err(err),
+            };
+            if sender.send(read).is_err() {
+                return;
+            }
+        });
+        Ok(HostProcess {
+            child,
+            stdin,
+            lines,
+            timeout,
+        })
+    }
+
+    fn send(&mut self, request: &Request) -> io::Result<()> {
+        let line = serde_json::to_string(request)?;
+        writeln!(self.stdin, "{}", line)?;
+        self.stdin.flush()
+    }
+
+    /// Next response, forwarding the plugin output read before it.
+    fn receive(&mut self) -> io::Result<Response> {
+        loop {
+            let line = match self.timeout {
+                Some(timeout) => self.lines.recv_timeout(timeout).map_err(|err| match err {
+                    RecvTimeoutError::Timeout => io::Error::from(io::ErrorKind::TimedOut),
+                    RecvTimeoutError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+                }),
+                None => self
+                    .lines
+                    .recv()
+                    .map_err(|_| io::ErrorKind::UnexpectedEof.into()),
+            }??;
+            match line.split_once(RESPONSE_MARKER) {
+                Some((output, response)) => {
+                    print!("{}", output);
+                    return Ok(serde_json::from_str(response)?);
+                }
+                None => print!("{}", line),
+            }
+        }
+    }
+
+    /// Exit status of the crashed child, once its pipes failed or it
+    /// stopped answering.
+    fn crash_status(&mut self) -> String {
+        let _ = self.child.kill();
+        match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    /// Close stdin so the child cleans up and exits, forward the output it
+    /// flushes on the way out, then reap it. A child still running after
+    /// the timeout is killed.
+    fn shutdown(self) {
+        let HostProcess {
+            mut child,
+            stdin,
+            lines,
+            timeout,
+        } = self;
+        drop(stdin);
+        if !Self::forward_remaining(&lines, timeout, |output| print!("{}", output)) {
+            let _ = child.kill();
+        }
+        let _ = child.wait();
+    }
+
+    /// Pass the output left in `lines` to `forward` up to the end of the
+    /// stdout of the child, false if it did not end within `timeout`.
+    fn forward_remaining(
+        lines: &Receiver<io::Result<String>>,
+        timeout: Option<Duration>,
+        mut forward: impl FnMut(&str),
+    ) -> bool {
+        loop {
+            let line = match timeout {
+                Some(timeout) => match lines.recv_timeout(timeout) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => return false,
+                    Err(RecvTimeoutError::Disconnected) => return true,
+                },
+                None => match lines.recv() {
+                    Ok(line) => line,
+                    Err(_) => return true,
+                },
+            };
+            let Ok(line) = line else {
+                return true;
+            };
+            match line.split_once(RESPONSE_MARKER) {
+                Some((output, _)) => forward(output),
+                None => forward(&line),
+            }
+        }
+    }
+}
+
+/// A plugin running in a `plugin_host` child process, seen through the
+/// same [`PluginInterface`] as the plugins loaded in process.
+pub struct RemotePlugin {
+    name: String,
+    program: PathBuf,
+    library: PathBuf,
+    process: Option<HostProcess>,
+    /// automatic restarts left after a crash
+    restarts: u32,
+    timeout: Option<Duration>,
+    params: ParamsGet,
+    settings: ParamsSet,
+    state: State,
+    data: PluginValue,
+}
+
+impl RemotePlugin {
+    /// Start the child process for plugin `name` and wait until its library
+    /// is loaded.
+    pub fn spawn(
+        name: &str,
+        library: &Path,
+        settings: HostSettings,
+    ) -> Result<Self, PluginLoadError> {
+        let program = Self::host_program();
+        let mut plugin = RemotePlugin {
+            name: name.to_string(),
+            program,
+            library: library.to_path_buf(),
+            process: None,
+            restarts: settings.restarts,
+            timeout: settings.timeout,
+            params: ParamsGet::new(),
+            settings: ParamsSet::new(),
+            state: State::default(),
+            data: PluginValue::default(),
+        };
+        plugin
+            .start()
+            .map_err(|reason| PluginLoadError::HostFailed {
+                plugin: name.to_string(),
+                path: library.to_path_buf(),
+                reason,
+            })?;
+        Ok(plugin)
+    }
+
+    /// `plugin_host` next to the running executable.
+    fn host_program() -> PathBuf {
+        let file_name = format!("{}{}", PLUGIN_HOST_PROGRAM, env::consts::EXE_SUFFIX);
+        env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(&file_name)))
+            .unwrap_or_else(|| PathBuf::from(file_name))
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        let mut process = HostProcess::spawn(&self.program, &self.library, self.timeout)
+            .map_err(|err| format!("cannot start {:?}: {}", self.program, err))?;
+        match process.receive() {
+            Ok(Response::Ready { params, state }) => {
+                self.params = params;
+                self.state = state;
+                self.process = Some(process);
+                Ok(())
+            }
+            Ok(Response::Failed(reason)) => {
+                process.shutdown();
+                Err(reason)
+            }
+            Ok(response) => {
+                process.shutdown();
+                Err(format!("unexpected response {:?}", response))
+            }
+            Err(err) => Err(format!(
+                "plugin host exited ({}): {}",
+                process.crash_status(),
+                err
+            )),
+        }
+    }
+
+    /// Start a new child after a crash and bring it back to the state of
+    /// the crashed one.
+    fn restart(&mut self) -> Result<(), String> {
+        let state = self.state;
+        self.start()?;
+        let settings = self.settings.clone();
+        let mut replay = vec![Request::SetParams(settings)];
+        if state.initialized {
+            replay.push(Request::Init);
+        }
+        if state.enabled {
+            replay.push(Request::Enable);
+        }
+        for request in replay {
+            match self.exchange(&request) {
+                Ok(Response::Done { state, .. }) => self.state = state,
+                Ok(response) => return Err(format!("unexpected response {:?}", response)),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    fn exchange(&mut self, request: &Request) -> io::Result<Response> {
+        let process = self
+            .process
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        process.send(request)?;
+        process.receive()
+    }
+
+    /// Run `request` in the child. A crash restarts it while restarts are
+    /// left and runs the request once more in the new child, a command can
+    /// then be executed twice. Without restarts, or when the request crashes
+    /// the new child too, the plugin is poisoned through the panic guard of
+    /// its handle, so the crash is reported whatever `FAULT_TOLERANT` says.
+    fn request(&mut self, request: Request) -> Option<(bool, PluginValue)> {
+        let err = match self.exchange(&request) {
+            Ok(response) => return self.answer(response),
+            Err(err) => err,
+        };
+        let message = self.crashed(err);
+        if self.restarts == 0 {
+            Self::poison(message);
+        }
+        self.restarts -= 1;
+        println!("⚠️ {}, restarting it", message);
+        if let Err(reason) = self.restart() {
+            Self::poison(format!("{}, restart failed: {}", message, reason));
+        }
+        match self.exchange(&request) {
+            Ok(response) => self.answer(response),
+            Err(err) => {
+                let again = self.crashed(err);
+                Self::poison(format!("{}, then after restarting: {}", message, again))
+            }
+        }
+    }
+
+    fn answer(&mut self, response: Response) -> Option<(bool, PluginValue)> {
+        match response {
+            Response::Done {
+                success,
+                data,
+                state,
+            } => {
+                self.state = state;
+                Some((success, data.into()))
+            }
+            Response::Failed(message) => Self::poison(message),
+            Response::Ready { .. } => None,
+        }
+    }
+
+    /// Reap the child whose exchange failed with `err`, and describe why.
+    fn crashed(&mut self, err: io::Error) -> String {
+        let status = match self.process.take() {
+            Some(mut process) => process.crash_status(),
+            None => err.to_string(),
+        };
+        if err.kind() == io::ErrorKind::TimedOut {
+            format!(
+                "plugin host process of {} did not answer within {:?}, killed ({})",
+                self.name,
+                self.timeout.unwrap_or_default(),
+                status
+            )
+        } else {
+            format!("plugin host process of {} exited ({})", self.name, status)
+        }
+    }
+
+    fn poison(message: String) -> ! {
+        // unwinds to the guard in `make_handle`, without the panic hook output
+        panic::resume_unwind(Box::new(message))
+    }
+}
+
+impl PluginInterface for RemotePlugin {
+    fn do_init(&mut self) {
+        self.request(Request::Init);
+    }
+    fn do_enable(&mut self) {
+        self.request(Request::Enable);
+    }
+    fn do_dispatch(&mut self, cmd: &str, args: &[&str]) -> bool {
+        let request = Request::Dispatch {
+            command: cmd.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        match self.request(request) {
+            Some((success, data)) => {
+                self.data = data;
+                success
+            }
+            None => false,
+        }
+    }
+    fn do_cleanup(&mut self) {
+        self.request(Request::Cleanup);
+    }
+    fn set_params(&mut self, params: &ParamsSet) -> bool {
+        self.settings = params.clone();
+        self.request(Request::SetParams(params.clone()))
+            .is_some_and(|(success, _)| success)
+    }
+    fn get_params(&self, params: &mut ParamsGet) {
+        *params = self.params.clone();
+    }
+    fn get_data(&self) -> &PluginValue {
+        &self.data
+    }
+    fn reset_data(&mut self) {
+        self.data.clear();
+        self.request(Request::ResetData);
+    }
+    fn is_initialized(&self) -> bool {
+        self.state.initialized
+    }
+    fn is_enabled(&self) -> bool {
+        self.state.enabled
+    }
+    fn is_privileged(&self) -> bool {
+        self.state.privileged
+    }
+    fn is_fault_tolerant(&self) -> bool {
+        self.state.fault_tolerant
+    }
+}
+
+impl Drop for RemotePlugin {
+    fn drop(&mut self) {
+        if let Some(process) = self.process.take() {
+            process.shutdown();
+        }
+    }
+}
+
+// ---------------------------
+// Child process side
+// ---------------------------
+
+fn respond(response: &Response) -> io::Result<()> {
+    let line = serde_json::to_string(response)?;
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}{}", RESPONSE_MARKER, line)?;
+    stdout.flush()
+}
+
+/// # Safety
+/// `handle` must point to a valid [`PluginHandle`].
+unsafe fn state(handle: *mut PluginHandle) -> State {
+    let plugin = &*handle;
+    let ptr: *mut c_void = plugin.ptr;
+    State {
+        initialized: (plugin.is_initialized)(ptr),
+        enabled: (plugin.is_enabled)(ptr),
+        privileged: (plugin.is_privileged)(ptr),
+        fault_tolerant: (plugin.is_fault_tolerant)(ptr),
+    }
+}
+
+/// Serve the plugin library at `library` on stdin/stdout until stdin is
+/// closed, the body of the `plugin_host` process.
+pub fn serve_plugin(library: &Path) -> io::Result<()> {
+    let name = library
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (_library, handle) = match PluginManager::open_plugin(&name, library) {
+        Ok(opened) => opened,
+        Err(err) => return respond(&Response::Failed(err.to_string())),
+    };
+    let handle = Box::into_raw(Box::new(handle));
+
+    unsafe {
+        respond(&Response::Ready {
+            params: plugin_get_params(handle),
+            state: state(handle),
+        })?;
+
+        for line in io::stdin().lock().lines() {
+            let request: Request = serde_json::from_str(&line?)?;
+            let success = match request {
+                Request::SetParams(params) => plugin_set_params(handle, &params),
+                Request::Init => plugin_do_init(handle),
+                Request::Enable => plugin_do_enable(handle),
+                Request::Dispatch { command, args } => {
+                    match plugin_do_dispatch(handle, &command, &args) {
+                        Ok(()) => true,
+                        Err(DispatchError::Failed) => false,
+                        Err(
+                            DispatchError::Panicked(message) | DispatchError::Poisoned(message),
+                        ) => {
+                            respond(&Response::Failed(message))?;
+                            continue;
+                        }
+                    }
+                }
+                Request::Cleanup => plugin_do_cleanup(handle),
+                Request::ResetData => {
+                    ((*handle).reset_data)((*handle).ptr);
+                    true
+                }
+            };
+            if let Some(message) = plugin_panic(handle) {
+                respond(&Response::Failed(message))?;
+                continue;
+            }
+            respond(&Response::Done {
+                success,
+                data: plugin_get_data(handle).into(),
+                state: state(handle),
+            })?;
+        }
+
+        // the host is gone, release what the plugin holds
+        plugin_do_cleanup(handle);
+        let handle = Box::from_raw(handle);
+        (handle.destroy)(handle.ptr);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str, extension: &str) -> PathBuf {
+        env::temp_dir().join(format!(
+            "remote_{}_{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ))
+    }
+
+    /// A child running the shell `script` in place of `plugin_host`.
+    fn host(name: &str, script: &str, timeout: Option<Duration>) -> HostProcess {
+        let path = temp_path(name, "sh");
+        fs::write(&path, script).unwrap();
+        HostProcess::spawn(Path::new("sh"), &path, timeout).unwrap()
+    }
+
+    /// A started plugin whose child is a shell `plugin_host` stand-in,
+    /// running `dispatch` for `Dispatch` and answering the other requests.
+    fn remote(name: &str, dispatch: &str, restarts: u32) -> RemotePlugin {
+        let path = temp_path(name, "sh");
+        let script = format!(
+            r#"S='{{"initialized":true,"enabled":true,"privileged":false,"fault_tolerant":true}}'
+answer() {{ printf '\036{{"Done":{{"success":true,"data":{{"Str":"%s"}},"state":%s}}}}\n' "$1" "$S"; }}
+printf '\036{{"Ready":{{"params":{{}},"state":%s}}}}\n' "$S"
+while read -r line; do
+  case "$line" in
+    *Dispatch*) {} ;;
+    *) answer ok ;;
+  esac
+done
+"#,
+            dispatch
+        );
+        fs::write(&path, script).unwrap();
+        let mut plugin = RemotePlugin {
+            name: name.to_string(),
+            program: PathBuf::from("sh"),
+            library: path,
+            process: None,
+            restarts,
+            timeout: Some(Duration::from_secs(10)),
+            params: ParamsGet::new(),
+            settings: ParamsSet::new(),
+            state: State::default(),
+            data: PluginValue::default(),
+        };
+        plugin.start().unwrap();
+        plugin
+    }
+
+    #[test]
+    fn response_after_output_without_newline_is_found() {
+        let mut process = host(
+            "marker",
+            "printf 'partial output\\036{\"Failed\":\"boom\"}\\n'",
+            None,
+        );
+        match process.receive() {
+            Ok(Response::Failed(message)) => assert_eq!(message, "boom"),
+            other => panic!("unexpected {:?}", other),
+        }
+        process.shutdown();
+    }
+
+    #[test]
+    fn silent_child_times_out() {
+        let mut process = host("silent", "sleep 10", Some(Duration::from_millis(100)));
+        let err = process.receive().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        process.crash_status();
+    }
+
+    #[test]
+    fn output_left_at_shutdown_is_forwarded() {
+        let HostProcess {
+            mut child,
+            stdin,
+            lines,
+            ..
+        } = host(
+            "late",
+            "cat >/dev/null; printf 'late\\nno newline'",
+            Some(Duration::from_secs(10)),
+        );
+        drop(stdin);
+        let mut output = Vec::new();
+        assert!(HostProcess::forward_remaining(
+            &lines,
+            Some(Duration::from_secs(10)),
+            |line| output.push(line.to_string())
+        ));
+        assert_eq!(output, ["late\n", "no newline"]);
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn crashed_command_runs_again_after_restart() {
+        let flag = temp_path("crash_once", "flag");
+        let _ = fs::remove_file(&flag);
+        let mut plugin = remote(
+            "crash_once",
+            &format!(
+                "[ -e {0} ] || {{ touch {0}; exit 1; }}; answer ran",
+                flag.display()
+            ),
+            1,
+        );
+        assert!(plugin.do_dispatch("CMD", &[]));
+        assert_eq!(plugin.get_data(), &PluginValue::Str("ran".to_string()));
+        assert_eq!(plugin.restarts, 0);
+        fs::remove_file(flag).unwrap();
+    }
+
+    #[test]
+    fn second_crash_is_not_hidden_by_fault_tolerance() {
+        let mut handle = plugin_api::make_handle(remote("crash_always", "exit 1", 1));
+        match unsafe { plugin_do_dispatch(&mut handle, "CMD", &[]) } {
+            Err(DispatchError::Panicked(message)) => {
+                assert!(message.contains("then after restarting"), "{}", message)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        unsafe { (handle.destroy)(handle.ptr) };
+    }
+
+    #[test]
+    fn exited_child_is_end_of_file() {
+        let mut process = host("exited", "exit 3", Some(Duration::from_secs(10)));
+        let err = process.receive().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(process.crash_status().contains('3'));
+    }
+}
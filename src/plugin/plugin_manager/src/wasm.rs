@@ -0,0 +1,688 @@
+//! WebAssembly plugins, run by the embedded wasmi interpreter.
+//!
+//! The module only reaches the host through the imports it was granted, so
+//! `PRIVILEGED` is enforced here instead of being a flag the plugin reports.
+//!
+//! Exports of a plugin module (`ptr`/`len` are `i32`, `packed` is an `i64`
+//! holding `ptr << 32 | len`):
+//!
+//! | export                                             | contract                                |
+//! |----------------------------------------------------|-----------------------------------------|
+//! | `memory`                                           | linear memory                           |
+//! | `plugin_abi_version() -> i32`                      | [`WASM_ABI_VERSION`]                    |
+//! | `plugin_alloc(len) -> ptr`                         | buffer for the host, valid for one call |
+//! | `plugin_init()`, `plugin_enable()`, `plugin_cleanup()` | lifecycle                           |
+//! | `plugin_set_params(ptr, len) -> i32`               | `KEY=VALUE` lines, non zero if accepted |
+//! | `plugin_get_params() -> packed`                    | `KEY=VALUE VALUE ...` lines             |
+//! | `plugin_dispatch(cmd, cmd_len, args, args_len) -> i32` | NUL separated args, non zero on success |
+//! | `plugin_get_data() -> packed`                      | UTF-8 result of the last command        |
+//!
+//! Imports from module `env`, each one needs a capability of the plugin:
+//!
+//! | import                                        | capability                                |
+//! |-----------------------------------------------|-------------------------------------------|
+//! | `log(ptr, len)`                               | `LOG`                                     |
+//! | `time_ms() -> i64`                            | `TIME`, milliseconds since the Unix epoch |
+//! | `fs_read(path, path_len, buf, cap) -> i32`    | `FS`, bytes read or -1                    |
+//! | `fs_write(path, path_len, data, len) -> i32`  | `FS`, bytes written or -1                 |
+//!
+//! `FS` gives access to the `FS_DIR` directory only, and only to privileged plugins.
+//! The directory must exist when the plugin is loaded, paths leading out of it,
+//! through `..` or a symlink, are refused.
+
+use std::collections::HashSet;
+use std::fs;
+use std::panic;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wasmi::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store};
+
+use plugin_api::{
+    ParamsGet, ParamsSet, PluginInterface, PluginValue, PARAMS_FAULT_TOLERANT, PARAMS_PRIVILEGED,
+};
+use utils::string_utils;
+
+use crate::PluginLoadError;
+
+/// Version of the export and import contract above.
+pub const WASM_ABI_VERSION: i32 = 1;
+
+/// Key of a plugin section with the comma separated capabilities granted
+pub const SETTINGS_WASM_CAPABILITIES: &str = "CAPABILITIES";
+/// Key of a plugin section with the directory of the `FS` capability
+pub const SETTINGS_WASM_FS_DIR: &str = "FS_DIR";
+
+const CAPABILITY_LOG: &str = "LOG";
+const CAPABILITY_TIME: &str = "TIME";
+const CAPABILITY_FS: &str = "FS";
+
+/// Granted when the plugin section does not list any
+const DEFAULT_CAPABILITIES: &[&str] = &[CAPABILITY_LOG];
+/// Only granted to plugins with `PRIVILEGED = TRUE`
+const PRIVILEGED_CAPABILITIES: &[&str] = &[CAPABILITY_FS];
+
+/// Capability needed by each `env` import.
+fn import_capability(name: &str) -> Option<&'static str> {
+    match name {
+        "log" => Some(CAPABILITY_LOG),
+        "time_ms" => Some(CAPABILITY_TIME),
+        "fs_read" | "fs_write" => Some(CAPABILITY_FS),
+        _ => None,
+    }
+}
+
+/// Store data: what the host functions may do for this plugin.
+struct WasmHost {
+    name: String,
+    fs_dir: Option<PathBuf>,
+}
+
+impl WasmHost {
+    /// `path` inside the `FS` directory, `None` if it would leave it.
+    fn fs_path(&self, path: &str) -> Option<PathBuf> {
+        let dir = self.fs_dir.as_ref()?;
+        let path = Path::new(path);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
+        }
+
+        // a symlink inside the directory can point out of it
+        let joined = dir.join(path);
+        let resolved = match joined.canonicalize() {
+            Ok(resolved) => resolved,
+            // a file to create, but not through a dangling symlink
+            Err(_) if fs::symlink_metadata(&joined).is_err() => joined
+                .parent()?
+                .canonicalize()
+                .ok()?
+                .join(joined.file_name()?),
+            Err(_) => return None,
+        };
+        resolved.starts_with(dir).then_some(resolved)
+    }
+}
+
+fn memory(caller: &Caller<'_, WasmHost>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+fn read_guest(caller: &Caller<'_, WasmHost>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let mut buffer = vec![0; usize::try_from(len).ok()?];
+    memory(caller)?
+        .read(caller, usize::try_from(ptr).ok()?, &mut buffer)
+        .ok()?;
+    Some(buffer)
+}
+
+fn read_guest_str(caller: &Caller<'_, WasmHost>, ptr: i32, len: i32) -> Option<String> {
+    read_guest(caller, ptr, len).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A WebAssembly plugin seen through the same [`PluginInterface`] as the
+/// native ones.
+pub struct WasmPlugin {
+    name: String,
+    store: Store<WasmHost>,
+    instance: Instance,
+    memory: Memory,
+    params: ParamsGet,
+    data: PluginValue,
+    initialized: bool,
+    enabled: bool,
+    privileged: bool,
+    fault_tolerant: bool,
+}
+
+impl WasmPlugin {
+    /// Compile and instantiate the module at `path` (`.wasm`, or `.wat`
+    /// text), granting the capabilities of its settings `section`.
+    pub fn load(name: &str, path: &Path, section: &ParamsSet) -> Result<Self, PluginLoadError> {
+        let failed = |reason: String| PluginLoadError::WasmFailed {
+            plugin: name.to_string(),
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        if !path.is_file() {
+            return Err(PluginLoadError::LibraryNotFound {
+                plugin: name.to_string(),
+                path: path.to_path_buf(),
+            });
+        }
+
+        let mut privileged = false;
+        if let Some(value) = section.get(PARAMS_PRIVILEGED) {
+            if !string_utils::string_to_bool(value, &mut privileged) {
+                return Err(failed(format!(
+                    "invalid value for {} -> {}",
+                    PARAMS_PRIVILEGED, value
+                )));
+            }
+        }
+        let capabilities = Self::capabilities(name, section, privileged)?;
+
+        let bytes = fs::read(path).map_err(|err| failed(err.to_string()))?;
+        let bytes = if path.extension().is_some_and(|ext| ext == "wat") {
+            wat::parse_bytes(&bytes)
+                .map_err(|err| failed(err.to_string()))?
+                .into_owned()
+        } else {
+            bytes
+        };
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &bytes).map_err(|err| failed(err.to_string()))?;
+
+        // refuse the imports of capabilities that were not granted, before linking
+        for import in module.imports() {
+            let capability = (import.module() == "env")
+                .then(|| import_capability(import.name()))
+                .flatten()
+                .ok_or_else(|| {
+                    failed(format!(
+                        "unknown import {}.{}",
+                        import.module(),
+                        import.name()
+                    ))
+                })?;
+            if !capabilities.contains(capability) {
+                return Err(PluginLoadError::CapabilityNotGranted {
+                    plugin: name.to_string(),
+                    path: path.to_path_buf(),
+                    import: import.name().to_string(),
+                    capability,
+                });
+            }
+        }
+
+        let fs_dir = if capabilities.contains(CAPABILITY_FS) {
+            Some(Self::fs_dir(name, section)?)
+        } else {
+            None
+        };
+        let mut store = Store::new(
+            &engine,
+            WasmHost {
+                name: name.to_string(),
+                fs_dir,
+            },
+        );
+        let linker = Self::linker(&engine).map_err(|err| failed(err.to_string()))?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|err| failed(err.to_string()))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| failed("no exported memory".to_string()))?;
+
+        let mut plugin = WasmPlugin {
+            name: name.to_string(),
+            store,
+            instance,
+            memory,
+            params: ParamsGet::new(),
+            data: PluginValue::default(),
+            initialized: false,
+            enabled: false,
+            privileged,
+            fault_tolerant: false,
+        };
+
+        let version = plugin
+            .call::<(), i32>("plugin_abi_version", ())
+            .map_err(failed)?;
+        if version != WASM_ABI_VERSION {
+            return Err(failed(format!(
+                "built for wasm plugin ABI version {}, expected version {}",
+                version, WASM_ABI_VERSION
+            )));
+        }
+        let params = plugin
+            .call::<(), i64>("plugin_get_params", ())
+            .and_then(|packed| plugin.read_packed(packed))
+            .map_err(failed)?;
+        plugin.params = Self::parse_params(&params);
+        Ok(plugin)
+    }
+
+    /// The capabilities listed in `section`, refusing the privileged ones
+    /// to an unprivileged plugin.
+    fn capabilities(
+        name: &str,
+        section: &ParamsSet,
+        privileged: bool,
+    ) -> Result<HashSet<&'static str>, PluginLoadError> {
+        let Some(listed) = section.get(SETTINGS_WASM_CAPABILITIES) else {
+            return Ok(DEFAULT_CAPABILITIES.iter().copied().collect());
+        };
+
+        let mut capabilities = HashSet::new();
+        for capability in listed.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let capability = [CAPABILITY_LOG, CAPABILITY_TIME, CAPABILITY_FS]
+                .into_iter()
+                .find(|known| known.eq_ignore_ascii_case(capability))
+                .ok_or_else(|| PluginLoadError::InvalidHostSettings {
+                    plugin: name.to_string(),
+                    key: SETTINGS_WASM_CAPABILITIES,
+                    value: capability.to_string(),
+                })?;
+            if PRIVILEGED_CAPABILITIES.contains(&capability) && !privileged {
+                return Err(PluginLoadError::CapabilityDenied {
+                    plugin: name.to_string(),
+                    capability,
+                });
+            }
+            capabilities.insert(capability);
+        }
+        Ok(capabilities)
+    }
+
+    /// The existing `FS_DIR` of `section`, canonicalized so the paths of
+    /// the plugin can be checked against it.
+    fn fs_dir(name: &str, section: &ParamsSet) -> Result<PathBuf, PluginLoadError> {
+        let dir = section
+            .get(SETTINGS_WASM_FS_DIR)
+            .map(String::as_str)
+            .unwrap_or_default();
+        Path::new(dir)
+            .canonicalize()
+            .ok()
+            .filter(|dir| dir.is_dir())
+            .ok_or_else(|| PluginLoadError::InvalidHostSettings {
+                plugin: name.to_string(),
+                key: SETTINGS_WASM_FS_DIR,
+                value: dir.to_string(),
+            })
+    }
+
+    fn linker(engine: &Engine) -> Result<Linker<WasmHost>, wasmi::Error> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap(
+            "env",
+            "log",
+            |caller: Caller<'_, WasmHost>, ptr: i32, len: i32| {
+                if let Some(message) = read_guest_str(&caller, ptr, len) {
+                    println!("📝 [{}] {}", caller.data().name, message);
+                }
+            },
+        )?;
+        linker.func_wrap("env", "time_ms", || -> i64 {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as i64)
+        })?;
+        linker.func_wrap(
+            "env",
+            "fs_read",
+            |mut caller: Caller<'_, WasmHost>, path: i32, path_len: i32, buf: i32, cap: i32| {
+                let Some(path) = read_guest_str(&caller, path, path_len)
+                    .and_then(|path| caller.data().fs_path(&path))
+                else {
+                    return -1;
+                };
+                let (Ok(contents), Some(memory)) = (fs::read(path), memory(&caller)) else {
+                    return -1;
+                };
+                let len = contents.len().min(usize::try_from(cap).unwrap_or(0));
+                match memory.write(&mut caller, buf as usize, &contents[..len]) {
+                    Ok(()) => len as i32,
+                    Err(_) => -1,
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "fs_write",
+            |caller: Caller<'_, WasmHost>, path: i32, path_len: i32, data: i32, len: i32| {
+                let Some(path) = read_guest_str(&caller, path, path_len)
+                    .and_then(|path| caller.data().fs_path(&path))
+                else {
+                    return -1;
+                };
+                match read_guest(&caller, data, len).map(|contents| fs::write(path, contents)) {
+                    Some(Ok(())) => len,
+                    _ => -1,
+                }
+            },
+        )?;
+        Ok(linker)
+    }
+
+    fn call<Params, Results>(&mut self, name: &str, params: Params) -> Result<Results, String>
+    where
+        Params: wasmi::WasmParams,
+        Results: wasmi::WasmResults,
+    {
+        self.instance
+            .get_typed_func::<Params, Results>(&self.store, name)
+            .and_then(|func| func.call(&mut self.store, params))
+            .map_err(|err| format!("{}: {}", name, err))
+    }
+
+    /// Like [`WasmPlugin::call`], a trap poisons the plugin through the
+    /// panic guard of its handle.
+    fn call_or_poison<Params, Results>(&mut self, name: &str, params: Params) -> Results
+    where
+        Params: wasmi::WasmParams,
+        Results: wasmi::WasmResults,
+    {
+        match self.call(name, params) {
+            Ok(results) => results,
+            Err(message) => panic::resume_unwind(Box::new(format!(
+                "wasm plugin {} trapped in {}",
+                self.name, message
+            ))),
+        }
+    }
+
+    /// Copy `bytes` into a buffer of the plugin, as `(ptr, len)`.
+    fn write_bytes(&mut self, bytes: &[u8]) -> (i32, i32) {
+        let len = bytes.len() as i32;
+        let ptr: i32 = self.call_or_poison("plugin_alloc", len);
+        if self
+            .memory
+            .write(&mut self.store, ptr as usize, bytes)
+            .is_err()
+        {
+            panic::resume_unwind(Box::new(format!(
+                "wasm plugin {} returned an invalid buffer",
+                self.name
+            )));
+        }
+        (ptr, len)
+    }
+
+    fn read_packed(&self, packed: i64) -> Result<String, String> {
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & 0xffff_ffff) as usize;
+        let mut buffer = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut buffer)
+            .map_err(|err| err.to_string())?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    /// `KEY=VALUE VALUE ...` lines.
+    fn parse_params(text: &str) -> ParamsGet {
+        text.lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, values)| {
+                (
+                    key.trim().to_string(),
+                    values.split_whitespace().map(String::from).collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl PluginInterface for WasmPlugin {
+    fn do_init(&mut self) {
+        self.call_or_poison::<(), ()>("plugin_init", ());
+        self.initialized = true;
+    }
+    fn do_enable(&mut self) {
+        self.call_or_poison::<(), ()>("plugin_enable", ());
+        self.enabled = true;
+    }
+    fn do_dispatch(&mut self, cmd: &str, args: &[&str]) -> bool {
+        let (cmd_ptr, cmd_len) = self.write_bytes(cmd.as_bytes());
+        let (args_ptr, args_len) = self.write_bytes(args.join("\0").as_bytes());
+        let success: i32 =
+            self.call_or_poison("plugin_dispatch", (cmd_ptr, cmd_len, args_ptr, args_len));
+
+        let packed: i64 = self.call_or_poison("plugin_get_data", ());
+        self.data = match self.read_packed(packed) {
+            Ok(text) => PluginValue::Str(text),
+            Err(_) => PluginValue::default(),
+        };
+        success != 0
+    }
+    fn do_cleanup(&mut self) {
+        self.call_or_poison::<(), ()>("plugin_cleanup", ());
+        self.enabled = false;
+        self.initialized = false;
+    }
+    fn set_params(&mut self, params: &ParamsSet) -> bool {
+        // the host decides, a plugin cannot grant itself privileges
+        if let Some(fault_tolerant) = params.get(PARAMS_FAULT_TOLERANT) {
+            if !string_utils::string_to_bool(fault_tolerant, &mut self.fault_tolerant) {
+                println!(
+                    "Invalid value for: {} -> {}",
+                    PARAMS_FAULT_TOLERANT, fault_tolerant
+                );
+                return false;
+            }
+        }
+
+        let text: String = params
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect();
+        let (ptr, len) = self.write_bytes(text.as_bytes());
+        let accepted: i32 = self.call_or_poison("plugin_set_params", (ptr, len));
+        accepted != 0
+    }
+    fn get_params(&self, params: &mut ParamsGet) {
+        *params = self.params.clone();
+    }
+    fn get_data(&self) -> &PluginValue {
+        &self.data
+    }
+    fn reset_data(&mut self) {
+        self.data.clear();
+    }
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    fn is_privileged(&self) -> bool {
+        self.privileged
+    }
+    fn is_fault_tolerant(&self) -> bool {
+        self.fault_tolerant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_api::{make_handle, plugin_do_dispatch, plugin_do_init, DispatchError};
+    use std::env;
+
+    const WASMDEMO: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../plugin_impl/wasmdemo_plugin/wasmdemo_plugin.wat"
+    );
+
+    fn section(settings: &[(&str, &str)]) -> ParamsSet {
+        settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn load(settings: &[(&str, &str)]) -> Result<WasmPlugin, PluginLoadError> {
+        WasmPlugin::load("WASMDEMO", Path::new(WASMDEMO), &section(settings))
+    }
+
+    fn dispatch(plugin: &mut WasmPlugin, cmd: &str, args: &[&str]) -> Option<String> {
+        plugin
+            .do_dispatch(cmd, args)
+            .then(|| plugin.get_data().clone())
+            .map(|data| match data {
+                PluginValue::Str(text) => text,
+                other => panic!("unexpected result {:?}", other),
+            })
+    }
+
+    #[test]
+    fn granted_capabilities_are_usable() {
+        let dir = env::temp_dir().join(format!("wasm_fs_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "some notes").unwrap();
+        let fs_dir = dir.to_string_lossy().into_owned();
+        let mut plugin = load(&[
+            (PARAMS_PRIVILEGED, "TRUE"),
+            (SETTINGS_WASM_CAPABILITIES, "log, time, FS"),
+            (SETTINGS_WASM_FS_DIR, &fs_dir),
+        ])
+        .unwrap();
+
+        let mut params = ParamsGet::new();
+        plugin.get_params(&mut params);
+        assert_eq!(params["cmds"], ["WECHO", "WTIME", "WREAD"]);
+        assert!(plugin.is_privileged());
+
+        plugin.do_init();
+        plugin.do_enable();
+        assert_eq!(
+            dispatch(&mut plugin, "WECHO", &["hello", "world"]).as_deref(),
+            Some("hello world")
+        );
+        let now = dispatch(&mut plugin, "WTIME", &[]).unwrap();
+        assert!(now.parse::<u64>().unwrap() > 0);
+        assert_eq!(
+            dispatch(&mut plugin, "WREAD", &["notes.txt"]).as_deref(),
+            Some("some notes")
+        );
+        assert_eq!(dispatch(&mut plugin, "WREAD", &["../notes.txt"]), None);
+        assert_eq!(dispatch(&mut plugin, "WREAD", &["/etc/hostname"]), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fs_needs_privileged() {
+        assert!(matches!(
+            load(&[(SETTINGS_WASM_CAPABILITIES, "LOG, TIME, FS")]),
+            Err(PluginLoadError::CapabilityDenied {
+                capability: CAPABILITY_FS,
+                ..
+            })
+        ));
+        assert!(matches!(
+            load(&[(SETTINGS_WASM_CAPABILITIES, "LOG, NET")]),
+            Err(PluginLoadError::InvalidHostSettings { value, .. }) if value == "NET"
+        ));
+    }
+
+    #[test]
+    fn fs_needs_an_existing_dir() {
+        let missing = env::temp_dir().join(format!("wasm_no_fs_{}", std::process::id()));
+        let missing = missing.to_string_lossy().into_owned();
+        for fs_dir in [None, Some(missing.as_str())] {
+            let mut settings = vec![
+                (PARAMS_PRIVILEGED, "TRUE"),
+                (SETTINGS_WASM_CAPABILITIES, "LOG, TIME, FS"),
+            ];
+            settings.extend(fs_dir.map(|dir| (SETTINGS_WASM_FS_DIR, dir)));
+            assert!(matches!(
+                load(&settings),
+                Err(PluginLoadError::InvalidHostSettings { key, value, .. })
+                    if key == SETTINGS_WASM_FS_DIR && value == fs_dir.unwrap_or_default()
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_leave_fs_dir() {
+        use std::os::unix::fs::symlink;
+
+        let root = env::temp_dir().join(format!("wasm_symlinks_{}", std::process::id()));
+        let dir = root.join("fs");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        fs::write(dir.join("sub/notes.txt"), "notes").unwrap();
+        symlink(root.join("secret.txt"), dir.join("secret.txt")).unwrap();
+        symlink(&root, dir.join("outside")).unwrap();
+        symlink(root.join("created.txt"), dir.join("dangling.txt")).unwrap();
+        symlink("sub/notes.txt", dir.join("inside.txt")).unwrap();
+
+        let host = WasmHost {
+            name: "WASMDEMO".to_string(),
+            fs_dir: Some(dir.canonicalize().unwrap()),
+        };
+        assert!(host.fs_path("sub/notes.txt").is_some());
+        assert!(host.fs_path("inside.txt").is_some());
+        assert!(host.fs_path("sub/new.txt").is_some());
+        assert_eq!(host.fs_path("secret.txt"), None);
+        assert_eq!(host.fs_path("outside/secret.txt"), None);
+        assert_eq!(host.fs_path("outside/new.txt"), None);
+        assert_eq!(host.fs_path("dangling.txt"), None);
+
+        let fs_dir = dir.to_string_lossy().into_owned();
+        let mut plugin = load(&[
+            (PARAMS_PRIVILEGED, "TRUE"),
+            (SETTINGS_WASM_CAPABILITIES, "LOG, TIME, FS"),
+            (SETTINGS_WASM_FS_DIR, &fs_dir),
+        ])
+        .unwrap();
+        plugin.do_init();
+        plugin.do_enable();
+        assert_eq!(
+            dispatch(&mut plugin, "WREAD", &["inside.txt"]).as_deref(),
+            Some("notes")
+        );
+        assert_eq!(dispatch(&mut plugin, "WREAD", &["secret.txt"]), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn imports_need_granted_capabilities() {
+        // only LOG by default
+        assert!(matches!(
+            load(&[]),
+            Err(PluginLoadError::CapabilityNotGranted {
+                import,
+                capability: CAPABILITY_TIME,
+                ..
+            }) if import == "time_ms"
+        ));
+        assert!(matches!(
+            load(&[
+                (PARAMS_PRIVILEGED, "TRUE"),
+                (SETTINGS_WASM_CAPABILITIES, "TIME, FS")
+            ]),
+            Err(PluginLoadError::CapabilityNotGranted { import, .. }) if import == "log"
+        ));
+    }
+
+    #[test]
+    fn trap_poisons_the_plugin() {
+        let path = env::temp_dir().join(format!("wasm_trap_{}.wat", std::process::id()));
+        fs::write(
+            &path,
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "plugin_abi_version") (result i32) (i32.const 1))
+                (func (export "plugin_alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "plugin_get_params") (result i64) (i64.const 0))
+                (func (export "plugin_init"))
+                (func (export "plugin_dispatch") (param i32 i32 i32 i32) (result i32)
+                  (unreachable)))"#,
+        )
+        .unwrap();
+        let plugin = WasmPlugin::load("TRAP", &path, &ParamsSet::new()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let mut handle = make_handle(plugin);
+        unsafe {
+            assert!(plugin_do_init(&mut handle));
+            let result = plugin_do_dispatch(&mut handle, "ANY", &[]);
+            assert!(
+                matches!(&result, Err(DispatchError::Panicked(message)) if message.contains("trapped in plugin_dispatch")),
+                "{:?}",
+                result
+            );
+            (handle.destroy)(handle.ptr);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Builds the C demo plugin against `include/plugin_api.h` and runs it
+//! through the plugin manager, so the header cannot drift from the Rust
+//! side of the ABI unnoticed. Libraries built for another ABI are refused.
+#![cfg(target_os = "linux")]
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use libloading::Library;
+use plugin_api::{
+    plugin_do_dispatch, plugin_get_data, PluginAbiVersionFn, PluginValue, PLUGIN_ABI_VERSION,
+};
+use plugin_manager::{PluginLoadError, PluginManager};
+
+/// `make OUT=dir` of the demo plugin into a fresh directory.
+fn build_cdemo() -> PathBuf {
+    let out = env::temp_dir().join(format!("cdemo_plugin_test_{}", process::id()));
+    fs::create_dir_all(&out).unwrap();
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../plugin_impl/cdemo_plugin");
+    let status = Command::new("make")
+        .arg("-B")
+        .arg("-C")
+        .arg(&source)
+        .arg(format!("OUT={}", out.display()))
+        .status()
+        .expect("make is needed to build the C demo plugin");
+    assert!(status.success(), "building the C demo plugin failed");
+    out
+}
+
+#[test]
+fn c_plugin_runs_through_the_manager() {
+    let out = build_cdemo();
+    let library_path = out.join("libcdemo_plugin.so");
+
+    let abi_version = unsafe {
+        let library = Library::new(&library_path).unwrap();
+        let abi_version = library
+            .get::<PluginAbiVersionFn>(b"plugin_abi_version")
+            .unwrap();
+        abi_version()
+    };
+    assert_eq!(abi_version, PLUGIN_ABI_VERSION);
+
+    let ini = out.join("settings.ini");
+    fs::write(
+        &ini,
+        "[COMMON]\nFAULT_TOLERANT = FALSE\n\n[CDEMO]\nPRIVILEGED = FALSE\n",
+    )
+    .unwrap();
+    let mut manager = PluginManager::new(vec![out.clone()], &ini);
+    manager
+        .load_plugins(&HashSet::from(["CDEMO".to_string()]))
+        .unwrap();
+    manager.enable_plugins().unwrap();
+    let handle = manager.plugins["CDEMO"].handle;
+
+    let args = ["hello".to_string(), "world".to_string()];
+    unsafe {
+        plugin_do_dispatch(handle, "CECHO", &args).unwrap();
+        assert_eq!(
+            plugin_get_data(handle),
+            PluginValue::Str("hello world".to_string())
+        );
+        plugin_do_dispatch(handle, "CCOUNT", &args).unwrap();
+        assert_eq!(plugin_get_data(handle), PluginValue::Int(2));
+        assert!(plugin_do_dispatch(handle, "CMISSING", &args).is_err());
+    }
+
+    drop(manager);
+    fs::remove_dir_all(out).unwrap();
+}
+
+/// A manager for the single library `lib<name>_plugin.so` built from the C `source`.
+fn manager_for(name: &str, source: &str) -> (PluginManager, PathBuf) {
+    let out = env::temp_dir().join(format!("c_plugin_{}_{}", name, process::id()));
+    fs::create_dir_all(&out).unwrap();
+    let source_path = out.join("plugin.c");
+    fs::write(&source_path, source).unwrap();
+    let status = Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(out.join(format!("lib{}_plugin.so", name)))
+        .arg(&source_path)
+        .status()
+        .expect("cc is needed to build the test plugins");
+    assert!(status.success(), "building the test plugin failed");
+
+    let ini = out.join("settings.ini");
+    fs::write(&ini, "[COMMON]\n").unwrap();
+    (PluginManager::new(vec![out.clone()], &ini), out)
+}
+
+#[test]
+fn other_abi_version_is_refused() {
+    let (mut manager, out) = manager_for(
+        "oldabi",
+        "unsigned int plugin_abi_version(void) { return 1; }\n",
+    );
+    let result = manager.load_plugins(&HashSet::from(["OLDABI".to_string()]));
+    assert!(matches!(
+        result,
+        Err(PluginLoadError::AbiMismatch { found: 1, expected, .. }) if expected == PLUGIN_ABI_VERSION
+    ));
+    assert!(manager.plugins.is_empty());
+
+    drop(manager);
+    fs::remove_dir_all(out).unwrap();
+}
+
+#[test]
+fn unversioned_library_is_refused() {
+    let (mut manager, out) = manager_for("noabi", "int unrelated(void) { return 0; }\n");
+    let result = manager.load_plugins(&HashSet::from(["NOABI".to_string()]));
+    assert!(matches!(
+        result,
+        Err(PluginLoadError::SymbolMissing {
+            symbol: "plugin_abi_version",
+            ..
+        })
+    ));
+
+    drop(manager);
+    fs::remove_dir_all(out).unwrap();
+}
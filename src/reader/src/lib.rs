@@ -1,25 +1,162 @@
-use interfaces::{Item, TokenType};
+use interfaces::{Item, Location, TokenType};
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Maximum nesting of `INCLUDE` directives.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+const INCLUDE_KEYWORD: &str = "INCLUDE";
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReadError {
+    OpenFailed {
+        file: String,
+        source: io::Error,
+    },
+    /// The included file is found neither next to the including file nor
+    /// in the include directories
+    IncludeNotFound {
+        file: String,
+        location: Box<Location>,
+    },
+    /// A file including itself, directly or through other files
+    IncludeCycle {
+        chain: Vec<String>,
+        location: Box<Location>,
+    },
+    IncludeTooDeep {
+        location: Box<Location>,
+    },
+}
+
+impl ReadError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ReadError::OpenFailed { .. } => None,
+            ReadError::IncludeNotFound { location, .. }
+            | ReadError::IncludeCycle { location, .. }
+            | ReadError::IncludeTooDeep { location } => Some(location),
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::OpenFailed { file, source } => {
+                return write!(f, "cannot open script `{}`: {}", file, source)
+            }
+            ReadError::IncludeNotFound { file, .. } => {
+                write!(f, "included file `{}` not found", file)?
+            }
+            ReadError::IncludeCycle { chain, .. } => {
+                write!(f, "include cycle: {}", chain.join(" -> "))?
+            }
+            ReadError::IncludeTooDeep { .. } => write!(
+                f,
+                "includes nested deeper than {} levels",
+                MAX_INCLUDE_DEPTH
+            )?,
+        }
+
+        match self.location() {
+            Some(location) => write!(f, "\n{}", location.snippet()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadError::OpenFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The statement part of `line`, before a `#` comment. A `#` inside double
+/// quotes or escaped as `\#` does not start a comment.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (offset, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return line[..offset].trim_end(),
+            _ => {}
+        }
+    }
+    line
+}
 
 pub struct ScriptReader {
-    scriptpathname: &'static str,
+    scriptpathname: PathBuf,
+    include_dirs: Vec<PathBuf>,
 }
 
 impl ScriptReader {
-    pub fn new(scriptpathname: &'static str) -> Self {
-        ScriptReader { scriptpathname }
+    pub fn new(scriptpathname: impl Into<PathBuf>) -> Self {
+        ScriptReader {
+            scriptpathname: scriptpathname.into(),
+            include_dirs: Vec::new(),
+        }
     }
 
-    pub fn read_script(&self, output: &mut Vec<Item>) -> Result<usize, Box<dyn Error>> {
-        println!("Reading script: {}", self.scriptpathname);
-        let file = File::open(self.scriptpathname)?;
+    /// Directory searched, in order of addition, for included files not
+    /// found next to the including file.
+    pub fn add_include_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.include_dirs.push(dir.into());
+    }
+
+    /// The path of `INCLUDE file` in `including`: relative to the including
+    /// file first, then to the include directories.
+    fn resolve_include(&self, file: &str, including: &Path) -> Option<PathBuf> {
+        let file = Path::new(file);
+        if file.is_absolute() {
+            return Some(file.to_path_buf()).filter(|path| path.is_file());
+        }
+        let including_dir = including.parent().unwrap_or(Path::new(""));
+        std::iter::once(including_dir)
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(file))
+            .find(|path| path.is_file())
+    }
+
+    pub fn read_script(&self, output: &mut Vec<Item>) -> Result<usize, ReadError> {
+        println!("Reading script: {}", self.scriptpathname.display());
+        let mut including: Vec<PathBuf> = Vec::new();
+        self.read_file(&self.scriptpathname, None, &mut including, output)?;
+        Ok(output.len())
+    }
+
+    /// Read `path` into `output`, `including` is the chain of files being
+    /// read, `included_from` the `INCLUDE` statement that named `path`.
+    fn read_file(
+        &self,
+        path: &Path,
+        included_from: Option<&Location>,
+        including: &mut Vec<PathBuf>,
+        output: &mut Vec<Item>,
+    ) -> Result<(), ReadError> {
+        let filename = path.display().to_string();
+        let file = File::open(path).map_err(|source| ReadError::OpenFailed {
+            file: filename.clone(),
+            source,
+        })?;
         let reader = BufReader::new(file);
+        including.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
 
         let mut in_block_comment = false;
 
-        for line in reader.lines().map_while(Result::ok) {
+        for (index, line) in reader.lines().map_while(Result::ok).enumerate() {
             let trimmed = line.trim();
 
             if in_block_comment {
@@ -37,18 +174,190 @@ impl ScriptReader {
                 continue;
             }
 
-            let valid_line = trimmed.to_string();
-            // Split only once on '#' to remove the comment at the end of line
-            let left = valid_line
-                .split_once('#')
-                .map(|(left, _)| left.trim()) // take the left part, trim again if needed
-                .unwrap_or(&valid_line);
+            // remove the comment at the end of line
+            let left = strip_comment(trimmed);
+
+            // byte offset of the statement in the original line
+            let column = line.len() - line.trim_start().len() + 1;
+
+            let location = Location {
+                file: filename.clone(),
+                line: index + 1,
+                column,
+                end_column: column + left.len(),
+                source: line.clone(),
+                included_from: included_from.cloned().map(Box::new),
+            };
+
+            if let Some(target) = left
+                .strip_prefix(INCLUDE_KEYWORD)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+            {
+                self.read_include(target.trim().trim_matches('"'), location, including, output)?;
+                continue;
+            }
 
             output.push(Item {
                 line: left.to_string(),
                 token_type: TokenType::None,
+                location,
             });
         }
-        Ok(output.len())
+
+        including.pop();
+        Ok(())
+    }
+
+    fn read_include(
+        &self,
+        target: &str,
+        location: Location,
+        including: &mut Vec<PathBuf>,
+        output: &mut Vec<Item>,
+    ) -> Result<(), ReadError> {
+        let location = Box::new(location.span_of(target));
+        if including.len() > MAX_INCLUDE_DEPTH {
+            return Err(ReadError::IncludeTooDeep { location });
+        }
+
+        let current = including.last().map_or(Path::new(""), PathBuf::as_path);
+        let Some(path) = self.resolve_include(target, current) else {
+            return Err(ReadError::IncludeNotFound {
+                file: target.to_string(),
+                location,
+            });
+        };
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Some(first) = including.iter().position(|file| *file == canonical) {
+            let mut chain: Vec<String> = including[first..]
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect();
+            chain.push(canonical.display().to_string());
+            return Err(ReadError::IncludeCycle { chain, location });
+        }
+
+        println!("Including script: {}", path.display());
+        self.read_file(&path, Some(&*location), including, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// An empty directory for the files of test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("reader_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> Result<Vec<Item>, ReadError> {
+        let mut items = Vec::new();
+        ScriptReader::new(path).read_script(&mut items)?;
+        Ok(items)
+    }
+
+    #[test]
+    fn items_locate_their_statement() {
+        let dir = test_dir("locations");
+        let script = dir.join("script.txt");
+        fs::write(
+            &script,
+            "# header\n\n    X = 1   # set X\n---\nignored\n!--\nY = 2\n",
+        )
+        .unwrap();
+
+        let items = read(&script).unwrap();
+        let lines: Vec<_> = items.iter().map(|item| item.line.as_str()).collect();
+        assert_eq!(lines, ["X = 1", "Y = 2"]);
+
+        let location = &items[0].location;
+        assert_eq!(location.file, script.display().to_string());
+        assert_eq!(
+            (location.line, location.column, location.end_column),
+            (3, 5, 10)
+        );
+        assert_eq!(location.source, "    X = 1   # set X");
+        assert_eq!((items[1].location.line, items[1].location.column), (7, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_script_has_no_location() {
+        let dir = test_dir("missing");
+        let err = read(&dir.join("missing.txt")).unwrap_err();
+        assert!(matches!(err, ReadError::OpenFailed { .. }));
+        assert!(err.location().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn comments_start_outside_quotes_and_escapes() {
+        assert_eq!(strip_comment("X = 1 # comment"), "X = 1");
+        assert_eq!(
+            strip_comment(r#"UTILS.UPRINT "a # b" # comment"#),
+            r#"UTILS.UPRINT "a # b""#
+        );
+        assert_eq!(
+            strip_comment(r"UTILS.UPRINT \# not a comment"),
+            r"UTILS.UPRINT \# not a comment"
+        );
+    }
+
+    #[test]
+    fn include_inserts_the_file_in_place() {
+        let dir = test_dir("include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.txt"), "A = 1\nINCLUDE \"sub.txt\"\nD = 4\n").unwrap();
+        fs::write(dir.join("sub.txt"), "B = 2\nINCLUDE common.txt\n").unwrap();
+        fs::write(dir.join("lib/common.txt"), "C = 3\n").unwrap();
+
+        let mut items = Vec::new();
+        let mut reader = ScriptReader::new(dir.join("main.txt"));
+        reader.add_include_dir(dir.join("lib"));
+        reader.read_script(&mut items).unwrap();
+
+        let lines: Vec<_> = items.iter().map(|item| item.line.as_str()).collect();
+        assert_eq!(lines, ["A = 1", "B = 2", "C = 3", "D = 4"]);
+        let common = &items[2].location;
+        assert!(common.file.ends_with("common.txt"));
+        let from_sub = common.included_from.as_deref().unwrap();
+        assert_eq!((from_sub.line, from_sub.column), (2, 9));
+        let from_main = from_sub.included_from.as_deref().unwrap();
+        assert_eq!((from_main.line, from_main.column), (2, 10));
+        assert!(from_main.included_from.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_include_is_located() {
+        let dir = test_dir("include_missing");
+        fs::write(dir.join("main.txt"), "INCLUDE missing.txt\n").unwrap();
+        let err = read(&dir.join("main.txt")).unwrap_err();
+        assert!(matches!(&err, ReadError::IncludeNotFound { file, .. } if file == "missing.txt"));
+        assert_eq!(err.location().map(|location| location.column), Some(9));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle_is_rejected() {
+        let dir = test_dir("include_cycle");
+        fs::write(dir.join("a.txt"), "INCLUDE b.txt\n").unwrap();
+        fs::write(dir.join("b.txt"), "INCLUDE a.txt\n").unwrap();
+        let err = read(&dir.join("a.txt")).unwrap_err();
+        let ReadError::IncludeCycle { chain, .. } = err else {
+            panic!("unexpected {:?}", err);
+        };
+        let files: Vec<_> = chain
+            .iter()
+            .map(|file| Path::new(file).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(files, ["a.txt", "b.txt", "a.txt"]);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,685 @@
+//! Boolean and comparison expressions used by `IF`, `WHILE` and `NAME = expr`.
+//!
+//! ```text
+//! expr    := and ( "||" and )*
+//! and     := not ( "&&" not )*
+//! not     := "!" not | cmp
+//! cmp     := primary ( ( "==" | "!=" | "<" | "<=" | ">" | ">=" | "EQ" | "NE" ) primary )?
+//! primary := "(" expr ")" | "quoted string" | "[" list "]" | $MACRO access? | ${MACRO} | word
+//! access  := "[" ( index | $MACRO ) "]" | ".len"
+//! ```
+//!
+//! Words and list elements are typed with [`Value::from_text`], quoted strings
+//! are always strings. Macro text (constants, plugin results) is kept as
+//! written and only typed where an operand needs a type: `007` is the integer
+//! 7 in `$ID == 7` or `$ID < 10`, but compares as text with `"007"`.
+//! Integers and floats compare numerically.
+//! `EQ`/`NE` compare the textual form of both operands, ignoring case.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+
+use utils::string_utils;
+
+use utils::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum EvalError {
+    Syntax(String),
+    Type(String),
+    UndefinedMacro(String),
+    IndexOutOfRange {
+        index: usize,
+        len: usize,
+    },
+    /// A condition that is empty or evaluates to an empty string
+    EmptyCondition,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Syntax(message) => write!(f, "syntax error: {}", message),
+            EvalError::Type(message) => write!(f, "type error: {}", message),
+            EvalError::UndefinedMacro(name) => write!(f, "undefined macro `${}`", name),
+            EvalError::IndexOutOfRange { index, len } => {
+                write!(
+                    f,
+                    "index {} out of range for a list of {} element(s)",
+                    index, len
+                )
+            }
+            EvalError::EmptyCondition => write!(f, "empty condition"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    EqIgnoreCase,
+    NeIgnoreCase,
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+            CmpOp::EqIgnoreCase => "EQ",
+            CmpOp::NeIgnoreCase => "NE",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// List access following `$MACRO`
+#[derive(Debug, Clone, PartialEq)]
+enum Access {
+    Index(usize),
+    IndexMacro(String),
+    Len,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Index(index) => write!(f, "[{}]", index),
+            Access::IndexMacro(name) => write!(f, "[${}]", name),
+            Access::Len => write!(f, ".len"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    Cmp(CmpOp),
+    Literal(Value),
+    Macro(String, Option<Access>),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Cmp(op) => write!(f, "`{}`", op),
+            Token::Literal(value) => write!(f, "`{}`", value),
+            Token::Macro(name, None) => write!(f, "`${}`", name),
+            Token::Macro(name, Some(access)) => write!(f, "`${}{}`", name, access),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Macro(String, Option<Access>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"()!=<>&|\"".contains(c)
+}
+
+fn identifier(chars: &[char]) -> String {
+    chars
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .collect()
+}
+
+/// The list access starting at `chars`, if any, and its width.
+fn access(chars: &[char]) -> Result<Option<(Access, usize)>, EvalError> {
+    match chars.first() {
+        Some('[') => {
+            let Some(close) = chars.iter().position(|&c| c == ']') else {
+                return Err(EvalError::Syntax(
+                    "missing `]` after list index".to_string(),
+                ));
+            };
+            let index: String = chars[1..close].iter().collect();
+            let access =
+                match index.trim().strip_prefix('$') {
+                    Some(name)
+                        if !name.is_empty()
+                            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                    {
+                        Access::IndexMacro(name.to_string())
+                    }
+                    _ => Access::Index(index.trim().parse().map_err(|_| {
+                        EvalError::Syntax(format!("invalid list index `{}`", index))
+                    })?),
+                };
+            Ok(Some((access, close + 1)))
+        }
+        Some('.') if identifier(&chars[1..]) == "len" => Ok(Some((Access::Len, 4))),
+        _ => Ok(None),
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, EvalError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let next = chars.get(pos + 1).copied();
+
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                pos += 1;
+                continue;
+            }
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Cmp(CmpOp::Eq), 2),
+            ('!', Some('=')) => (Token::Cmp(CmpOp::Ne), 2),
+            ('<', Some('=')) => (Token::Cmp(CmpOp::Le), 2),
+            ('>', Some('=')) => (Token::Cmp(CmpOp::Ge), 2),
+            ('<', _) => (Token::Cmp(CmpOp::Lt), 1),
+            ('>', _) => (Token::Cmp(CmpOp::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+
+            ('"', _) => {
+                let mut text = String::new();
+                let mut end = pos + 1;
+                loop {
+                    match chars.get(end) {
+                        None => return Err(EvalError::Syntax("unterminated string".to_string())),
+                        Some('"') => break,
+                        Some('\\') if end + 1 < chars.len() => {
+                            text.push(chars[end + 1]);
+                            end += 2;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            end += 1;
+                        }
+                    }
+                }
+                (Token::Literal(Value::Str(text)), end + 1 - pos)
+            }
+
+            ('[', _) => {
+                // a quoted element may contain `]`
+                let mut quoted = false;
+                let mut escaped = false;
+                let close = chars[pos..].iter().position(|&c| {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' if quoted => escaped = true,
+                        '"' => quoted = !quoted,
+                        ']' if !quoted => return true,
+                        _ => {}
+                    }
+                    false
+                });
+                let Some(close) = close else {
+                    return Err(EvalError::Syntax("missing `]` after list".to_string()));
+                };
+                let text: String = chars[pos..=pos + close].iter().collect();
+                let list = string_utils::parse_list(&text)
+                    .ok_or_else(|| EvalError::Syntax(format!("invalid list `{}`", text)))?;
+                let elements = list.iter().map(|element| Value::from_text(element));
+                (Token::Literal(Value::List(elements.collect())), close + 1)
+            }
+
+            ('$', next) => {
+                let braced = next == Some('{');
+                let start = pos + 1 + usize::from(braced);
+                let name = identifier(&chars[start..]);
+                if name.is_empty() {
+                    return Err(EvalError::Syntax("`$` without macro name".to_string()));
+                }
+                let mut width = start - pos + name.len();
+                if braced {
+                    if chars.get(pos + width) != Some(&'}') {
+                        return Err(EvalError::Syntax(format!(
+                            "missing `}}` after `${{{}`",
+                            name
+                        )));
+                    }
+                    width += 1;
+                    (Token::Macro(name, None), width)
+                } else {
+                    match access(&chars[pos + width..])? {
+                        Some((access, access_width)) => {
+                            (Token::Macro(name, Some(access)), width + access_width)
+                        }
+                        None => (Token::Macro(name, None), width),
+                    }
+                }
+            }
+
+            (c, _) if is_word_char(c) => {
+                let word: String = chars[pos..]
+                    .iter()
+                    .take_while(|c| is_word_char(**c))
+                    .collect();
+                let token = if word.eq_ignore_ascii_case("EQ") {
+                    Token::Cmp(CmpOp::EqIgnoreCase)
+                } else if word.eq_ignore_ascii_case("NE") {
+                    Token::Cmp(CmpOp::NeIgnoreCase)
+                } else {
+                    Token::Literal(Value::from_text(&word))
+                };
+                (token, word.chars().count())
+            }
+
+            (c, _) => return Err(EvalError::Syntax(format!("unexpected `{}`", c))),
+        };
+
+        tokens.push(token);
+        pos += width;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, EvalError> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, EvalError> {
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, EvalError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr, EvalError> {
+        let left = self.parse_primary()?;
+        if let Some(&Token::Cmp(op)) = self.peek() {
+            self.pos += 1;
+            let right = self.parse_primary()?;
+            return Ok(Expr::Cmp(op, Box::new(left), Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, EvalError> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => Err(EvalError::Syntax("missing `)`".to_string())),
+                }
+            }
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Macro(name, access)) => Ok(Expr::Macro(name, access)),
+            Some(token) => Err(EvalError::Syntax(format!("unexpected {}", token))),
+            None => Err(EvalError::Syntax(
+                "unexpected end of expression".to_string(),
+            )),
+        }
+    }
+}
+
+fn as_bool(value: Value) -> Result<bool, EvalError> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        other => Err(EvalError::Type(format!(
+            "expected boolean, got {} `{}`",
+            other.type_name(),
+            other
+        ))),
+    }
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(int) => *int as f64,
+        Value::Float(float) => *float,
+        _ => f64::NAN,
+    }
+}
+
+fn compare(op: CmpOp, left: &Value, right: &Value) -> Result<bool, EvalError> {
+    let ordering = match (op, left, right) {
+        (CmpOp::EqIgnoreCase | CmpOp::NeIgnoreCase, _, _) => {
+            let equal = left.to_string().to_lowercase() == right.to_string().to_lowercase();
+            return Ok(equal == (op == CmpOp::EqIgnoreCase));
+        }
+        (_, Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (_, Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            match as_float(left).partial_cmp(&as_float(right)) {
+                Some(ordering) => ordering,
+                // NaN is neither equal nor ordered
+                None => return Ok(op == CmpOp::Ne),
+            }
+        }
+        (_, Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (CmpOp::Eq | CmpOp::Ne, Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (CmpOp::Eq | CmpOp::Ne, Value::List(a), Value::List(b)) => {
+            return Ok((a == b) == (op == CmpOp::Eq))
+        }
+        (_, Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+        _ => {
+            return Err(EvalError::Type(format!(
+                "cannot compare {} `{}` with {} `{}` using `{}`",
+                left.type_name(),
+                left,
+                right.type_name(),
+                right,
+                op
+            )))
+        }
+    };
+
+    Ok(match op {
+        CmpOp::Eq => ordering == Ordering::Equal,
+        CmpOp::Ne => ordering != Ordering::Equal,
+        CmpOp::Lt => ordering == Ordering::Less,
+        CmpOp::Le => ordering != Ordering::Greater,
+        CmpOp::Gt => ordering == Ordering::Greater,
+        CmpOp::Ge => ordering != Ordering::Less,
+        CmpOp::EqIgnoreCase | CmpOp::NeIgnoreCase => unreachable!("handled above"),
+    })
+}
+
+fn lookup_macro(name: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, EvalError> {
+    lookup(name).ok_or_else(|| EvalError::UndefinedMacro(name.to_string()))
+}
+
+/// The value of `expr` where a type is needed, macro text typed like a word.
+fn eval_typed(expr: &Expr, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, EvalError> {
+    Ok(match (expr, eval(expr, lookup)?) {
+        (Expr::Macro(..), Value::Str(text)) => Value::from_text(&text),
+        (_, value) => value,
+    })
+}
+
+/// Apply `access` to a list or bytes `value`, the length of any other value
+/// is the character count of its text. The elements of list text stay text.
+fn select(
+    value: Value,
+    access: &Access,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value, EvalError> {
+    let value = match value {
+        Value::Str(text) => match string_utils::parse_list(&text) {
+            Some(elements) => Value::List(elements.into_iter().map(Value::Str).collect()),
+            None => Value::Str(text),
+        },
+        value => value,
+    };
+    let index = match access {
+        Access::Len => {
+            let len = match &value {
+                Value::List(elements) => elements.len(),
+                Value::Bytes(bytes) => bytes.len(),
+                other => other.to_string().chars().count(),
+            };
+            return Ok(Value::Int(len as i64));
+        }
+        Access::Index(index) => *index,
+        Access::IndexMacro(name) => match eval_typed(&Expr::Macro(name.clone(), None), lookup)? {
+            Value::Int(index) if index >= 0 => index as usize,
+            other => {
+                return Err(EvalError::Type(format!(
+                    "list index must be a non-negative integer, got {} `{}`",
+                    other.type_name(),
+                    other
+                )))
+            }
+        },
+    };
+
+    match value {
+        Value::List(elements) => {
+            let len = elements.len();
+            elements
+                .into_iter()
+                .nth(index)
+                .ok_or(EvalError::IndexOutOfRange { index, len })
+        }
+        Value::Bytes(bytes) => bytes.get(index).map(|&byte| Value::Int(byte.into())).ok_or(
+            EvalError::IndexOutOfRange {
+                index,
+                len: bytes.len(),
+            },
+        ),
+        other => Err(EvalError::Type(format!(
+            "expected list or bytes, got {} `{}`",
+            other.type_name(),
+            other
+        ))),
+    }
+}
+
+fn eval(expr: &Expr, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, EvalError> {
+    Ok(match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Macro(name, None) => lookup_macro(name, lookup)?,
+        Expr::Macro(name, Some(access)) => select(lookup_macro(name, lookup)?, access, lookup)?,
+        Expr::Not(inner) => Value::Bool(!as_bool(eval_typed(inner, lookup)?)?),
+        // && and || short-circuit, the right side is only checked when evaluated
+        Expr::And(left, right) => {
+            Value::Bool(as_bool(eval_typed(left, lookup)?)? && as_bool(eval_typed(right, lookup)?)?)
+        }
+        Expr::Or(left, right) => {
+            Value::Bool(as_bool(eval_typed(left, lookup)?)? || as_bool(eval_typed(right, lookup)?)?)
+        }
+        Expr::Cmp(op, left, right) => {
+            // compared with a string literal, macro text compares as text
+            let operand = |expr: &Expr, other: &Expr| match other {
+                Expr::Literal(Value::Str(_)) => eval(expr, lookup),
+                _ => eval_typed(expr, lookup),
+            };
+            Value::Bool(compare(
+                *op,
+                &operand(left, right)?,
+                &operand(right, left)?,
+            )?)
+        }
+    })
+}
+
+/// Evaluate `expression`, resolving `$NAME` references through `lookup`.
+pub fn evaluate(
+    expression: &str,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value, EvalError> {
+    eval(&parse(expression)?, lookup)
+}
+
+fn parse(expression: &str) -> Result<Expr, EvalError> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+    };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(EvalError::Syntax(format!("unexpected {}", token)));
+    }
+    Ok(expr)
+}
+
+/// Evaluate `expression` as a condition, which must produce a boolean.
+pub fn evaluate_condition(
+    expression: &str,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<bool, EvalError> {
+    if expression.trim().is_empty() {
+        return Err(EvalError::EmptyCondition);
+    }
+    match eval_typed(&parse(expression)?, lookup)? {
+        Value::Str(text) if text.trim().is_empty() => Err(EvalError::EmptyCondition),
+        value => as_bool(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<Value> {
+        match name {
+            "INT" => Some(Value::Int(10)),
+            "FLOAT" => Some(Value::Float(2.5)),
+            "TEXT" => Some(Value::Str("hello world".to_string())),
+            "FLAG" => Some(Value::Bool(true)),
+            _ => None,
+        }
+    }
+
+    fn eval(expression: &str) -> Result<Value, EvalError> {
+        evaluate(expression, &lookup)
+    }
+
+    fn is_true(expression: &str) -> bool {
+        evaluate_condition(expression, &lookup).unwrap()
+    }
+
+    #[test]
+    fn numbers_compare_numerically() {
+        assert!(is_true("$INT > 9"));
+        assert!(is_true("$INT == 10.0"));
+        assert!(is_true("$FLOAT < $INT"));
+        assert!(is_true("2 <= 10"));
+        assert!(!is_true("-1 >= 0"));
+    }
+
+    #[test]
+    fn strings_compare_by_text() {
+        assert!(is_true(r#"$TEXT == "hello world""#));
+        assert!(is_true(r#""abc" < "abd""#));
+        assert!(is_true(r#"$TEXT EQ "HELLO WORLD""#));
+        assert!(is_true("yes NE no"));
+    }
+
+    #[test]
+    fn logic_operators_and_precedence() {
+        assert!(is_true("1 == 2 || 1 == 1 && 2 == 2"));
+        assert!(!is_true("(1 == 2 || 1 == 1) && 2 == 3"));
+        assert!(is_true("!(1 == 2) && $FLAG"));
+        assert!(is_true("!!$FLAG"));
+    }
+
+    #[test]
+    fn right_operand_is_only_checked_when_evaluated() {
+        assert!(!is_true("1 == 2 && $MISSING"));
+        assert!(is_true("$FLAG || $MISSING"));
+        assert_eq!(
+            eval("$FLAG && $MISSING"),
+            Err(EvalError::UndefinedMacro("MISSING".to_string()))
+        );
+    }
+
+    #[test]
+    fn assignments_evaluate_to_typed_values() {
+        assert_eq!(eval("42"), Ok(Value::Int(42)));
+        assert_eq!(eval("$FLOAT"), Ok(Value::Float(2.5)));
+        assert_eq!(eval("${TEXT}"), Ok(Value::Str("hello world".to_string())));
+        assert_eq!(eval("$INT != 10"), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        assert!(matches!(eval("(1 == 1"), Err(EvalError::Syntax(_))));
+        assert!(matches!(eval("1 == 1 2"), Err(EvalError::Syntax(_))));
+        assert!(matches!(eval(r#""open"#), Err(EvalError::Syntax(_))));
+        assert!(matches!(eval("${TEXT"), Err(EvalError::Syntax(_))));
+        assert!(matches!(eval("1 == "), Err(EvalError::Syntax(_))));
+        assert!(matches!(eval("$TEXT < 3"), Err(EvalError::Type(_))));
+        assert!(matches!(eval("!$INT"), Err(EvalError::Type(_))));
+        assert!(matches!(
+            evaluate_condition("$INT", &lookup),
+            Err(EvalError::Type(_))
+        ));
+    }
+
+    #[test]
+    fn empty_conditions_are_rejected() {
+        let empty = |name: &str| (name == "EMPTY").then(|| Value::Str(" ".to_string()));
+        assert_eq!(
+            evaluate_condition("  ", &empty),
+            Err(EvalError::EmptyCondition)
+        );
+        assert_eq!(
+            evaluate_condition("$EMPTY", &empty),
+            Err(EvalError::EmptyCondition)
+        );
+    }
+
+    #[test]
+    fn list_access() {
+        let lists = |name: &str| match name {
+            "L" => Some(Value::from_text("[1, two, 3.5]")),
+            "I" => Some(Value::Int(1)),
+            "B" => Some(Value::Bytes(vec![7, 8])),
+            _ => None,
+        };
+        let eval = |expression: &str| evaluate(expression, &lists);
+        assert_eq!(eval("$L[0]"), Ok(Value::Int(1)));
+        assert_eq!(eval("$L[$I]"), Ok(Value::Str("two".to_string())));
+        assert_eq!(eval("$L.len"), Ok(Value::Int(3)));
+        assert_eq!(eval("$B[1]"), Ok(Value::Int(8)));
+        assert_eq!(eval("$L == [1, two, 3.5]"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval("$L[3]"),
+            Err(EvalError::IndexOutOfRange { index: 3, len: 3 })
+        );
+        assert!(matches!(eval("$I[0]"), Err(EvalError::Type(_))));
+    }
+}
@@ -1,92 +1,450 @@
-use interfaces::{Item, TokenType};
-use plugin_api::{plugin_do_dispatch, plugin_get_data, PluginHandle};
-use plugin_manager::PluginManager;
-use std::collections::HashMap;
+use interfaces::{Item, Location, TokenType, UndefinedMacros};
+use plugin_api::{plugin_do_dispatch, plugin_get_data, DispatchError, PluginHandle, PluginValue};
+use plugin_manager::{PluginLoadError, PluginManager};
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+use utils::control_flow::{BlockTable, ControlFlow};
 use utils::string_utils;
+use utils::symbols::{ScopeKind, SymbolError, SymbolTable};
+
+mod expr;
+mod limits;
+
+pub use expr::EvalError;
+pub use limits::{
+    RunLimits, Watchdog, DEFAULT_MAX_CALL_DEPTH, SETTINGS_LABEL_LIMITS, SETTINGS_MAX_CALL_DEPTH,
+    SETTINGS_MAX_LABEL_ITERATIONS, SETTINGS_MAX_STATEMENTS, SETTINGS_TIMEOUT,
+};
+pub use utils::value::Value;
 
 #[derive(Debug)]
-enum RunError {
-    ErrorExecutingCommand,
-    PluginNotFound,
+#[non_exhaustive]
+pub enum RunError {
+    ErrorExecutingCommand {
+        plugin: String,
+        command: String,
+        args: String,
+        location: Box<Location>,
+    },
+    PluginNotFound {
+        plugin: String,
+        location: Box<Location>,
+    },
+    /// The plugin panicked while executing the command, it is poisoned
+    PluginPanicked {
+        plugin: String,
+        command: String,
+        message: String,
+        location: Box<Location>,
+    },
+    /// A command refused by a plugin poisoned by an earlier panic
+    PluginPoisoned {
+        plugin: String,
+        command: String,
+        message: String,
+        location: Box<Location>,
+    },
+    /// Arguments that cannot be split, a macro value may have added a quote
+    InvalidArguments {
+        args: String,
+        location: Box<Location>,
+    },
+    LabelNotFound {
+        label: String,
+        location: Box<Location>,
+    },
+    FunctionNotFound {
+        function: String,
+        location: Box<Location>,
+    },
+    /// A CALL whose arguments, once the macros are substituted, do not
+    /// match the FUNCTION parameters
+    FunctionArity {
+        function: String,
+        expected: usize,
+        found: usize,
+        location: Box<Location>,
+    },
+    /// A block statement without its matching statements, see the validator
+    UnbalancedBlock { location: Box<Location> },
+    InvalidRepeatCount {
+        count: String,
+        location: Box<Location>,
+    },
+    /// Syntax, type or undefined macro error in a condition or assignment
+    InvalidExpression {
+        expression: String,
+        error: EvalError,
+        location: Box<Location>,
+    },
+    /// A `$NAME` reference to a macro that is not defined, in strict mode
+    UndefinedMacro {
+        name: String,
+        location: Box<Location>,
+    },
+    /// `$NAME[index]` on a value that is not a list or out of its range, in
+    /// strict mode
+    InvalidListAccess {
+        reference: String,
+        location: Box<Location>,
+    },
+    /// Assigning, declaring or unsetting a constant macro
+    ConstantModified {
+        name: String,
+        location: Box<Location>,
+    },
+    /// A plugin refused to be enabled before the script runs
+    PluginEnablingFailed { source: PluginLoadError },
+    /// A watchdog limit stopped the script, `label` is the last label executed
+    WatchdogExpired {
+        watchdog: Watchdog,
+        label: Option<String>,
+        location: Box<Location>,
+    },
+}
+
+impl RunError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            RunError::ErrorExecutingCommand { location, .. }
+            | RunError::PluginNotFound { location, .. }
+            | RunError::PluginPanicked { location, .. }
+            | RunError::PluginPoisoned { location, .. }
+            | RunError::InvalidArguments { location, .. }
+            | RunError::LabelNotFound { location, .. }
+            | RunError::FunctionNotFound { location, .. }
+            | RunError::FunctionArity { location, .. }
+            | RunError::UnbalancedBlock { location }
+            | RunError::InvalidRepeatCount { location, .. }
+            | RunError::InvalidExpression { location, .. }
+            | RunError::UndefinedMacro { location, .. }
+            | RunError::InvalidListAccess { location, .. }
+            | RunError::ConstantModified { location, .. }
+            | RunError::WatchdogExpired { location, .. } => Some(location),
+            RunError::PluginEnablingFailed { .. } => None,
+        }
+    }
 }
 
 impl fmt::Display for RunError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RunError::ErrorExecutingCommand {
+                plugin,
+                command,
+                args,
+                ..
+            } => write!(f, "failed executing {}.{} {}", plugin, command, args)?,
+            RunError::PluginNotFound { plugin, .. } => {
+                write!(f, "plugin `{}` is not loaded", plugin)?
+            }
+            RunError::PluginPanicked {
+                plugin,
+                command,
+                message,
+                ..
+            } => write!(
+                f,
+                "plugin `{}` panicked in {}.{}: {}",
+                plugin, plugin, command, message
+            )?,
+            RunError::PluginPoisoned {
+                plugin,
+                command,
+                message,
+                ..
+            } => write!(
+                f,
+                "{}.{} refused, plugin `{}` is poisoned by an earlier panic: {}",
+                plugin, command, plugin, message
+            )?,
+            RunError::InvalidArguments { args, .. } => {
+                write!(f, "unterminated quoted string in `{}`", args)?
+            }
+            RunError::LabelNotFound { label, .. } => write!(f, "label `{}` is not defined", label)?,
+            RunError::FunctionNotFound { function, .. } => {
+                write!(f, "function `{}` is not defined", function)?
+            }
+            RunError::FunctionArity {
+                function,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "function `{}` expects {} argument(s), got {}",
+                function, expected, found
+            )?,
+            RunError::UnbalancedBlock { .. } => write!(f, "unbalanced block statement")?,
+            RunError::InvalidRepeatCount { count, .. } => {
+                write!(f, "invalid REPEAT count `{}`", count)?
+            }
+            RunError::InvalidExpression {
+                expression, error, ..
+            } => write!(f, "{} in `{}`", error, expression)?,
+            RunError::UndefinedMacro { name, .. } => write!(f, "macro `${}` is not defined", name)?,
+            RunError::InvalidListAccess { reference, .. } => {
+                write!(f, "`{}` does not select a list element", reference)?
+            }
+            RunError::ConstantModified { name, .. } => {
+                write!(f, "constant `{}` cannot be modified", name)?
+            }
+            RunError::PluginEnablingFailed { source } => write!(f, "{}", source)?,
+            RunError::WatchdogExpired {
+                watchdog, label, ..
+            } => match label {
+                Some(label) => write!(f, "watchdog: {} (looping on label `{}`)", watchdog, label)?,
+                None => write!(f, "watchdog: {}", watchdog)?,
+            },
+        }
+
+        match self.location() {
+            Some(location) => write!(f, "\n{}", location.snippet()),
+            None => Ok(()),
+        }
     }
 }
 
-impl Error for RunError {}
+impl Error for RunError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RunError::PluginEnablingFailed { source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// [COMMON] settings key for [`UndefinedCondition`]
+pub const SETTINGS_UNDEFINED_CONDITION: &str = "UNDEFINED_CONDITION";
+
+/// How a condition that is empty or references an undefined macro is handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UndefinedCondition {
+    /// Abort the script with [`RunError::InvalidExpression`]
+    #[default]
+    Error,
+    /// Evaluate the condition as false
+    False,
+}
+
+impl FromStr for UndefinedCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "ERROR" => Ok(UndefinedCondition::Error),
+            "FALSE" => Ok(UndefinedCondition::False),
+            _ => Err(format!(
+                "Invalid value for: {} -> {}",
+                SETTINGS_UNDEFINED_CONDITION, s
+            )),
+        }
+    }
+}
+
+/// A CALL in progress, undone when the function returns.
+struct CallFrame {
+    /// Statement to continue with after the function returns
+    return_to: usize,
+    vmacro: Option<String>,
+    /// REPEAT counters of the caller, recursive calls run the same blocks
+    repeat_counters: HashMap<usize, u64>,
+    /// remaining FOREACH elements of the caller
+    foreach_elements: HashMap<usize, VecDeque<Value>>,
+}
 
 pub struct ScriptRunner {
-    macros: HashMap<String, String>,
+    symbols: SymbolTable,
+    limits: RunLimits,
+    undefined_condition: UndefinedCondition,
+    undefined_macros: UndefinedMacros,
 }
 
 impl ScriptRunner {
     pub fn new() -> Self {
         ScriptRunner {
-            macros: HashMap::new(),
+            symbols: SymbolTable::new(),
+            limits: RunLimits::default(),
+            undefined_condition: UndefinedCondition::default(),
+            undefined_macros: UndefinedMacros::default(),
         }
     }
 
+    /// Start from `symbols`, usually the constants collected by the parser.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn set_undefined_condition(&mut self, undefined_condition: UndefinedCondition) {
+        self.undefined_condition = undefined_condition;
+    }
+
+    pub fn set_undefined_macros(&mut self, undefined_macros: UndefinedMacros) {
+        self.undefined_macros = undefined_macros;
+    }
+
+    pub fn set_limits(&mut self, limits: RunLimits) {
+        self.limits = limits;
+    }
+
+    /// Check the watchdog limits before executing `item`, the statement number `executed`.
+    fn check_limits(
+        &self,
+        item: &Item,
+        executed: u64,
+        started: &Instant,
+        last_label: Option<&str>,
+    ) -> Result<(), RunError> {
+        let watchdog = match (self.limits.max_statements, self.limits.timeout) {
+            (Some(limit), _) if executed > limit => Watchdog::Statements(limit),
+            (_, Some(limit)) if started.elapsed() > limit => Watchdog::Timeout(limit),
+            _ => return Ok(()),
+        };
+
+        Err(RunError::WatchdogExpired {
+            watchdog,
+            label: last_label.map(str::to_string),
+            location: Box::new(item.location.clone()),
+        })
+    }
+
+    fn split_args(args: &str, location: &Location) -> Result<Vec<String>, RunError> {
+        string_utils::split_args(args).ok_or_else(|| RunError::InvalidArguments {
+            args: args.to_string(),
+            location: Box::new(location.clone()),
+        })
+    }
+
+    /// The arguments of `args` as split in the script text, with the macros
+    /// substituted inside each argument.
+    fn substitute_args(&self, args: &str, location: &Location) -> Result<Vec<String>, RunError> {
+        string_utils::split_args_with(args, |part| self.substitute(part, location))?.ok_or_else(
+            || RunError::InvalidArguments {
+                args: args.to_string(),
+                location: Box::new(location.clone()),
+            },
+        )
+    }
+
     fn execute_plugin_command_real_mode(
         &self,
         plugin_manager: &mut PluginManager,
         plugin: &str,
         command: &str,
-        args: &mut String,
-    ) -> Result<Option<String>, Box<dyn Error>> {
-        let descriptor = plugin_manager
-            .plugins
-            .get(plugin)
-            .ok_or(RunError::PluginNotFound)?;
-        string_utils::replace_macros(args, &self.macros);
+        args: &str,
+        location: &Location,
+    ) -> Result<Option<PluginValue>, RunError> {
+        let descriptor =
+            plugin_manager
+                .plugins
+                .get(plugin)
+                .ok_or_else(|| RunError::PluginNotFound {
+                    plugin: plugin.to_string(),
+                    location: Box::new(location.span_of(plugin)),
+                })?;
+        // items are executed repeatedly in loops, substitute into a copy
+        let argv = self.substitute_args(args, location)?;
+        let args = self.substitute(args, location)?;
         unsafe {
             let handle: &mut PluginHandle = &mut *descriptor.handle;
 
-            if plugin_do_dispatch(handle, command, args) {
-                println!("✅ Executed {} {}", command, args);
+            match plugin_do_dispatch(handle, command, &argv) {
+                Ok(()) => {
+                    println!("✅ Executed {} {}", command, args);
 
-                // Return output string (for VariableMacro)
-                Ok(Some(plugin_get_data(handle)))
-            } else {
-                eprintln!("❌ Failed {} {}", command, args);
-                Err(Box::new(RunError::ErrorExecutingCommand))
+                    // Return output value (for VariableMacro)
+                    Ok(Some(plugin_get_data(handle)))
+                }
+                Err(err) => Err(Self::dispatch_error(err, plugin, command, args, location)),
             }
         }
     }
 
+    fn dispatch_error(
+        err: DispatchError,
+        plugin: &str,
+        command: &str,
+        args: String,
+        location: &Location,
+    ) -> RunError {
+        let plugin = plugin.to_string();
+        let command = command.to_string();
+        let location = Box::new(location.clone());
+        match err {
+            DispatchError::Failed => RunError::ErrorExecutingCommand {
+                plugin,
+                command,
+                args,
+                location,
+            },
+            DispatchError::Panicked(message) => RunError::PluginPanicked {
+                plugin,
+                command,
+                message,
+                location,
+            },
+            DispatchError::Poisoned(message) => RunError::PluginPoisoned {
+                plugin,
+                command,
+                message,
+                location,
+            },
+        }
+    }
+
     fn execute_plugin_command_dry_mode(
         &self,
         plugin_manager: &mut PluginManager,
         plugin: &str,
         command: &str,
         args: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        let descriptor = plugin_manager
-            .plugins
-            .get(plugin)
-            .ok_or(RunError::PluginNotFound)?;
+        location: &Location,
+    ) -> Result<(), RunError> {
+        let descriptor =
+            plugin_manager
+                .plugins
+                .get(plugin)
+                .ok_or_else(|| RunError::PluginNotFound {
+                    plugin: plugin.to_string(),
+                    location: Box::new(location.span_of(plugin)),
+                })?;
+        // only the constants are known before the script runs
+        let argv = string_utils::split_args_with(args, |part| {
+            let mut part = part.to_string();
+            self.symbols.substitute(&mut part);
+            Ok::<_, RunError>(part)
+        })?
+        .ok_or_else(|| RunError::InvalidArguments {
+            args: args.to_string(),
+            location: Box::new(location.clone()),
+        })?;
         unsafe {
             let handle: &mut PluginHandle = &mut *descriptor.handle;
 
-            if plugin_do_dispatch(handle, command, args) {
-                println!("✅ Executed {} {}", command, args);
-                Ok(())
-            } else {
-                eprintln!("❌ Failed {} {}", command, args);
-                Err(Box::new(RunError::ErrorExecutingCommand))
+            match plugin_do_dispatch(handle, command, &argv) {
+                Ok(()) => {
+                    println!("✅ Executed {} {}", command, args);
+                    Ok(())
+                }
+                Err(err) => Err(Self::dispatch_error(
+                    err,
+                    plugin,
+                    command,
+                    args.to_string(),
+                    location,
+                )),
             }
         }
     }
 
     fn run_script_dry_mode(
         &mut self,
-        items: &mut Vec<Item>,
+        items: &[Item],
         plugin_manager: &mut PluginManager,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), RunError> {
         println!("---> Executing for parameter validation");
         for item in items {
             match &item.token_type {
@@ -102,7 +460,13 @@ impl ScriptRunner {
                     args,
                     ..
                 } => {
-                    self.execute_plugin_command_dry_mode(plugin_manager, plugin, command, args)?;
+                    self.execute_plugin_command_dry_mode(
+                        plugin_manager,
+                        plugin,
+                        command,
+                        args,
+                        &item.location,
+                    )?;
                 }
                 _ => {}
             }
@@ -110,32 +474,182 @@ impl ScriptRunner {
         Ok(())
     }
 
+    /// Replace the macros referenced in `text`. References to undefined
+    /// macros are kept verbatim, or rejected in strict mode.
+    fn substitute(&self, text: &str, location: &Location) -> Result<String, RunError> {
+        let mut text = text.to_string();
+        self.symbols.substitute(&mut text);
+
+        if self.undefined_macros == UndefinedMacros::Strict {
+            let references = string_utils::macro_references(&text);
+            if let Some(reference) = references
+                .iter()
+                .find(|reference| self.symbols.get(reference.name).is_none())
+            {
+                return Err(RunError::UndefinedMacro {
+                    name: reference.name.to_string(),
+                    location: Box::new(location.span_of(reference.text)),
+                });
+            }
+            if let Some(reference) = references
+                .iter()
+                .find(|reference| reference.accessor.is_some())
+            {
+                return Err(RunError::InvalidListAccess {
+                    reference: reference.text.to_string(),
+                    location: Box::new(location.span_of(reference.text)),
+                });
+            }
+        }
+        Ok(text)
+    }
+
+    /// The value of `text`: the value of the macro, with its type, if `text`
+    /// is a single `$NAME` reference, otherwise the substituted text typed
+    /// with [`Value::from_text`].
+    fn text_value(&self, text: &str, location: &Location) -> Result<Value, RunError> {
+        let trimmed = text.trim();
+        if let [reference] = string_utils::macro_references(trimmed).as_slice() {
+            if reference.text == trimmed && reference.accessor.is_none() {
+                if let Some(value) = self.symbols.value(reference.name) {
+                    return Ok(value.clone());
+                }
+            }
+        }
+        Ok(Value::from_text(&self.substitute(text, location)?))
+    }
+
+    /// The script value of a plugin result, text is kept as written.
+    fn plugin_value(data: PluginValue) -> Value {
+        match data {
+            PluginValue::Bool(boolean) => Value::Bool(boolean),
+            PluginValue::Int(int) => Value::Int(int),
+            PluginValue::Float(float) => Value::Float(float),
+            PluginValue::Str(text) => Value::Str(text),
+            PluginValue::Bytes(bytes) => Value::Bytes(bytes),
+            PluginValue::List(elements) => {
+                Value::List(elements.into_iter().map(Self::plugin_value).collect())
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        self.symbols.value(name).cloned()
+    }
+
+    /// Apply a change to the symbols made by the statement `item`.
+    fn update_symbols(
+        &mut self,
+        name: &str,
+        item: &Item,
+        update: impl FnOnce(&mut SymbolTable) -> Result<(), SymbolError>,
+    ) -> Result<(), RunError> {
+        update(&mut self.symbols).map_err(|_| RunError::ConstantModified {
+            name: name.to_string(),
+            location: Box::new(item.location.span_of(name)),
+        })
+    }
+
+    fn expression_error(expression: &str, error: EvalError, item: &Item) -> RunError {
+        RunError::InvalidExpression {
+            expression: expression.to_string(),
+            error,
+            location: Box::new(item.location.span_of(expression)),
+        }
+    }
+
+    fn evaluate(&self, expression: &str, item: &Item) -> Result<Value, RunError> {
+        expr::evaluate(expression, &|name| self.lookup(name))
+            .map_err(|error| Self::expression_error(expression, error, item))
+    }
+
+    fn is_true(&self, condition: &str, item: &Item) -> Result<bool, RunError> {
+        match expr::evaluate_condition(condition, &|name| self.lookup(name)) {
+            Ok(value) => Ok(value),
+            Err(EvalError::UndefinedMacro(_) | EvalError::EmptyCondition)
+                if self.undefined_condition == UndefinedCondition::False =>
+            {
+                Ok(false)
+            }
+            Err(error) => Err(Self::expression_error(condition, error, item)),
+        }
+    }
+
+    /// Index of the statement to continue with after the IF (or ELSEIF) at
+    /// `index` evaluated to false: the body of the first true ELSEIF, the
+    /// ELSE body, or the statement after ENDIF.
+    fn select_branch(
+        &self,
+        items: &[Item],
+        blocks: &BlockTable,
+        index: usize,
+    ) -> Result<usize, RunError> {
+        let mut branch = index;
+        loop {
+            let next =
+                *blocks
+                    .next_branch
+                    .get(&branch)
+                    .ok_or_else(|| RunError::UnbalancedBlock {
+                        location: Box::new(items[branch].location.clone()),
+                    })?;
+            match &items[next].token_type {
+                TokenType::ElseIf { condition } if !self.is_true(condition, &items[next])? => {
+                    branch = next
+                }
+                _ => return Ok(next + 1),
+            }
+        }
+    }
+
     fn run_script_full_mode(
         &mut self,
-        items: &mut [Item],
+        items: &[Item],
         plugin_manager: &mut PluginManager,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), RunError> {
         println!("---> Executing in real mode");
-        let mut skiplabel = String::new();
-
-        for item in items.iter_mut() {
-            // Skip all tokens until we hit the right label
-            if !skiplabel.is_empty() {
-                if let TokenType::Label { label } = &item.token_type {
-                    if *label == skiplabel {
-                        println!("▶️ Found label '{}', resuming execution", label);
-                        skiplabel.clear(); // stop skipping
-                        continue;
-                    }
-                }
-                // if we're still skipping, move to the next item
-                if !skiplabel.is_empty() {
-                    println!("🚫Skipping item: {:?}", item);
-                    continue;
-                }
-            }
+        let control_flow = ControlFlow::resolve(items);
+        let blocks = &control_flow.blocks;
+        let block_end = |index: usize| -> Result<usize, RunError> {
+            blocks
+                .end
+                .get(&index)
+                .copied()
+                .ok_or_else(|| RunError::UnbalancedBlock {
+                    location: Box::new(items[index].location.clone()),
+                })
+        };
+        let jump_target = |label: &str, item: &Item| -> Result<usize, RunError> {
+            control_flow
+                .labels
+                .get(label)
+                .copied()
+                .ok_or_else(|| RunError::LabelNotFound {
+                    label: label.to_string(),
+                    location: Box::new(item.location.span_of(label)),
+                })
+        };
+        // remaining iterations of the active REPEAT blocks
+        let mut repeat_counters: HashMap<usize, u64> = HashMap::new();
+        // elements not yet visited by the active FOREACH blocks
+        let mut foreach_elements: HashMap<usize, VecDeque<Value>> = HashMap::new();
+        let mut call_stack: Vec<CallFrame> = Vec::new();
+        let mut label_iterations: HashMap<&str, u64> = HashMap::new();
+        let mut last_label: Option<&str> = None;
+        let mut executed: u64 = 0;
+        let started = Instant::now();
+        let mut pc: usize = 0;
 
-            match &mut item.token_type {
+        while let Some(item) = items.get(pc) {
+            let index = pc;
+            pc += 1;
+            executed += 1;
+            self.check_limits(item, executed, &started, last_label)?;
+            // LOCAL variables end with their block, however it was left
+            self.symbols
+                .leave_blocks(|block| blocks.contains(block, index));
+
+            match &item.token_type {
                 TokenType::VariableMacro {
                     plugin,
                     command,
@@ -144,9 +658,16 @@ impl ScriptRunner {
                     ..
                 } => {
                     let result = self
-                        .execute_plugin_command_real_mode(plugin_manager, plugin, command, args)?
+                        .execute_plugin_command_real_mode(
+                            plugin_manager,
+                            plugin,
+                            command,
+                            args,
+                            &item.location,
+                        )?
                         .unwrap_or_default();
-                    self.macros.insert(vmacro.clone(), result);
+                    let value = Self::plugin_value(result);
+                    self.update_symbols(vmacro, item, |symbols| symbols.assign(vmacro, value))?;
                 }
 
                 TokenType::Command {
@@ -155,22 +676,264 @@ impl ScriptRunner {
                     args,
                     ..
                 } => {
-                    self.execute_plugin_command_real_mode(plugin_manager, plugin, command, args)?;
+                    self.execute_plugin_command_real_mode(
+                        plugin_manager,
+                        plugin,
+                        command,
+                        args,
+                        &item.location,
+                    )?;
                 }
 
-                TokenType::IfGoTo { condition, label } => {
-                    string_utils::replace_macros(condition, &self.macros);
-                    if condition.is_empty() || condition.to_lowercase() == "true" {
-                        println!("⏩ Skipping until label '{}'", label);
-                        skiplabel = label.clone();
-                        continue;
-                    }
+                TokenType::Assignment { vmacro, expression } => {
+                    let value = self.evaluate(expression, item)?;
+                    self.update_symbols(vmacro, item, |symbols| symbols.assign(vmacro, value))?;
+                }
+
+                TokenType::Local { vmacro, expression } => {
+                    let value = self.evaluate(expression, item)?;
+                    // directly in a function body it is local to the function
+                    let block = blocks.parent.get(&index).copied().filter(|&parent| {
+                        !matches!(items[parent].token_type, TokenType::Function { .. })
+                    });
+                    self.update_symbols(vmacro, item, |symbols| {
+                        symbols.declare_local(vmacro, value, block)
+                    })?;
+                }
+
+                TokenType::Unset { vmacro } => {
+                    self.update_symbols(vmacro, item, |symbols| symbols.unset(vmacro))?;
+                }
+
+                TokenType::Goto { label } => {
+                    println!("⏩ Jumping to label '{}'", label);
+                    pc = jump_target(label, item)?;
+                }
+
+                TokenType::IfGoTo { condition, label } if self.is_true(condition, item)? => {
+                    println!("⏩ Jumping to label '{}'", label);
+                    pc = jump_target(label, item)?;
                 }
 
                 TokenType::Label { label } => {
-                    // normal labels are handled in the skip block above
-                    // (this will only run when not skipping)
                     println!("🏷️ Encountered label '{}'", label);
+                    last_label = Some(label);
+
+                    let iterations = label_iterations.entry(label).or_insert(0);
+                    *iterations += 1;
+                    if let Some(limit) = self.limits.label_limit(label) {
+                        if *iterations > limit {
+                            return Err(RunError::WatchdogExpired {
+                                watchdog: Watchdog::LabelIterations(limit),
+                                label: Some(label.clone()),
+                                location: Box::new(item.location.span_of(label)),
+                            });
+                        }
+                    }
+                }
+
+                TokenType::If { condition } if !self.is_true(condition, item)? => {
+                    pc = self.select_branch(items, blocks, index)?;
+                }
+
+                // reached at the end of the branch that was taken
+                TokenType::ElseIf { .. } | TokenType::Else => {
+                    pc = block_end(index)? + 1;
+                }
+
+                TokenType::While { condition } if !self.is_true(condition, item)? => {
+                    pc = block_end(index)? + 1;
+                }
+
+                TokenType::EndWhile => {
+                    pc = *blocks
+                        .start
+                        .get(&index)
+                        .ok_or_else(|| RunError::UnbalancedBlock {
+                            location: Box::new(item.location.clone()),
+                        })?;
+                }
+
+                TokenType::Repeat { count } => {
+                    let value = self.substitute(count, &item.location)?;
+                    let count =
+                        value
+                            .trim()
+                            .parse::<u64>()
+                            .map_err(|_| RunError::InvalidRepeatCount {
+                                count: value.clone(),
+                                location: Box::new(item.location.span_of(count)),
+                            })?;
+                    if count == 0 {
+                        pc = block_end(index)? + 1;
+                    } else {
+                        repeat_counters.insert(index, count);
+                    }
+                }
+
+                TokenType::EndRepeat => {
+                    let start =
+                        *blocks
+                            .start
+                            .get(&index)
+                            .ok_or_else(|| RunError::UnbalancedBlock {
+                                location: Box::new(item.location.clone()),
+                            })?;
+                    let remaining = repeat_counters.entry(start).or_insert(1);
+                    *remaining -= 1;
+                    if *remaining > 0 {
+                        pc = start + 1;
+                    } else {
+                        repeat_counters.remove(&start);
+                    }
+                }
+
+                TokenType::Foreach { vmacro, list } => {
+                    let mut elements: VecDeque<Value> =
+                        match self.text_value(list, &item.location)? {
+                            Value::List(elements) => elements.into(),
+                            // the elements of list text, e.g. a constant, stay text
+                            Value::Str(text) if string_utils::parse_list(&text).is_some() => {
+                                string_utils::parse_list(&text)
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .map(Value::Str)
+                                    .collect()
+                            }
+                            value => Self::split_args(&value.to_string(), &item.location)?
+                                .iter()
+                                .map(|word| Value::from_text(word))
+                                .collect(),
+                        };
+                    match elements.pop_front() {
+                        Some(first) => {
+                            self.update_symbols(vmacro, item, |symbols| {
+                                symbols.declare_local(vmacro, first, Some(index))
+                            })?;
+                            foreach_elements.insert(index, elements);
+                        }
+                        None => pc = block_end(index)? + 1,
+                    }
+                }
+
+                TokenType::EndForeach => {
+                    let start =
+                        *blocks
+                            .start
+                            .get(&index)
+                            .ok_or_else(|| RunError::UnbalancedBlock {
+                                location: Box::new(item.location.clone()),
+                            })?;
+                    let TokenType::Foreach { vmacro, .. } = &items[start].token_type else {
+                        unreachable!("ENDFOREACH closes a FOREACH");
+                    };
+                    match foreach_elements
+                        .get_mut(&start)
+                        .and_then(VecDeque::pop_front)
+                    {
+                        Some(next) => {
+                            // the loop variable ended with the previous iteration
+                            self.update_symbols(vmacro, &items[start], |symbols| {
+                                symbols.declare_local(vmacro, next, Some(start))
+                            })?;
+                            pc = start + 1;
+                        }
+                        None => {
+                            foreach_elements.remove(&start);
+                        }
+                    }
+                }
+
+                TokenType::Break => {
+                    let end = block_end(index)?;
+                    if let Some(start) = blocks.start.get(&end) {
+                        repeat_counters.remove(start);
+                        foreach_elements.remove(start);
+                    }
+                    pc = end + 1;
+                }
+
+                // the loop end re-evaluates WHILE and counts down REPEAT
+                TokenType::Continue => {
+                    pc = block_end(index)?;
+                }
+
+                // the body is only executed through CALL
+                TokenType::Function { .. } => {
+                    pc = block_end(index)? + 1;
+                }
+
+                TokenType::Call {
+                    function,
+                    args,
+                    vmacro,
+                } => {
+                    let start =
+                        *control_flow
+                            .functions
+                            .get(function.as_str())
+                            .ok_or_else(|| RunError::FunctionNotFound {
+                                function: function.clone(),
+                                location: Box::new(item.location.span_of(function)),
+                            })?;
+                    let TokenType::Function { params, .. } = &items[start].token_type else {
+                        unreachable!("functions map to FUNCTION statements");
+                    };
+
+                    let args = self.substitute_args(args, &item.location)?;
+                    if args.len() != params.len() {
+                        return Err(RunError::FunctionArity {
+                            function: function.clone(),
+                            expected: params.len(),
+                            found: args.len(),
+                            location: Box::new(item.location.span_of(function)),
+                        });
+                    }
+
+                    let limit = self.limits.call_depth_limit();
+                    if call_stack.len() as u64 >= limit {
+                        return Err(RunError::WatchdogExpired {
+                            watchdog: Watchdog::CallDepth(limit),
+                            label: last_label.map(str::to_string),
+                            location: Box::new(item.location.span_of(function)),
+                        });
+                    }
+
+                    println!("📞 Calling function '{}'", function);
+                    self.symbols.push_scope(ScopeKind::Function);
+                    for (param, value) in params.iter().zip(args) {
+                        self.update_symbols(param, &items[start], |symbols| {
+                            symbols.declare_local(param, Value::Str(value), None)
+                        })?;
+                    }
+                    call_stack.push(CallFrame {
+                        return_to: pc,
+                        vmacro: vmacro.clone(),
+                        repeat_counters: std::mem::take(&mut repeat_counters),
+                        foreach_elements: std::mem::take(&mut foreach_elements),
+                    });
+                    pc = start + 1;
+                }
+
+                TokenType::Return { .. } | TokenType::EndFunction => {
+                    let value = match &item.token_type {
+                        TokenType::Return { value } => self.text_value(value, &item.location)?,
+                        _ => Value::Str(String::new()),
+                    };
+                    let frame = call_stack.pop().ok_or_else(|| RunError::UnbalancedBlock {
+                        location: Box::new(item.location.clone()),
+                    })?;
+
+                    self.symbols.pop_function_scope();
+                    repeat_counters = frame.repeat_counters;
+                    foreach_elements = frame.foreach_elements;
+                    if let Some(vmacro) = frame.vmacro {
+                        let call = &items[frame.return_to - 1];
+                        self.update_symbols(&vmacro, call, |symbols| {
+                            symbols.assign(&vmacro, value)
+                        })?;
+                    }
+                    pc = frame.return_to;
                 }
 
                 _ => {}
@@ -179,13 +942,23 @@ impl ScriptRunner {
         Ok(())
     }
 
+    pub fn dry_run_script(
+        &mut self,
+        items: &[Item],
+        plugin_manager: &mut PluginManager,
+    ) -> Result<(), RunError> {
+        self.run_script_dry_mode(items, plugin_manager)
+    }
+
     pub fn run_script(
         &mut self,
-        items: &mut Vec<Item>,
+        items: &[Item],
         plugin_manager: &mut PluginManager,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), RunError> {
         self.run_script_dry_mode(items, plugin_manager)?;
-        plugin_manager.enable_plugins();
+        plugin_manager
+            .enable_plugins()
+            .map_err(|source| RunError::PluginEnablingFailed { source })?;
         self.run_script_full_mode(items, plugin_manager)?;
         Ok(())
     }
@@ -196,3 +969,443 @@ impl Default for ScriptRunner {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ScriptParser;
+
+    fn parse(lines: &[&str]) -> (Vec<Item>, SymbolTable) {
+        let mut parser = ScriptParser::new();
+        let mut items: Vec<Item> = lines
+            .iter()
+            .map(|line| Item {
+                line: line.to_string(),
+                ..Item::default()
+            })
+            .collect();
+        parser.parse_script(&mut items).unwrap();
+        (items, parser.symbols().clone())
+    }
+
+    /// Run a script using no plugins with `runner`.
+    fn run_with(runner: &mut ScriptRunner, lines: &[&str]) -> Result<(), RunError> {
+        let (items, symbols) = parse(lines);
+        runner.set_symbols(symbols);
+        runner.run_script(&items, &mut PluginManager::new(Vec::new(), ""))
+    }
+
+    fn run(lines: &[&str]) -> ScriptRunner {
+        let mut runner = ScriptRunner::new();
+        run_with(&mut runner, lines).unwrap();
+        runner
+    }
+
+    fn value(runner: &ScriptRunner, name: &str) -> Option<String> {
+        runner.lookup(name).map(|value| value.to_string())
+    }
+
+    /// Plugin `ARGS`, its `LIST` command returns the arguments it received.
+    #[derive(Default)]
+    struct ArgsPlugin {
+        data: PluginValue,
+        enabled: bool,
+    }
+
+    impl plugin_api::PluginInterface for ArgsPlugin {
+        fn do_init(&mut self) {}
+        fn do_enable(&mut self) {
+            self.enabled = true;
+        }
+        fn do_dispatch(&mut self, _cmd: &str, args: &[&str]) -> bool {
+            self.data = PluginValue::List(args.iter().map(|arg| (*arg).into()).collect());
+            true
+        }
+        fn do_cleanup(&mut self) {}
+        fn set_params(&mut self, _params: &plugin_api::ParamsSet) -> bool {
+            true
+        }
+        fn get_params(&self, _params: &mut plugin_api::ParamsGet) {}
+        fn get_data(&self) -> &PluginValue {
+            &self.data
+        }
+        fn reset_data(&mut self) {
+            self.data.clear();
+        }
+        fn is_initialized(&self) -> bool {
+            true
+        }
+        fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+        fn is_privileged(&self) -> bool {
+            false
+        }
+        fn is_fault_tolerant(&self) -> bool {
+            false
+        }
+    }
+
+    /// Run a script using the `ARGS` plugin.
+    fn run_with_args_plugin(lines: &[&str]) -> ScriptRunner {
+        let mut manager = PluginManager::new(Vec::new(), "");
+        manager.plugins.insert(
+            "ARGS".to_string(),
+            plugin_manager::PluginDescriptor {
+                handle: Box::into_raw(Box::new(plugin_api::make_handle(ArgsPlugin::default()))),
+                path: std::path::PathBuf::new(),
+                privileged: false,
+                _lib: None,
+            },
+        );
+        let (items, symbols) = parse(lines);
+        let mut runner = ScriptRunner::new();
+        runner.set_symbols(symbols);
+        runner.run_script(&items, &mut manager).unwrap();
+        runner
+    }
+
+    fn texts(texts: &[&str]) -> Value {
+        Value::List(
+            texts
+                .iter()
+                .map(|text| Value::Str(text.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn backward_jump_repeats_statements() {
+        let runner = run(&[
+            "X = first",
+            "COUNT = zero",
+            "LABEL AGAIN",
+            "IF $X == second GOTO DONE",
+            "X = second",
+            "GOTO AGAIN",
+            "COUNT = skipped",
+            "LABEL DONE",
+        ]);
+        assert_eq!(value(&runner, "X").as_deref(), Some("second"));
+        assert_eq!(value(&runner, "COUNT").as_deref(), Some("zero"));
+    }
+
+    fn watchdog(limits: RunLimits, lines: &[&str]) -> (Watchdog, Option<String>) {
+        let mut runner = ScriptRunner::new();
+        runner.set_limits(limits);
+        match run_with(&mut runner, lines) {
+            Err(RunError::WatchdogExpired {
+                watchdog, label, ..
+            }) => (watchdog, label),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn statement_limit_stops_an_endless_loop() {
+        let limits = RunLimits {
+            max_statements: Some(100),
+            ..RunLimits::default()
+        };
+        assert_eq!(
+            watchdog(limits, &["LABEL FOREVER", "GOTO FOREVER"]),
+            (Watchdog::Statements(100), Some("FOREVER".to_string()))
+        );
+    }
+
+    #[test]
+    fn label_limit_takes_precedence_over_the_global_one() {
+        let limits = RunLimits {
+            max_label_iterations: Some(1000),
+            label_limits: HashMap::from([("RETRY".to_string(), 3)]),
+            ..RunLimits::default()
+        };
+        assert_eq!(
+            watchdog(limits, &["LABEL RETRY", "GOTO RETRY"]),
+            (Watchdog::LabelIterations(3), Some("RETRY".to_string()))
+        );
+    }
+
+    #[test]
+    fn timeout_stops_an_endless_loop() {
+        let limits = RunLimits {
+            timeout: Some(std::time::Duration::from_millis(50)),
+            ..RunLimits::default()
+        };
+        let (watchdog, _) = watchdog(limits, &["LABEL FOREVER", "GOTO FOREVER"]);
+        assert!(matches!(watchdog, Watchdog::Timeout(_)));
+    }
+
+    #[test]
+    fn if_takes_the_first_true_branch() {
+        for (x, expected) in [("1", "one"), ("2", "two"), ("3", "other")] {
+            let runner = run(&[
+                &format!("X = {}", x),
+                "IF $X == 1",
+                "R = one",
+                "ELSEIF $X == 2",
+                "R = two",
+                "ELSEIF $X >= 2",
+                "R = twice",
+                "ELSE",
+                "R = other",
+                "ENDIF",
+            ]);
+            let expected = if x == "3" { "twice" } else { expected };
+            assert_eq!(value(&runner, "R").as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn repeat_runs_its_body_count_times() {
+        for (count, expected) in [("0", "a"), ("1", "b"), ("2", "c")] {
+            let runner = run(&[
+                "X = a",
+                &format!("REPEAT {}", count),
+                "IF $X == b",
+                "X = c",
+                "ELSEIF $X == a",
+                "X = b",
+                "ENDIF",
+                "ENDREPEAT",
+            ]);
+            assert_eq!(value(&runner, "X").as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn while_loop_with_break_and_continue() {
+        let runner = run(&[
+            "X = a",
+            "SEEN = none",
+            "WHILE $X != done",
+            "IF $X == a",
+            "X = b",
+            "CONTINUE",
+            "ENDIF",
+            "SEEN = $X",
+            "BREAK",
+            "SEEN = never",
+            "ENDWHILE",
+        ]);
+        assert_eq!(value(&runner, "X").as_deref(), Some("b"));
+        assert_eq!(value(&runner, "SEEN").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn undefined_condition_is_an_error_or_false() {
+        let script = [
+            "R = jumped",
+            "IF $MISSING GOTO DONE",
+            "R = fallthrough",
+            "LABEL DONE",
+        ];
+        let err = run_with(&mut ScriptRunner::new(), &script).unwrap_err();
+        assert!(matches!(
+            err,
+            RunError::InvalidExpression {
+                error: EvalError::UndefinedMacro(_),
+                ..
+            }
+        ));
+
+        let mut runner = ScriptRunner::new();
+        runner.set_undefined_condition(UndefinedCondition::False);
+        run_with(&mut runner, &script).unwrap();
+        assert_eq!(value(&runner, "R").as_deref(), Some("fallthrough"));
+    }
+
+    #[test]
+    fn goto_always_jumps() {
+        let runner = run(&["R = jumped", "GOTO DONE", "R = fallthrough", "LABEL DONE"]);
+        assert_eq!(value(&runner, "R").as_deref(), Some("jumped"));
+    }
+
+    #[test]
+    fn strict_mode_rejects_undefined_macros() {
+        let script = ["REPEAT $MISSING", "ENDREPEAT"];
+        let err = run_with(&mut ScriptRunner::new(), &script).unwrap_err();
+        assert!(matches!(err, RunError::InvalidRepeatCount { count, .. } if count == "$MISSING"));
+
+        let mut runner = ScriptRunner::new();
+        runner.set_undefined_macros(UndefinedMacros::Strict);
+        let err = run_with(&mut runner, &script).unwrap_err();
+        assert!(matches!(err, RunError::UndefinedMacro { name, .. } if name == "MISSING"));
+    }
+
+    #[test]
+    fn call_binds_parameters_and_returns_a_value() {
+        let runner = run(&[
+            "FUNCTION PICK A B",
+            "IF $A == first",
+            "RETURN $B",
+            "ENDIF",
+            "RETURN $A",
+            "ENDFUNCTION",
+            "R ?= CALL PICK first second",
+            "S ?= CALL PICK other second",
+            "T ?= CALL PICK \"a b\" c",
+        ]);
+        assert_eq!(value(&runner, "R").as_deref(), Some("second"));
+        assert_eq!(value(&runner, "S").as_deref(), Some("other"));
+        assert_eq!(value(&runner, "T").as_deref(), Some("a b"));
+        assert_eq!(value(&runner, "A"), None);
+    }
+
+    #[test]
+    fn functions_set_globals_unless_local() {
+        let runner = run(&[
+            "FUNCTION SETUP",
+            "LOCAL TEMP = scratch",
+            "DONE = $TEMP",
+            "ENDFUNCTION",
+            "CALL SETUP",
+        ]);
+        assert_eq!(value(&runner, "DONE").as_deref(), Some("scratch"));
+        assert_eq!(value(&runner, "TEMP"), None);
+    }
+
+    #[test]
+    fn endless_recursion_hits_the_call_depth_limit() {
+        let limits = RunLimits {
+            max_call_depth: Some(5),
+            ..RunLimits::default()
+        };
+        let (watchdog, _) = watchdog(
+            limits,
+            &[
+                "FUNCTION DEEPER",
+                "CALL DEEPER",
+                "ENDFUNCTION",
+                "CALL DEEPER",
+            ],
+        );
+        assert_eq!(watchdog, Watchdog::CallDepth(5));
+    }
+
+    #[test]
+    fn locals_end_with_their_block() {
+        let runner = run(&[
+            "X = outer",
+            "IF 1 == 1",
+            "LOCAL X = inner",
+            "SEEN = $X",
+            "ENDIF",
+            "GONE = temporary",
+            "UNSET GONE",
+        ]);
+        assert_eq!(value(&runner, "SEEN").as_deref(), Some("inner"));
+        assert_eq!(value(&runner, "X").as_deref(), Some("outer"));
+        assert_eq!(value(&runner, "GONE"), None);
+    }
+
+    #[test]
+    fn constants_cannot_be_assigned() {
+        let err = run_with(
+            &mut ScriptRunner::new(),
+            &["NAME := hello world", "NAME = other"],
+        )
+        .unwrap_err();
+        assert!(matches!(err, RunError::ConstantModified { name, .. } if name == "NAME"));
+    }
+
+    #[test]
+    fn foreach_visits_every_element() {
+        let runner = run(&[
+            "LAST = none",
+            "FOREACH ITEM IN [a, \"b c\", d]",
+            "IF $ITEM == d",
+            "BREAK",
+            "ENDIF",
+            "LAST = $ITEM",
+            "ENDFOREACH",
+            "WORDS = none",
+            "FOREACH WORD IN x y",
+            "WORDS = $WORD",
+            "ENDFOREACH",
+        ]);
+        assert_eq!(value(&runner, "LAST").as_deref(), Some("b c"));
+        assert_eq!(value(&runner, "WORDS").as_deref(), Some("y"));
+        assert_eq!(value(&runner, "ITEM"), None);
+    }
+
+    #[test]
+    fn plugin_results_keep_their_type() {
+        assert_eq!(
+            ScriptRunner::plugin_value(PluginValue::Str("0042".to_string())),
+            Value::Str("0042".to_string())
+        );
+        assert_eq!(
+            ScriptRunner::plugin_value(PluginValue::List(vec![
+                PluginValue::Float(1.0),
+                PluginValue::Str("[a]".to_string()),
+                PluginValue::Bytes(vec![1]),
+            ])),
+            Value::List(vec![
+                Value::Float(1.0),
+                Value::Str("[a]".to_string()),
+                Value::Bytes(vec![1]),
+            ])
+        );
+    }
+
+    #[test]
+    fn constants_keep_their_text() {
+        let runner = run(&[
+            "ID := 007",
+            "V := 1.10",
+            "L := [007, b]",
+            "COPY = $ID",
+            "TEXT = $ID == \"007\" && $ID EQ 007",
+            "NUMBER = $ID == 7 && $ID < 10 && $V == 1.1",
+            "FIRST = $L[0]",
+            "ELEMENT = $L[0] == 7 && $L.len == 2",
+            "LAST = none",
+            "FOREACH E IN $L",
+            "LAST = $E",
+            "ENDFOREACH",
+        ]);
+        assert_eq!(value(&runner, "COPY").as_deref(), Some("007"));
+        assert_eq!(value(&runner, "V").as_deref(), Some("1.10"));
+        assert_eq!(value(&runner, "TEXT").as_deref(), Some("TRUE"));
+        assert_eq!(value(&runner, "NUMBER").as_deref(), Some("TRUE"));
+        assert_eq!(value(&runner, "FIRST").as_deref(), Some("007"));
+        assert_eq!(value(&runner, "ELEMENT").as_deref(), Some("TRUE"));
+        assert_eq!(value(&runner, "LAST").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn macro_values_do_not_split_or_quote_arguments() {
+        let runner = run_with_args_plugin(&[
+            r#"C := say "hi"#,
+            r#"V = "two words""#,
+            r#"R ?= ARGS.LIST $V "$C and \"$V\"" \$V $C"#,
+        ]);
+        assert_eq!(
+            runner.lookup("R"),
+            Some(texts(&[
+                "two words",
+                r#"say "hi and "two words""#,
+                "$V",
+                r#"say "hi"#,
+            ]))
+        );
+    }
+
+    #[test]
+    fn lists_are_passed_as_one_argument() {
+        let runner = run_with_args_plugin(&[
+            "L := [a, b]",
+            "M = [c, \"d e\"]",
+            "FUNCTION SECOND P",
+            "RETURN $P[1]",
+            "ENDFUNCTION",
+            "R ?= CALL SECOND $L",
+            "S ?= CALL SECOND $M",
+            "A ?= ARGS.LIST $L $M",
+        ]);
+        assert_eq!(value(&runner, "R").as_deref(), Some("b"));
+        assert_eq!(value(&runner, "S").as_deref(), Some("d e"));
+        assert_eq!(runner.lookup("A"), Some(texts(&["[a, b]", "[c, d e]"])));
+    }
+}
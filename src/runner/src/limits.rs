@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// ---------------------------
+// [COMMON] settings keys
+// ---------------------------
+pub const SETTINGS_MAX_STATEMENTS: &str = "MAX_STATEMENTS";
+pub const SETTINGS_MAX_LABEL_ITERATIONS: &str = "MAX_LABEL_ITERATIONS";
+pub const SETTINGS_LABEL_LIMITS: &str = "LABEL_LIMITS";
+pub const SETTINGS_TIMEOUT: &str = "TIMEOUT";
+pub const SETTINGS_MAX_CALL_DEPTH: &str = "MAX_CALL_DEPTH";
+
+/// Nesting of CALL statements allowed when `max_call_depth` is not set
+pub const DEFAULT_MAX_CALL_DEPTH: u64 = 1000;
+
+/// Watchdog limits applied while executing a script, all disabled by default
+/// except the call depth.
+#[derive(Debug, Default, Clone)]
+pub struct RunLimits {
+    /// Maximum number of statements executed in real mode
+    pub max_statements: Option<u64>,
+    /// Maximum number of times any single label may be executed
+    pub max_label_iterations: Option<u64>,
+    /// Per-label caps, taking precedence over `max_label_iterations`
+    pub label_limits: HashMap<String, u64>,
+    /// Wall-clock limit for the whole real mode execution
+    pub timeout: Option<Duration>,
+    /// Maximum nesting of CALL statements, [`DEFAULT_MAX_CALL_DEPTH`] if not set
+    pub max_call_depth: Option<u64>,
+}
+
+/// The limit that stopped the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Watchdog {
+    Statements(u64),
+    LabelIterations(u64),
+    Timeout(Duration),
+    CallDepth(u64),
+}
+
+impl fmt::Display for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchdog::Statements(limit) => write!(f, "more than {} statements executed", limit),
+            Watchdog::LabelIterations(limit) => write!(f, "more than {} iterations", limit),
+            Watchdog::Timeout(limit) => write!(f, "timeout of {:?} exceeded", limit),
+            Watchdog::CallDepth(limit) => write!(f, "more than {} nested calls", limit),
+        }
+    }
+}
+
+impl RunLimits {
+    /// Build the limits from a resolved `[COMMON]` section:
+    ///
+    /// ```ini
+    /// MAX_STATEMENTS       = 100000
+    /// MAX_LABEL_ITERATIONS = 1000
+    /// LABEL_LIMITS         = RETRY:10, POLL:500
+    /// # seconds
+    /// TIMEOUT              = 3600
+    /// MAX_CALL_DEPTH       = 100
+    /// ```
+    pub fn from_settings(section: &HashMap<String, String>) -> Result<Self, String> {
+        let number = |key: &str| -> Result<Option<u64>, String> {
+            section
+                .get(key)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid value for: {} -> {}", key, value))
+                })
+                .transpose()
+        };
+
+        let mut limits = RunLimits {
+            max_statements: number(SETTINGS_MAX_STATEMENTS)?,
+            max_label_iterations: number(SETTINGS_MAX_LABEL_ITERATIONS)?,
+            timeout: number(SETTINGS_TIMEOUT)?.map(Duration::from_secs),
+            max_call_depth: number(SETTINGS_MAX_CALL_DEPTH)?,
+            ..Default::default()
+        };
+
+        if let Some(value) = section.get(SETTINGS_LABEL_LIMITS) {
+            for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (label, limit) = entry
+                    .split_once(':')
+                    .and_then(|(label, limit)| Some((label.trim(), limit.trim().parse().ok()?)))
+                    .ok_or_else(|| {
+                        format!("Invalid value for: {} -> {}", SETTINGS_LABEL_LIMITS, entry)
+                    })?;
+                limits.label_limits.insert(label.to_string(), limit);
+            }
+        }
+
+        Ok(limits)
+    }
+
+    pub fn call_depth_limit(&self) -> u64 {
+        self.max_call_depth.unwrap_or(DEFAULT_MAX_CALL_DEPTH)
+    }
+
+    /// Iteration cap for `label`, if any.
+    pub fn label_limit(&self, label: &str) -> Option<u64> {
+        self.label_limits
+            .get(label)
+            .copied()
+            .or(self.max_label_iterations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn limits_are_read_from_the_settings() {
+        let limits = RunLimits::from_settings(&section(&[
+            (SETTINGS_MAX_STATEMENTS, "100"),
+            (SETTINGS_MAX_LABEL_ITERATIONS, " 10 "),
+            (SETTINGS_LABEL_LIMITS, "RETRY:3, POLL : 5,"),
+            (SETTINGS_TIMEOUT, "60"),
+        ]))
+        .unwrap();
+        assert_eq!(limits.max_statements, Some(100));
+        assert_eq!(limits.timeout, Some(Duration::from_secs(60)));
+        assert_eq!(limits.label_limit("RETRY"), Some(3));
+        assert_eq!(limits.label_limit("POLL"), Some(5));
+        assert_eq!(limits.label_limit("OTHER"), Some(10));
+        assert_eq!(limits.call_depth_limit(), DEFAULT_MAX_CALL_DEPTH);
+    }
+
+    #[test]
+    fn no_settings_means_no_limits() {
+        let limits = RunLimits::from_settings(&HashMap::new()).unwrap();
+        assert_eq!(limits.max_statements, None);
+        assert_eq!(limits.label_limit("ANY"), None);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(RunLimits::from_settings(&section(&[(SETTINGS_MAX_STATEMENTS, "many")])).is_err());
+        assert!(RunLimits::from_settings(&section(&[(SETTINGS_LABEL_LIMITS, "RETRY")])).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use interfaces::{Item, TokenType};
+
+/// Index of the `LABEL` statement of each label, the first one if defined twice.
+pub fn resolve_labels(items: &[Item]) -> HashMap<&str, usize> {
+    let mut labels = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        if let TokenType::Label { label } = &item.token_type {
+            labels.entry(label.as_str()).or_insert(index);
+        }
+    }
+    labels
+}
+
+/// Index of the `FUNCTION` statement of each function, the first one if
+/// defined twice.
+pub fn resolve_functions(items: &[Item]) -> HashMap<&str, usize> {
+    let mut functions = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        if let TokenType::Function { name, .. } = &item.token_type {
+            functions.entry(name.as_str()).or_insert(index);
+        }
+    }
+    functions
+}
+
+/// Jump targets of the block statements, indexed by statement.
+/// The nesting is checked by the validator, unmatched statements get no entry.
+#[derive(Debug, Default)]
+pub struct BlockTable {
+    /// IF/ELSEIF -> next ELSEIF/ELSE/ENDIF of the same chain
+    pub next_branch: HashMap<usize, usize>,
+    /// IF/ELSEIF/ELSE -> ENDIF, WHILE/REPEAT/FOREACH -> ENDWHILE/ENDREPEAT/ENDFOREACH,
+    /// FUNCTION -> ENDFUNCTION, BREAK/CONTINUE -> end of the innermost loop
+    pub end: HashMap<usize, usize>,
+    /// ENDWHILE/ENDREPEAT/ENDFOREACH/ENDFUNCTION -> WHILE/REPEAT/FOREACH/FUNCTION
+    pub start: HashMap<usize, usize>,
+    /// statement -> IF/ELSEIF/ELSE branch, WHILE, REPEAT, FOREACH or FUNCTION
+    /// whose body directly contains it
+    pub parent: HashMap<usize, usize>,
+}
+
+impl BlockTable {
+    pub fn resolve(items: &[Item]) -> Self {
+        let mut table = BlockTable::default();
+        // open blocks: (statement index, branches of an IF chain)
+        let mut open: Vec<(usize, Vec<usize>)> = Vec::new();
+        // BREAK/CONTINUE waiting for the end of their loop, with the loop start
+        let mut loop_exits: Vec<(usize, usize)> = Vec::new();
+
+        for (index, item) in items.iter().enumerate() {
+            if let Some(&parent) = open.last().and_then(|(_, branches)| branches.last()) {
+                table.parent.insert(index, parent);
+            }
+
+            match &item.token_type {
+                TokenType::If { .. }
+                | TokenType::While { .. }
+                | TokenType::Repeat { .. }
+                | TokenType::Foreach { .. }
+                | TokenType::Function { .. } => {
+                    open.push((index, vec![index]));
+                }
+
+                TokenType::ElseIf { .. } | TokenType::Else => {
+                    if let Some((_, branches)) = open.last_mut() {
+                        if let Some(&previous) = branches.last() {
+                            table.next_branch.insert(previous, index);
+                        }
+                        branches.push(index);
+                    }
+                }
+
+                TokenType::EndIf => {
+                    if let Some((_, branches)) = open.pop() {
+                        if let Some(&previous) = branches.last() {
+                            table.next_branch.insert(previous, index);
+                        }
+                        for branch in branches {
+                            table.end.insert(branch, index);
+                        }
+                    }
+                }
+
+                TokenType::EndWhile
+                | TokenType::EndRepeat
+                | TokenType::EndForeach
+                | TokenType::EndFunction => {
+                    if let Some((start, _)) = open.pop() {
+                        table.end.insert(start, index);
+                        table.start.insert(index, start);
+
+                        while let Some(&(exit, loop_start)) = loop_exits.last() {
+                            if loop_start != start {
+                                break;
+                            }
+                            table.end.insert(exit, index);
+                            loop_exits.pop();
+                        }
+                    }
+                }
+
+                TokenType::Break | TokenType::Continue => {
+                    let innermost_loop = open.iter().rev().find(|(start, _)| {
+                        matches!(
+                            items[*start].token_type,
+                            TokenType::While { .. }
+                                | TokenType::Repeat { .. }
+                                | TokenType::Foreach { .. }
+                        )
+                    });
+                    if let Some(&(start, _)) = innermost_loop {
+                        loop_exits.push((index, start));
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        table
+    }
+
+    /// True if the statement at `index` is in the body of the block (or IF
+    /// branch) opened at `block`, the closing statement excluded.
+    pub fn contains(&self, block: usize, index: usize) -> bool {
+        block < index && self.end.get(&block).is_some_and(|&end| index < end)
+    }
+}
+
+/// The jump targets of a script, resolved once before following its control flow.
+#[derive(Debug, Default)]
+pub struct ControlFlow<'a> {
+    pub labels: HashMap<&'a str, usize>,
+    pub functions: HashMap<&'a str, usize>,
+    pub blocks: BlockTable,
+}
+
+impl<'a> ControlFlow<'a> {
+    pub fn resolve(items: &'a [Item]) -> Self {
+        ControlFlow {
+            labels: resolve_labels(items),
+            functions: resolve_functions(items),
+            blocks: BlockTable::resolve(items),
+        }
+    }
+
+    /// Indexes of the statements that may be executed after the one at `index`.
+    /// Conditional statements list every outcome, indexes past the end mean the
+    /// script terminates.
+    ///
+    /// An ELSEIF or ELSE statement stands for the evaluation of its branch, it
+    /// is only reached from the previous IF/ELSEIF when that condition is false.
+    /// Falling through to it from the end of the previous branch goes to ENDIF.
+    pub fn successors(&self, items: &[Item], index: usize) -> Vec<usize> {
+        let ControlFlow {
+            labels,
+            functions,
+            blocks,
+        } = self;
+        let label_target = |label: &str| labels.get(label).copied();
+        let after_end = || blocks.end.get(&index).map(|end| end + 1);
+        let fall_through = |next: usize| match items.get(next).map(|item| &item.token_type) {
+            Some(TokenType::ElseIf { .. } | TokenType::Else) => {
+                blocks.end.get(&next).copied().unwrap_or(next)
+            }
+            _ => next,
+        };
+
+        let next = match &items[index].token_type {
+            // the false outcome evaluates the next branch, ENDIF if there is none
+            TokenType::If { .. } | TokenType::ElseIf { .. } => {
+                let mut next = vec![fall_through(index + 1)];
+                next.extend(blocks.next_branch.get(&index).copied());
+                return next;
+            }
+            // an unconditional GOTO never falls through
+            TokenType::Goto { label } => label_target(label).into_iter().collect(),
+            TokenType::IfGoTo { label, .. } => {
+                let mut next = vec![index + 1];
+                next.extend(label_target(label));
+                next
+            }
+            // a literal non-zero count always enters the loop
+            TokenType::Repeat { count } if count.trim().parse::<u64>().is_ok_and(|n| n > 0) => {
+                vec![index + 1]
+            }
+            TokenType::While { .. } | TokenType::Repeat { .. } | TokenType::Foreach { .. } => {
+                let mut next = vec![index + 1];
+                next.extend(after_end());
+                next
+            }
+            TokenType::Break => after_end().into_iter().collect(),
+            TokenType::EndWhile => blocks.start.get(&index).copied().into_iter().collect(),
+            TokenType::EndRepeat | TokenType::EndForeach => {
+                let mut next = vec![index + 1];
+                next.extend(blocks.start.get(&index).map(|start| start + 1));
+                next
+            }
+            TokenType::Continue => blocks.end.get(&index).copied().into_iter().collect(),
+            // the body is only entered through CALL
+            TokenType::Function { .. } => after_end().into_iter().collect(),
+            // back to the caller, which continues with the statement after its CALL
+            TokenType::Return { .. } | TokenType::EndFunction => Vec::new(),
+            TokenType::Call { function, .. } => {
+                let mut next = vec![index + 1];
+                next.extend(functions.get(function.as_str()).map(|start| start + 1));
+                next
+            }
+            _ => vec![index + 1],
+        };
+        next.into_iter().map(fall_through).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(tokens: Vec<TokenType>) -> Vec<Item> {
+        tokens
+            .into_iter()
+            .map(|token_type| Item {
+                token_type,
+                ..Item::default()
+            })
+            .collect()
+    }
+
+    fn if_(condition: &str) -> TokenType {
+        TokenType::If {
+            condition: condition.to_string(),
+        }
+    }
+
+    fn elseif(condition: &str) -> TokenType {
+        TokenType::ElseIf {
+            condition: condition.to_string(),
+        }
+    }
+
+    fn assign(vmacro: &str) -> TokenType {
+        TokenType::Assignment {
+            vmacro: vmacro.to_string(),
+            expression: "1".to_string(),
+        }
+    }
+
+    fn successors(items: &[Item]) -> Vec<Vec<usize>> {
+        let control_flow = ControlFlow::resolve(items);
+        (0..items.len())
+            .map(|index| control_flow.successors(items, index))
+            .collect()
+    }
+
+    #[test]
+    fn if_else_branches_do_not_fall_into_each_other() {
+        let items = items(vec![
+            if_("$C"),
+            assign("X"),
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+            assign("Y"),
+        ]);
+        let next = successors(&items);
+        assert_eq!(next[0], vec![1, 2]);
+        assert_eq!(next[1], vec![4]);
+        assert_eq!(next[2], vec![3]);
+        assert_eq!(next[3], vec![4]);
+        assert_eq!(next[4], vec![5]);
+    }
+
+    #[test]
+    fn if_without_else_may_skip_its_body() {
+        let items = items(vec![if_("$C"), assign("X"), TokenType::EndIf]);
+        let next = successors(&items);
+        assert_eq!(next[0], vec![1, 2]);
+        assert_eq!(next[1], vec![2]);
+    }
+
+    #[test]
+    fn elseif_chain_evaluates_branches_in_order() {
+        let items = items(vec![
+            if_("$A"),
+            assign("X"),
+            elseif("$B"),
+            assign("X"),
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+        ]);
+        let next = successors(&items);
+        assert_eq!(next[0], vec![1, 2]);
+        assert_eq!(next[1], vec![6]);
+        assert_eq!(next[2], vec![3, 4]);
+        assert_eq!(next[3], vec![6]);
+        assert_eq!(next[4], vec![5]);
+        assert_eq!(next[5], vec![6]);
+    }
+
+    #[test]
+    fn empty_branch_goes_to_endif() {
+        let items = items(vec![
+            if_("$C"),
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+        ]);
+        let next = successors(&items);
+        assert_eq!(next[0], vec![3, 1]);
+        assert_eq!(next[1], vec![2]);
+    }
+
+    #[test]
+    fn nested_if_falls_through_to_outer_endif() {
+        let items = items(vec![
+            if_("$A"),
+            if_("$B"),
+            assign("X"),
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+        ]);
+        let next = successors(&items);
+        assert_eq!(next[0], vec![1, 6]);
+        assert_eq!(next[1], vec![2, 3]);
+        assert_eq!(next[2], vec![5]);
+        assert_eq!(next[4], vec![5]);
+        assert_eq!(next[5], vec![8]);
+        assert_eq!(next[6], vec![7]);
+        assert_eq!(next[7], vec![8]);
+    }
+
+    #[test]
+    fn loop_exit_inside_branch_skips_else() {
+        let items = items(vec![
+            if_("$C"),
+            TokenType::While {
+                condition: "$W".to_string(),
+            },
+            TokenType::Break,
+            TokenType::EndWhile,
+            TokenType::Else,
+            assign("X"),
+            TokenType::EndIf,
+        ]);
+        let next = successors(&items);
+        assert_eq!(next[1], vec![2, 6]);
+        assert_eq!(next[2], vec![6]);
+        assert_eq!(next[3], vec![1]);
+    }
+}
@@ -2,6 +2,7 @@ use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 
 #[derive(Debug, Default, Clone)]
 pub struct IniParserEx {
@@ -10,7 +11,7 @@ pub struct IniParserEx {
 
 impl IniParserEx {
     /// Load an INI file into memory.
-    pub fn load(&mut self, filename: &str) -> bool {
+    pub fn load(&mut self, filename: impl AsRef<Path>) -> bool {
         let file = match File::open(filename) {
             Ok(f) => f,
             Err(_) => return false,
@@ -94,6 +95,11 @@ impl IniParserEx {
         None
     }
 
+    /// Names of the sections, in no particular order.
+    pub fn sections(&self) -> impl Iterator<Item = &String> {
+        self.ini_data.keys()
+    }
+
     /// Check if a section exists.
     pub fn section_exists(&self, section: &str) -> bool {
         self.ini_data.contains_key(section)
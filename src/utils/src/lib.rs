@@ -1,2 +1,5 @@
+pub mod control_flow;
 pub mod ini_parser;
 pub mod string_utils;
+pub mod symbols;
+pub mod value;
@@ -1,46 +1,299 @@
-use regex::Regex;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::Infallible;
 
+// escaped characters first, so that `\$NAME` is never taken for a reference,
+// an unbraced reference may be followed by `[index]`, `[$NAME]` or `.len`
+const RE_MACRO_REFERENCE: &str = r#"\\[$#"]|\$\{([A-Za-z_][A-Za-z0-9_]*)\}|\$([A-Za-z_][A-Za-z0-9_]*)(?:\[(\d+|\$[A-Za-z_][A-Za-z0-9_]*)\]|\.(len)\b)?"#;
+
+/// Characters that lose their meaning when preceded by `\`.
+pub const ESCAPABLE: &[char] = &['$', '#', '"'];
+
+/// List access following a `$NAME` reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accessor<'a> {
+    /// `$NAME[0]`, the first element
+    Index(usize),
+    /// `$NAME[$I]`, the element at the index held by macro `I`
+    IndexMacro(&'a str),
+    /// `$NAME.len`, the number of elements
+    Len,
+}
+
+/// A `$NAME` or `${NAME}` reference found in a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacroReference<'a> {
+    pub name: &'a str,
+    /// The reference as written, including `$`, the braces and the accessor
+    pub text: &'a str,
+    pub accessor: Option<Accessor<'a>>,
+}
+
+fn accessor<'a>(caps: &Captures<'a>) -> Option<Accessor<'a>> {
+    if caps.get(4).is_some() {
+        return Some(Accessor::Len);
+    }
+    let index = caps.get(3)?.as_str();
+    Some(match index.strip_prefix('$') {
+        Some(name) => Accessor::IndexMacro(name),
+        // too large to be a valid index anyway
+        None => Accessor::Index(index.parse().unwrap_or(usize::MAX)),
+    })
+}
+
+/// The value selected by `accessor` in `value`: an element or the length of
+/// a list. The length of a text that is not a list is its character count.
+fn access<'a>(
+    value: &str,
+    accessor: Accessor,
+    lookup: &impl Fn(&str) -> Option<Cow<'a, str>>,
+) -> Option<String> {
+    let index = match accessor {
+        Accessor::Len => {
+            return Some(match parse_list(value) {
+                Some(elements) => elements.len().to_string(),
+                None => value.chars().count().to_string(),
+            })
+        }
+        Accessor::Index(index) => index,
+        Accessor::IndexMacro(name) => lookup(name)?.trim().parse().ok()?,
+    };
+    parse_list(value)?.into_iter().nth(index)
+}
+
+/// Replace every `$NAME` or `${NAME}` reference to a macro in `map` by its
+/// value. `NAME` is the whole identifier after `$`, references to names not
+/// in `map` and escaped `\$` are left untouched. Returns true if the line
+/// changed.
 pub fn replace_macros(line: &mut String, map: &HashMap<String, String>) -> bool {
-    if !line.contains('$') || map.is_empty() {
+    if map.is_empty() {
         return false;
     }
+    replace_macros_with(line, |name| {
+        map.get(name).map(|value| Cow::Borrowed(value.as_str()))
+    })
+}
 
-    // Sort keys by length descending (longest first)
-    let mut keys: Vec<&String> = map.keys().collect();
-    //keys.sort_by(|a, b| b.len().cmp(&a.len()));
-    keys.sort_by_key(|b| std::cmp::Reverse(b.len()));
+/// [`replace_macros`] with the values given by `lookup`.
+pub fn replace_macros_with<'a>(
+    line: &mut String,
+    lookup: impl Fn(&str) -> Option<Cow<'a, str>>,
+) -> bool {
+    if !line.contains('$') {
+        return false;
+    }
 
-    // Join keys into a regex alternation: (XXXX|XXX|...)
-    let pattern = keys
-        .iter()
-        .map(|k| regex::escape(k))
-        .collect::<Vec<_>>()
-        .join("|");
+    let re = Regex::new(RE_MACRO_REFERENCE).unwrap();
+    let matches: Vec<regex::Captures> = re.captures_iter(line).collect();
+    let value = |caps: &Captures| -> Option<Cow<str>> {
+        let name = caps.get(1).or(caps.get(2))?;
+        let value = lookup(name.as_str())?;
+        match accessor(caps) {
+            Some(accessor) => access(&value, accessor, &lookup).map(Cow::Owned),
+            None => Some(value),
+        }
+    };
 
-    let re = Regex::new(&format!(r"\$({})", pattern)).unwrap();
+    let mut replaced = String::with_capacity(line.len());
+    let mut last = 0;
+    for (index, caps) in matches.iter().enumerate() {
+        let whole = caps.get(0).unwrap();
+        replaced.push_str(&line[last..whole.start()]);
+        last = whole.end();
 
-    // Fast path: check if anything matches
-    if !re.is_match(line) {
-        return false;
+        match (value(caps), caps.get(2)) {
+            (Some(value), _) => replaced.push_str(&value),
+            // a kept `$NAME` must not absorb the value substituted right after it
+            (None, Some(name))
+                if whole.as_str().len() == name.len() + 1
+                    && matches.get(index + 1).is_some_and(|next| {
+                        next.get(0).unwrap().start() == whole.end()
+                            && value(next).is_some_and(|value| {
+                                value.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                            })
+                    }) =>
+            {
+                replaced.push_str(&format!("${{{}}}", name.as_str()))
+            }
+            (None, _) => replaced.push_str(whole.as_str()),
+        }
     }
-
-    // Replace matches using map
-    let replaced = re.replace_all(line, |caps: &regex::Captures| {
-        let key = &caps[1];
-        map.get(key).map(|v| v.as_str()).unwrap_or("")
-    });
+    replaced.push_str(&line[last..]);
 
     // Only assign back if the line actually changed
-    if let std::borrow::Cow::Owned(new_text) = replaced {
-        *line = new_text;
+    if replaced != *line {
+        *line = replaced;
         true
     } else {
         false
     }
 }
 
+/// The macro references in `text`, in order of appearance. The index macro
+/// of `$NAME[$I]` is reported after `NAME`.
+pub fn macro_references(text: &str) -> Vec<MacroReference<'_>> {
+    if !text.contains('$') {
+        return Vec::new();
+    }
+
+    let re = Regex::new(RE_MACRO_REFERENCE).unwrap();
+    re.captures_iter(text)
+        .flat_map(|caps| {
+            let Some(name) = caps.get(1).or(caps.get(2)) else {
+                return Vec::new();
+            };
+            let accessor = accessor(&caps);
+            let mut references = vec![MacroReference {
+                name: name.as_str(),
+                text: caps.get(0).unwrap().as_str(),
+                accessor,
+            }];
+            if let (Some(Accessor::IndexMacro(index)), Some(text)) = (accessor, caps.get(3)) {
+                references.push(MacroReference {
+                    name: index,
+                    text: text.as_str(),
+                    accessor: None,
+                });
+            }
+            references
+        })
+        .collect()
+}
+
+/// Parse a list value: `[a, b, "c, d"]`. Elements are separated by commas
+/// and trimmed, double quotes keep commas, brackets and spaces, `\"` and `\\`
+/// inside them stand for the character itself. Lists are not nested.
+/// Returns `None` if `text` is not a list.
+pub fn parse_list(text: &str) -> Option<Vec<String>> {
+    let inner = text.trim().strip_prefix('[')?.strip_suffix(']')?;
+    if inner.trim().is_empty() {
+        return Some(Vec::new());
+    }
+
+    let mut elements = Vec::new();
+    let mut current = String::new();
+    // length of `current` without the unquoted trailing whitespace
+    let mut end = 0;
+    let mut quoted = false;
+    let mut chars = inner.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted && chars.peek().is_some_and(|next| matches!(next, '"' | '\\')) => {
+                current.extend(chars.next());
+                end = current.len();
+            }
+            '"' => {
+                quoted = !quoted;
+                end = current.len();
+            }
+            ',' if !quoted => {
+                current.truncate(end);
+                elements.push(std::mem::take(&mut current));
+                end = 0;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if end > 0 {
+                    current.push(c);
+                }
+            }
+            c => {
+                current.push(c);
+                end = current.len();
+            }
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    current.truncate(end);
+    elements.push(current);
+    Some(elements)
+}
+
+/// Format `elements` as a list value, the reverse of [`parse_list`].
+/// Plugins return list results in this format.
+pub fn format_list<S: AsRef<str>>(elements: &[S]) -> String {
+    let formatted: Vec<String> = elements
+        .iter()
+        .map(|element| {
+            let element = element.as_ref();
+            let plain = !element.is_empty()
+                && element.trim() == element
+                && !element.contains([',', '"', '[', ']', '\\']);
+            if plain {
+                element.to_string()
+            } else {
+                format!("\"{}\"", element.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        })
+        .collect();
+    format!("[{}]", formatted.join(", "))
+}
+
+/// Split plugin arguments on whitespace. Double quotes group words and keep
+/// their whitespace (`"a b"` is one argument, `""` an empty one), `\$`, `\#`
+/// and `\"` stand for the character itself, any other `\` is kept as is.
+/// Returns `None` if a quoted string is not terminated.
+pub fn split_args(text: &str) -> Option<Vec<String>> {
+    split_args_with(text, |part| Ok::<_, Infallible>(part.to_string()))
+        .unwrap_or_else(|err| match err {})
+}
+
+/// [`split_args`] on the script text, then `substitute` the macros in the
+/// parts of each argument. Substituted values are inserted as they are, their
+/// whitespace, quotes and `\` neither split nor quote arguments.
+pub fn split_args_with<E>(
+    text: &str,
+    mut substitute: impl FnMut(&str) -> Result<String, E>,
+) -> Result<Option<Vec<String>>, E> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    // script text of the current argument not substituted yet, references
+    // never contain whitespace, quotes or escapes
+    let mut part = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    let mut flush = |part: &mut String, current: &mut Option<String>| -> Result<(), E> {
+        if !part.is_empty() {
+            let value = substitute(part)?;
+            current.get_or_insert_with(String::new).push_str(&value);
+            part.clear();
+        }
+        Ok(())
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|next| ESCAPABLE.contains(next)) => {
+                flush(&mut part, &mut current)?;
+                current.get_or_insert_with(String::new).extend(chars.next());
+            }
+            '"' => {
+                flush(&mut part, &mut current)?;
+                quoted = !quoted;
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => {
+                flush(&mut part, &mut current)?;
+                args.extend(current.take());
+            }
+            c => part.push(c),
+        }
+    }
+
+    if quoted {
+        return Ok(None);
+    }
+    flush(&mut part, &mut current)?;
+    args.extend(current);
+    Ok(Some(args))
+}
+
 pub fn string_to_bool(input: &str, out: &mut bool) -> bool {
     let s = input.trim();
     if s.eq_ignore_ascii_case("true") {
@@ -90,3 +343,114 @@ pub fn compare_versions(v1: &str, rule: &str, v2: &str) -> bool {
         _ => panic!("Invalid comparison operator: {}", rule),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(text: &str) -> Vec<&str> {
+        macro_references(text)
+            .iter()
+            .map(|reference| reference.name)
+            .collect()
+    }
+
+    #[test]
+    fn macro_references_skip_escaped_dollars() {
+        assert_eq!(names(r"$A ${B}c \$C $_D1"), ["A", "B", "_D1"]);
+        assert!(macro_references("no macros").is_empty());
+        assert_eq!(macro_references("x${B}y")[0].text, "${B}");
+    }
+
+    #[test]
+    fn undefined_macros_are_kept_verbatim() {
+        let map = HashMap::from([("A".to_string(), "1".to_string())]);
+        let mut line = "$A $MISSING ${A}x $AB".to_string();
+        assert!(replace_macros(&mut line, &map));
+        assert_eq!(line, "1 $MISSING 1x $AB");
+
+        let mut unchanged = "$MISSING".to_string();
+        assert!(!replace_macros(&mut unchanged, &map));
+    }
+
+    #[test]
+    fn split_args_groups_quoted_words() {
+        assert_eq!(
+            split_args(r#"a  "b c" "" d"e"f"#).unwrap(),
+            ["a", "b c", "", "def"]
+        );
+        assert!(split_args("   ").unwrap().is_empty());
+        assert_eq!(split_args(r#""unterminated"#), None);
+    }
+
+    #[test]
+    fn split_args_unescapes_only_special_characters() {
+        assert_eq!(
+            split_args(r#"\$A \# \"q\" C:\dir"#).unwrap(),
+            ["$A", "#", "\"q\"", r"C:\dir"]
+        );
+    }
+
+    #[test]
+    fn escaped_dollar_is_not_substituted() {
+        let map = HashMap::from([("A".to_string(), "1".to_string())]);
+        let mut line = r"\$A $A".to_string();
+        replace_macros(&mut line, &map);
+        assert_eq!(line, r"\$A 1");
+    }
+
+    #[test]
+    fn kept_reference_is_braced_before_a_substituted_value() {
+        let map = HashMap::from([("B".to_string(), "x".to_string())]);
+        let mut line = "$A$B".to_string();
+        replace_macros(&mut line, &map);
+        assert_eq!(line, "${A}x");
+    }
+
+    #[test]
+    fn lists_parse_and_format_back() {
+        let elements = parse_list(r#"[ a , "b, c", "", " d ", "e\"f"]"#).unwrap();
+        assert_eq!(elements, ["a", "b, c", "", " d ", "e\"f"]);
+        assert_eq!(parse_list(&format_list(&elements)).unwrap(), elements);
+        assert_eq!(parse_list("[]").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_list("not a list"), None);
+        assert_eq!(parse_list(r#"["open]"#), None);
+    }
+
+    #[test]
+    fn list_access_in_substitution() {
+        let map = HashMap::from([
+            ("L".to_string(), "[a, b, c]".to_string()),
+            ("I".to_string(), "2".to_string()),
+            ("S".to_string(), "text".to_string()),
+        ]);
+        let mut line = "$L[1] $L[$I] $L.len $S.len $L[9]".to_string();
+        replace_macros(&mut line, &map);
+        assert_eq!(line, "b c 3 4 $L[9]");
+
+        let references = macro_references("$L[$I]");
+        assert_eq!(references[0].accessor, Some(Accessor::IndexMacro("I")));
+        assert_eq!(references[1].name, "I");
+    }
+
+    #[test]
+    fn split_args_with_substitutes_inside_arguments() {
+        let map = HashMap::from([("V".to_string(), r#"a "b" c"#.to_string())]);
+        let substitute = |part: &str| {
+            let mut part = part.to_string();
+            replace_macros(&mut part, &map);
+            Ok::<_, ()>(part)
+        };
+        assert_eq!(
+            split_args_with(r#"$V x$V "$V y" \$V"#, substitute),
+            Ok(Some(vec![
+                r#"a "b" c"#.to_string(),
+                r#"xa "b" c"#.to_string(),
+                r#"a "b" c y"#.to_string(),
+                "$V".to_string(),
+            ]))
+        );
+        assert_eq!(split_args_with(r#""$V"#, substitute), Ok(None));
+        assert_eq!(split_args_with("$V", |_| Err("failed")), Err("failed"));
+    }
+}
@@ -0,0 +1,367 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::string_utils;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Defined once with `:=`, immutable afterwards
+    Constant,
+    /// Set by `=`, `?=`, `LOCAL` and CALL parameters, removed by `UNSET`
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub value: Value,
+}
+
+/// Owner of a scope, local scopes are dropped together with their owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    Global,
+    /// A CALL in progress
+    Function,
+    /// The block opened by the statement at this index, for `LOCAL` variables
+    Block(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    kind: ScopeKind,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Scope {
+    fn new(kind: ScopeKind) -> Self {
+        Scope {
+            kind,
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn is_block(&self) -> bool {
+        matches!(self.kind, ScopeKind::Block(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SymbolError {
+    /// A second `:=` for the same name
+    ConstantRedefined(String),
+    /// Assigning, declaring or unsetting a constant
+    ConstantModified(String),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::ConstantRedefined(name) => {
+                write!(f, "constant `{}` is already defined", name)
+            }
+            SymbolError::ConstantModified(name) => {
+                write!(f, "constant `{}` cannot be modified", name)
+            }
+        }
+    }
+}
+
+impl Error for SymbolError {}
+
+/// The macros of a script, constants and variables in one namespace.
+///
+/// Scopes are searched from the innermost one outwards, a function body sees
+/// its own scopes and the global scope but not the scopes of its caller.
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    scopes: Vec<Scope>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            scopes: vec![Scope::new(ScopeKind::Global)],
+        }
+    }
+
+    /// Indexes of the scopes visible from the innermost one, innermost first.
+    fn visible_scopes(&self) -> impl Iterator<Item = usize> + '_ {
+        let function = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.kind == ScopeKind::Function);
+        let innermost = self.scopes.len() - 1;
+        let local_end = function.unwrap_or(0);
+
+        (local_end..=innermost).rev().chain(function.map(|_| 0))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.visible_scopes()
+            .find_map(|index| self.scopes[index].symbols.get(name))
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.get(name).map(|symbol| &symbol.value)
+    }
+
+    fn check_not_constant(&self, name: &str) -> Result<(), SymbolError> {
+        match self.get(name) {
+            Some(symbol) if symbol.kind == SymbolKind::Constant => {
+                Err(SymbolError::ConstantModified(name.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Define a constant in the global scope, its text kept as written.
+    pub fn define_constant(&mut self, name: &str, value: &str) -> Result<(), SymbolError> {
+        match self.scopes[0].symbols.get(name) {
+            Some(symbol) if symbol.kind == SymbolKind::Constant => {
+                return Err(SymbolError::ConstantRedefined(name.to_string()))
+            }
+            Some(_) => return Err(SymbolError::ConstantModified(name.to_string())),
+            None => {}
+        }
+        self.scopes[0].symbols.insert(
+            name.to_string(),
+            Symbol {
+                kind: SymbolKind::Constant,
+                value: Value::Str(value.to_string()),
+            },
+        );
+        Ok(())
+    }
+
+    /// Substitute the references between constants, so their values no longer
+    /// depend on the definition order. Circular references stay unresolved.
+    pub fn resolve_constants(&mut self) {
+        let names: Vec<String> = self.scopes[0]
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.kind == SymbolKind::Constant)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        // a chain of N constants is resolved after at most N rounds
+        for _ in 0..names.len() {
+            let mut changed = false;
+            for name in &names {
+                let mut value = self.scopes[0].symbols[name].value.to_string();
+                if self.substitute(&mut value) {
+                    if let Some(symbol) = self.scopes[0].symbols.get_mut(name) {
+                        symbol.value = Value::Str(value);
+                    }
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Set a variable: the visible one if it exists, otherwise a new global
+    /// one, a function keeps a variable to itself with `declare_local`.
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), SymbolError> {
+        self.check_not_constant(name)?;
+        let target = self
+            .visible_scopes()
+            .find(|&index| self.scopes[index].symbols.contains_key(name))
+            .unwrap_or(0);
+
+        self.scopes[target].symbols.insert(
+            name.to_string(),
+            Symbol {
+                kind: SymbolKind::Variable,
+                value,
+            },
+        );
+        Ok(())
+    }
+
+    /// Declare a variable local to the block opened at `block`, or to the
+    /// current function without block, shadowing any outer variable of the
+    /// same name.
+    pub fn declare_local(
+        &mut self,
+        name: &str,
+        value: Value,
+        block: Option<usize>,
+    ) -> Result<(), SymbolError> {
+        self.check_not_constant(name)?;
+        let target = match block {
+            Some(owner) => {
+                if self.scopes.last().map(|scope| scope.kind) != Some(ScopeKind::Block(owner)) {
+                    self.push_scope(ScopeKind::Block(owner));
+                }
+                self.scopes.len() - 1
+            }
+            None => self
+                .scopes
+                .iter()
+                .rposition(|scope| !scope.is_block())
+                .unwrap_or(0),
+        };
+
+        self.scopes[target].symbols.insert(
+            name.to_string(),
+            Symbol {
+                kind: SymbolKind::Variable,
+                value,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove the visible variable `name`, if any.
+    pub fn unset(&mut self, name: &str) -> Result<(), SymbolError> {
+        self.check_not_constant(name)?;
+        let found = self
+            .visible_scopes()
+            .find(|&index| self.scopes[index].symbols.contains_key(name));
+        if let Some(index) = found {
+            self.scopes[index].symbols.remove(name);
+        }
+        Ok(())
+    }
+
+    pub fn push_scope(&mut self, kind: ScopeKind) {
+        self.scopes.push(Scope::new(kind));
+    }
+
+    /// Drop the innermost function scope and every block scope opened in it.
+    pub fn pop_function_scope(&mut self) {
+        if let Some(index) = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.kind == ScopeKind::Function)
+        {
+            self.scopes.truncate(index);
+        }
+    }
+
+    /// Drop the innermost block scopes for which `is_active` is false,
+    /// stopping at the first function or global scope.
+    pub fn leave_blocks(&mut self, is_active: impl Fn(usize) -> bool) {
+        while let Some(Scope {
+            kind: ScopeKind::Block(owner),
+            ..
+        }) = self.scopes.last()
+        {
+            if is_active(*owner) {
+                break;
+            }
+            self.scopes.pop();
+        }
+    }
+
+    /// Replace the `$NAME` references to visible symbols in `text` by the
+    /// text of their value.
+    pub fn substitute(&self, text: &mut String) -> bool {
+        string_utils::replace_macros_with(text, |name| {
+            self.value(name).map(|value| match value {
+                Value::Str(text) => Cow::Borrowed(text.as_str()),
+                value => Cow::Owned(value.to_string()),
+            })
+        })
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(symbols: &SymbolTable, name: &str) -> Option<String> {
+        symbols.value(name).map(ToString::to_string)
+    }
+
+    #[test]
+    fn constants_are_defined_once_and_never_modified() {
+        let mut symbols = SymbolTable::new();
+        symbols.define_constant("NAME", "hello world").unwrap();
+        assert_eq!(
+            symbols.define_constant("NAME", "other"),
+            Err(SymbolError::ConstantRedefined("NAME".to_string()))
+        );
+        assert_eq!(
+            symbols.assign("NAME", Value::Int(1)),
+            Err(SymbolError::ConstantModified("NAME".to_string()))
+        );
+        assert_eq!(
+            symbols.unset("NAME"),
+            Err(SymbolError::ConstantModified("NAME".to_string()))
+        );
+        assert_eq!(text(&symbols, "NAME").as_deref(), Some("hello world"));
+    }
+
+    #[test]
+    fn constants_resolve_in_any_order() {
+        let mut symbols = SymbolTable::new();
+        symbols.define_constant("PATH", "$DIR/file").unwrap();
+        symbols.define_constant("DIR", "$ROOT/dir").unwrap();
+        symbols.define_constant("ROOT", "/opt").unwrap();
+        symbols.define_constant("LOOP", "$LOOP!").unwrap();
+        symbols.resolve_constants();
+        assert_eq!(text(&symbols, "PATH").as_deref(), Some("/opt/dir/file"));
+        assert!(text(&symbols, "LOOP").unwrap().contains("$LOOP"));
+    }
+
+    #[test]
+    fn function_scope_hides_the_caller_variables() {
+        let mut symbols = SymbolTable::new();
+        symbols.assign("GLOBAL", Value::Int(1)).unwrap();
+        symbols.push_scope(ScopeKind::Function);
+        symbols
+            .declare_local("CALLER", Value::Int(2), None)
+            .unwrap();
+        symbols.push_scope(ScopeKind::Function);
+        assert_eq!(text(&symbols, "GLOBAL").as_deref(), Some("1"));
+        assert_eq!(symbols.value("CALLER"), None);
+
+        symbols.declare_local("INNER", Value::Int(3), None).unwrap();
+        symbols.assign("GLOBAL", Value::Int(4)).unwrap();
+        symbols.assign("NEW", Value::Int(5)).unwrap();
+        symbols.pop_function_scope();
+        assert_eq!(symbols.value("INNER"), None);
+        assert_eq!(text(&symbols, "CALLER").as_deref(), Some("2"));
+        symbols.pop_function_scope();
+        assert_eq!(text(&symbols, "GLOBAL").as_deref(), Some("4"));
+        assert_eq!(text(&symbols, "NEW").as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn block_locals_shadow_until_the_block_ends() {
+        let mut symbols = SymbolTable::new();
+        symbols.assign("X", Value::Int(1)).unwrap();
+        symbols.declare_local("X", Value::Int(2), Some(5)).unwrap();
+        symbols.assign("X", Value::Int(3)).unwrap();
+        assert_eq!(text(&symbols, "X").as_deref(), Some("3"));
+
+        symbols.leave_blocks(|owner| owner == 5);
+        assert_eq!(text(&symbols, "X").as_deref(), Some("3"));
+        symbols.leave_blocks(|_| false);
+        assert_eq!(text(&symbols, "X").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn substitute_uses_the_text_of_the_values() {
+        let mut symbols = SymbolTable::new();
+        symbols.define_constant("NAME", "hello world").unwrap();
+        symbols.assign("N", Value::Float(1.5)).unwrap();
+        let mut line = "$NAME: $N $MISSING".to_string();
+        assert!(symbols.substitute(&mut line));
+        assert_eq!(line, "hello world: 1.5 $MISSING");
+    }
+}
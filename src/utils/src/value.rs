@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::string_utils;
+
+/// A typed script value, as held by the symbol table and produced by
+/// expressions and plugins.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Type a textual value (macro contents, plugin output, bare words):
+    /// `true`/`false` in any case are booleans, decimal numbers are integers,
+    /// or floats if they have a `.`, `[a, b, c]` is a list of typed elements,
+    /// anything else is a string.
+    pub fn from_text(text: &str) -> Value {
+        match string_utils::parse_list(text) {
+            Some(elements) => Value::List(elements.iter().map(|e| Value::from_scalar(e)).collect()),
+            None => Value::from_scalar(text),
+        }
+    }
+
+    // lists are not nested, an element looking like a list is a string
+    fn from_scalar(text: &str) -> Value {
+        let trimmed = text.trim();
+        let is_float = trimmed.contains('.')
+            && trimmed
+                .chars()
+                .all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+
+        if trimmed.eq_ignore_ascii_case("true") {
+            Value::Bool(true)
+        } else if trimmed.eq_ignore_ascii_case("false") {
+            Value::Bool(false)
+        } else if let Ok(number) = trimmed.parse::<i64>() {
+            Value::Int(number)
+        } else if let Some(number) = trimmed.parse::<f64>().ok().filter(|_| is_float) {
+            Value::Float(number)
+        } else {
+            Value::Str(text.to_string())
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "boolean",
+            Value::Int(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(true) => write!(f, "TRUE"),
+            Value::Bool(false) => write!(f, "FALSE"),
+            Value::Int(number) => write!(f, "{}", number),
+            // keeps the `.` of whole numbers, so the text is read back as a float
+            Value::Float(number) => write!(f, "{:?}", number),
+            Value::Str(text) => write!(f, "{}", text),
+            Value::Bytes(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Value::List(elements) => {
+                let elements: Vec<String> = elements.iter().map(Value::to_string).collect();
+                write!(f, "{}", string_utils::format_list(&elements))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_typed() {
+        assert_eq!(Value::from_text("TRUE"), Value::Bool(true));
+        assert_eq!(Value::from_text("false"), Value::Bool(false));
+        assert_eq!(Value::from_text("-42"), Value::Int(-42));
+        assert_eq!(Value::from_text(" 7 "), Value::Int(7));
+        assert_eq!(Value::from_text("2.50"), Value::Float(2.5));
+        assert_eq!(Value::from_text("1e3"), Value::Str("1e3".to_string()));
+        assert_eq!(Value::from_text("inf"), Value::Str("inf".to_string()));
+        assert_eq!(Value::from_text("1.2.3"), Value::Str("1.2.3".to_string()));
+        assert_eq!(
+            Value::from_text("[1, x, \"[2]\"]"),
+            Value::List(vec![
+                Value::Int(1),
+                Value::Str("x".to_string()),
+                Value::Str("[2]".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn display_reads_back_as_the_same_value() {
+        let values = [
+            Value::Bool(true),
+            Value::Int(3),
+            Value::Float(3.0),
+            Value::Float(-0.25),
+            Value::Str("a b".to_string()),
+            Value::List(vec![Value::Int(1), Value::Str("x, y".to_string())]),
+        ];
+        for value in values {
+            assert_eq!(Value::from_text(&value.to_string()), value);
+        }
+        assert_eq!(Value::Float(3.0).to_string(), "3.0");
+        assert_eq!(Value::Bytes(vec![0, 0xab, 16]).to_string(), "0x00ab10");
+        assert_eq!(Value::Bytes(Vec::new()).type_name(), "bytes");
+    }
+}
@@ -1,60 +1,361 @@
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::ops::RangeInclusive;
 
-use interfaces::{Item, TokenType};
-use plugin_api::{ParamsGet, PARAMS_GET_CMDS_KEY, PARAMS_GET_VERS_KEY};
-use plugin_manager::{PluginDescriptor, PluginManager};
-use utils::string_utils;
+use interfaces::{Item, Location, TokenType, UndefinedMacros};
+use plugin_api::{
+    plugin_get_params, PARAMS_GET_CMDS_KEY, PARAMS_GET_PRIV_CMDS_KEY, PARAMS_GET_VERS_KEY,
+};
+use plugin_manager::{PluginLoadError, PluginManager};
+use utils::control_flow::ControlFlow;
+use utils::string_utils::{self, MacroReference};
+
+/// [COMMON] settings key with the comma separated privileged plugins a script may use
+pub const SETTINGS_ALLOW_PRIVILEGED: &str = "ALLOW_PRIVILEGED";
 
 #[derive(Debug)]
-enum ValidateError {
-    PluginNotSetForLoading,
-    PluginLoadingFailed,
-    PluginCommandAvailability,
-    PluginVersionIncompatible,
-    JumpsLabelMismatch,
+#[non_exhaustive]
+pub enum ValidateError {
+    PluginNotSetForLoading {
+        plugin: String,
+        location: Box<Location>,
+    },
+    PluginNotUsed {
+        plugin: String,
+        location: Box<Location>,
+    },
+    PluginLoadingFailed {
+        plugins: Vec<String>,
+        source: PluginLoadError,
+    },
+    PluginCommandsNotReported {
+        plugin: String,
+        location: Box<Location>,
+    },
+    PluginCommandAvailability {
+        plugin: String,
+        command: String,
+        location: Box<Location>,
+    },
+    PluginVersionIncompatible {
+        plugin: String,
+        reported: String,
+        rule: String,
+        vers: String,
+        location: Box<Location>,
+    },
+    /// A privileged plugin, or a privileged command of a plugin (`command`),
+    /// used without being allowed by the host
+    PrivilegeNotAllowed {
+        plugin: String,
+        command: Option<String>,
+        location: Box<Location>,
+    },
+    JumpWithoutLabel {
+        label: String,
+        location: Box<Location>,
+    },
+    LabelWithoutJump {
+        label: String,
+        location: Box<Location>,
+    },
+    DuplicateLabel {
+        label: String,
+        location: Box<Location>,
+        first: Box<Location>,
+    },
+    UnreachableLabel {
+        label: String,
+        location: Box<Location>,
+    },
+    /// A block closing statement (or ELSE/ELSEIF) without its opening statement
+    UnmatchedBlock {
+        keyword: String,
+        location: Box<Location>,
+    },
+    /// A block opening statement without its closing statement
+    UnclosedBlock {
+        keyword: String,
+        location: Box<Location>,
+    },
+    /// BREAK/CONTINUE outside a loop, ELSE/ELSEIF after ELSE
+    MisplacedStatement {
+        keyword: String,
+        location: Box<Location>,
+    },
+    /// CALL of a function without FUNCTION statement
+    UndefinedFunction {
+        function: String,
+        location: Box<Location>,
+    },
+    DuplicateFunction {
+        function: String,
+        location: Box<Location>,
+        first: Box<Location>,
+    },
+    /// CALL with a number of arguments different from the FUNCTION parameters
+    FunctionArity {
+        function: String,
+        expected: usize,
+        found: usize,
+        location: Box<Location>,
+    },
+    /// A `$NAME` reference to a macro that is never defined
+    UndefinedMacro {
+        name: String,
+        location: Box<Location>,
+    },
+    /// A `$NAME` reference to a macro that is defined, but not on every
+    /// path leading to the statement
+    MacroUsedBeforeDefinition {
+        name: String,
+        location: Box<Location>,
+    },
+    /// A second `:=` for a constant
+    ConstantRedefined {
+        name: String,
+        location: Box<Location>,
+        first: Box<Location>,
+    },
+    /// Assigning, declaring or unsetting a constant, or a FUNCTION parameter
+    /// named like one
+    ConstantModified {
+        name: String,
+        location: Box<Location>,
+    },
+    /// All the problems found in collect-all mode, in detection order
+    Multiple(Vec<ValidateError>),
+}
+
+impl ValidateError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ValidateError::PluginNotSetForLoading { location, .. }
+            | ValidateError::PluginNotUsed { location, .. }
+            | ValidateError::PluginCommandsNotReported { location, .. }
+            | ValidateError::PluginCommandAvailability { location, .. }
+            | ValidateError::PluginVersionIncompatible { location, .. }
+            | ValidateError::PrivilegeNotAllowed { location, .. }
+            | ValidateError::JumpWithoutLabel { location, .. }
+            | ValidateError::LabelWithoutJump { location, .. }
+            | ValidateError::DuplicateLabel { location, .. }
+            | ValidateError::UnreachableLabel { location, .. }
+            | ValidateError::UnmatchedBlock { location, .. }
+            | ValidateError::UnclosedBlock { location, .. }
+            | ValidateError::MisplacedStatement { location, .. }
+            | ValidateError::UndefinedFunction { location, .. }
+            | ValidateError::DuplicateFunction { location, .. }
+            | ValidateError::FunctionArity { location, .. }
+            | ValidateError::UndefinedMacro { location, .. }
+            | ValidateError::MacroUsedBeforeDefinition { location, .. }
+            | ValidateError::ConstantRedefined { location, .. }
+            | ValidateError::ConstantModified { location, .. } => Some(location),
+            ValidateError::PluginLoadingFailed { .. } | ValidateError::Multiple(_) => None,
+        }
+    }
+
+    /// The individual errors, flattening collect-all results.
+    pub fn errors(&self) -> Vec<&ValidateError> {
+        match self {
+            ValidateError::Multiple(errors) => errors.iter().flat_map(|e| e.errors()).collect(),
+            _ => vec![self],
+        }
+    }
 }
 
 impl fmt::Display for ValidateError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ValidateError::PluginNotSetForLoading { plugin, .. } => {
+                write!(f, "plugin `{}` is used without LOAD_PLUGIN", plugin)?
+            }
+            ValidateError::PluginNotUsed { plugin, .. } => {
+                write!(f, "plugin `{}` is loaded but never used", plugin)?
+            }
+            ValidateError::PluginLoadingFailed { plugins, source } => {
+                return write!(f, "failed loading plugins {:?}: {}", plugins, source)
+            }
+            ValidateError::PluginCommandsNotReported { plugin, .. } => write!(
+                f,
+                "plugin `{}` does not report its commands ({:?})",
+                plugin, PARAMS_GET_CMDS_KEY
+            )?,
+            ValidateError::PluginCommandAvailability {
+                plugin, command, ..
+            } => write!(
+                f,
+                "command `{}` is not supported by plugin `{}`",
+                command, plugin
+            )?,
+            ValidateError::PluginVersionIncompatible {
+                plugin,
+                reported,
+                rule,
+                vers,
+                ..
+            } => write!(
+                f,
+                "plugin `{}` version mismatch: reported {} (expected {} {})",
+                plugin, reported, rule, vers
+            )?,
+            ValidateError::PrivilegeNotAllowed {
+                plugin,
+                command: None,
+                ..
+            } => write!(
+                f,
+                "plugin `{}` is privileged but not in the {} list",
+                plugin, SETTINGS_ALLOW_PRIVILEGED
+            )?,
+            ValidateError::PrivilegeNotAllowed {
+                plugin,
+                command: Some(command),
+                ..
+            } => write!(
+                f,
+                "command `{}` is privileged but plugin `{}` is not in the {} list",
+                command, plugin, SETTINGS_ALLOW_PRIVILEGED
+            )?,
+            ValidateError::JumpWithoutLabel { label, .. } => {
+                write!(f, "jump to `{}` without corresponding label", label)?
+            }
+            ValidateError::LabelWithoutJump { label, .. } => {
+                write!(f, "label `{}` is never jumped to", label)?
+            }
+            ValidateError::DuplicateLabel { label, first, .. } => {
+                write!(f, "label `{}` is already defined at {}", label, first)?
+            }
+            ValidateError::UnreachableLabel { label, .. } => {
+                write!(f, "label `{}` can never be reached", label)?
+            }
+            ValidateError::UnmatchedBlock { keyword, .. } => {
+                write!(f, "`{}` without matching opening statement", keyword)?
+            }
+            ValidateError::UnclosedBlock { keyword, .. } => {
+                write!(f, "`{}` block is never closed", keyword)?
+            }
+            ValidateError::MisplacedStatement { keyword, .. } => {
+                write!(f, "`{}` is not allowed here", keyword)?
+            }
+            ValidateError::UndefinedFunction { function, .. } => {
+                write!(f, "function `{}` is not defined", function)?
+            }
+            ValidateError::DuplicateFunction {
+                function, first, ..
+            } => write!(f, "function `{}` is already defined at {}", function, first)?,
+            ValidateError::FunctionArity {
+                function,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "function `{}` expects {} argument(s), got {}",
+                function, expected, found
+            )?,
+            ValidateError::UndefinedMacro { name, .. } => {
+                write!(f, "macro `${}` is not defined", name)?
+            }
+            ValidateError::MacroUsedBeforeDefinition { name, .. } => {
+                write!(f, "macro `${}` may be used before it is defined", name)?
+            }
+            ValidateError::ConstantRedefined { name, first, .. } => {
+                write!(f, "constant `{}` is already defined at {}", name, first)?
+            }
+            ValidateError::ConstantModified { name, .. } => {
+                write!(f, "constant `{}` cannot be modified", name)?
+            }
+            ValidateError::Multiple(errors) => {
+                write!(f, "{} validation errors", errors.len())?;
+                for error in errors {
+                    write!(f, "\n\n{}", error)?;
+                }
+                return Ok(());
+            }
+        }
+
+        match self.location() {
+            Some(location) => write!(f, "\n{}", location.snippet()),
+            None => Ok(()),
+        }
     }
 }
 
-impl Error for ValidateError {}
+impl Error for ValidateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ValidateError::PluginLoadingFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
-pub struct ScriptValidator;
+/// A function as seen by the assigned macros analysis.
+struct FunctionBody<'a> {
+    start: usize,
+    /// The statements after FUNCTION, up to ENDFUNCTION
+    body: RangeInclusive<usize>,
+    /// Parameters, LOCAL and FOREACH variables, gone after returning
+    locals: HashSet<&'a str>,
+    /// Macros the body may unset
+    unset: HashSet<&'a str>,
+    /// The CALL statements of the function
+    calls: Vec<usize>,
+}
+
+pub struct ScriptValidator {
+    collect_all: bool,
+    undefined_macros: UndefinedMacros,
+    allowed_privileged: HashSet<String>,
+    constants: HashSet<String>,
+}
 
 impl ScriptValidator {
     pub fn new() -> Self {
-        ScriptValidator {}
+        ScriptValidator {
+            collect_all: false,
+            undefined_macros: UndefinedMacros::default(),
+            allowed_privileged: HashSet::new(),
+            constants: HashSet::new(),
+        }
+    }
+
+    /// Report every problem found instead of stopping at the first one.
+    pub fn set_collect_all(&mut self, collect_all: bool) {
+        self.collect_all = collect_all;
+    }
+
+    /// Report references to undefined macros as errors or as warnings.
+    pub fn set_undefined_macros(&mut self, undefined_macros: UndefinedMacros) {
+        self.undefined_macros = undefined_macros;
+    }
+
+    /// Let the script use the privileged plugin `plugin`, whatever its case.
+    pub fn allow_privileged(&mut self, plugin: &str) {
+        self.allowed_privileged.insert(plugin.to_uppercase());
+    }
+
+    /// Declare a constant defined outside the script, like a `-D` define.
+    pub fn add_constant(&mut self, name: &str) {
+        self.constants.insert(name.to_string());
     }
 
     fn validate_plugins_availability(
         &self,
-        items: &mut Vec<Item>,
+        items: &[Item],
         plugins: &mut HashSet<String>,
-        plugin_commands: &mut HashMap<String, HashSet<String>>,
-    ) -> bool {
+    ) -> Vec<ValidateError> {
         let mut used: HashSet<String> = HashSet::new();
+        let mut errors = Vec::new();
 
         for item in items {
             match &item.token_type {
                 TokenType::LoadPlugin { plugin, .. } => {
                     plugins.insert(plugin.to_string());
                 }
-                TokenType::VariableMacro {
-                    plugin, command, ..
-                }
-                | TokenType::Command {
-                    plugin, command, ..
-                } => {
+                TokenType::VariableMacro { plugin, .. } | TokenType::Command { plugin, .. } => {
                     used.insert(plugin.to_string());
-                    plugin_commands
-                        .entry(plugin.to_string())
-                        .or_default()
-                        .insert(command.to_string());
                 }
                 _ => {}
             }
@@ -63,104 +364,294 @@ impl ScriptValidator {
         println!(" Loaded plugins: {:?}", plugins);
         println!("   Used plugins: {:?}", used);
 
-        if *plugins != used {
-            let missing: HashSet<_> = used.difference(plugins).cloned().collect();
-            println!("Missing plugins: {:?}", missing);
-            return false;
+        // report each offending plugin once, at its first statement
+        let mut reported: HashSet<&str> = HashSet::new();
+        for item in items {
+            match &item.token_type {
+                TokenType::VariableMacro { plugin, .. } | TokenType::Command { plugin, .. }
+                    if !plugins.contains(plugin) && reported.insert(plugin) =>
+                {
+                    errors.push(ValidateError::PluginNotSetForLoading {
+                        plugin: plugin.clone(),
+                        location: Box::new(item.location.span_of(plugin)),
+                    });
+                }
+                TokenType::LoadPlugin { plugin, .. }
+                    if !used.contains(plugin) && reported.insert(plugin) =>
+                {
+                    errors.push(ValidateError::PluginNotUsed {
+                        plugin: plugin.clone(),
+                        location: Box::new(item.location.span_of(plugin)),
+                    });
+                }
+                _ => {}
+            }
         }
-        true
+        errors
     }
 
     fn validate_plugins_commands(
         &self,
-        plugin_commands: &mut HashMap<String, HashSet<String>>,
+        items: &[Item],
         plugin_manager: &mut PluginManager,
-    ) -> bool {
-        for (plugin_name, PluginDescriptor { handle, _lib }) in &plugin_manager.plugins {
-            let mut params: ParamsGet = Default::default();
+    ) -> Vec<ValidateError> {
+        let mut supported_commands: HashMap<&str, Option<Vec<String>>> = HashMap::new();
+        let mut reported: HashSet<(&str, &str)> = HashSet::new();
+        let mut errors = Vec::new();
 
-            unsafe {
-                // *handle ==> *mut PluginHandle
-                // (*handle).as_mut() =>  Option<&mut PluginHandle>, see: impl<T> *mut T {pub fn as_mut(&mut self) -> Option<&mut T>;}
-                // Some(handle) => handle : &mut PluginHandle
-
-                if let Some(handle) = (*handle).as_mut() {
-                    (handle.get_params)(handle.ptr, &mut params);
-                }
+        for item in items {
+            let (TokenType::VariableMacro {
+                plugin, command, ..
             }
+            | TokenType::Command {
+                plugin, command, ..
+            }) = &item.token_type
+            else {
+                continue;
+            };
 
-            if let Some(plugin_supported_commands) = params.get(PARAMS_GET_CMDS_KEY) {
-                println!(
-                    "📝 Plugin {} -> Commands : {:?}",
-                    plugin_name, plugin_supported_commands
-                );
+            // plugins missing a LOAD_PLUGIN are reported by the availability check
+            if !plugin_manager.plugins.contains_key(plugin) {
+                continue;
+            }
 
-                // Find which commands are used in the script for this plugin
-                let used_for_this_plugin = plugin_commands
-                    .get(plugin_name)
-                    .cloned()
+            let commands = supported_commands.entry(plugin).or_insert_with(|| {
+                let mut params = plugin_manager
+                    .plugins
+                    .get(plugin)
+                    .map(|descriptor| unsafe { plugin_get_params(descriptor.handle) })
                     .unwrap_or_default();
 
-                // Check which script commands are NOT supported by plugin
-                let unsupported_used_commands: Vec<_> = used_for_this_plugin
-                    .iter()
-                    .filter(|cmd| !plugin_supported_commands.contains(&cmd.as_str()))
-                    .collect();
+                let commands = params.remove(PARAMS_GET_CMDS_KEY);
+                if let Some(commands) = &commands {
+                    println!("📝 Plugin {} -> Commands : {:?}", plugin, commands);
+                }
+                commands
+            });
 
-                if !unsupported_used_commands.is_empty() {
-                    println!(
-                        "❌ Plugin '{}' missing script commands: {:?}",
-                        plugin_name, unsupported_used_commands
-                    );
-                    return false;
+            match commands {
+                None if reported.insert((plugin, "")) => {
+                    errors.push(ValidateError::PluginCommandsNotReported {
+                        plugin: plugin.clone(),
+                        location: Box::new(item.location.span_of(plugin)),
+                    });
+                }
+                Some(commands)
+                    if !commands.contains(command) && reported.insert((plugin, command)) =>
+                {
+                    errors.push(ValidateError::PluginCommandAvailability {
+                        plugin: plugin.clone(),
+                        command: command.clone(),
+                        location: Box::new(
+                            item.location.span_of(&format!("{}.{}", plugin, command)),
+                        ),
+                    });
                 }
-            } else {
-                println!(
-                    "❌ Section {:?} not found in ParamsGet",
-                    PARAMS_GET_CMDS_KEY
-                );
-                return false;
+                _ => {}
             }
         }
 
-        println!("✅ Commands supported by plugins");
-        true
+        if errors.is_empty() {
+            println!("✅ Commands supported by plugins");
+        }
+        errors
+    }
+
+    /// A plugin reporting privileged commands needs to be allowed for those
+    /// commands only, a plugin with `PRIVILEGED = TRUE` in its section and
+    /// without any for all its commands.
+    fn validate_plugins_privileges(
+        &self,
+        items: &[Item],
+        plugin_manager: &mut PluginManager,
+    ) -> Vec<ValidateError> {
+        // per plugin, the commands needing its authorisation (empty: all of them)
+        let mut privileged_commands: HashMap<&str, Option<Vec<String>>> = HashMap::new();
+        let mut reported: HashSet<(&str, &str)> = HashSet::new();
+        let mut errors = Vec::new();
+
+        for item in items {
+            let (TokenType::VariableMacro {
+                plugin, command, ..
+            }
+            | TokenType::Command {
+                plugin, command, ..
+            }) = &item.token_type
+            else {
+                continue;
+            };
+            if self.allowed_privileged.contains(&plugin.to_uppercase()) {
+                continue;
+            }
+            let Some(descriptor) = plugin_manager.plugins.get(plugin) else {
+                continue;
+            };
+
+            let commands = privileged_commands.entry(plugin).or_insert_with(|| {
+                let commands = unsafe { plugin_get_params(descriptor.handle) }
+                    .remove(PARAMS_GET_PRIV_CMDS_KEY)
+                    .unwrap_or_default();
+                if commands.is_empty() && !descriptor.privileged {
+                    None
+                } else {
+                    Some(commands)
+                }
+            });
+
+            match commands {
+                Some(commands) if commands.is_empty() && reported.insert((plugin, "")) => {
+                    errors.push(ValidateError::PrivilegeNotAllowed {
+                        plugin: plugin.clone(),
+                        command: None,
+                        location: Box::new(item.location.span_of(plugin)),
+                    });
+                }
+                Some(commands)
+                    if commands.contains(command) && reported.insert((plugin, command)) =>
+                {
+                    errors.push(ValidateError::PrivilegeNotAllowed {
+                        plugin: plugin.clone(),
+                        command: Some(command.clone()),
+                        location: Box::new(
+                            item.location.span_of(&format!("{}.{}", plugin, command)),
+                        ),
+                    });
+                }
+                _ => {}
+            }
+        }
+        errors
     }
 
     fn validate_plugins_loading(
         &self,
         plugins: &HashSet<String>,
         plugin_manager: &mut PluginManager,
-    ) -> bool {
-        plugin_manager.load_plugins(plugins)
+    ) -> Result<(), ValidateError> {
+        plugin_manager.load_plugins(plugins).map_err(|source| {
+            let mut plugins: Vec<String> = plugins.iter().cloned().collect();
+            plugins.sort();
+            ValidateError::PluginLoadingFailed { plugins, source }
+        })
     }
 
-    fn validate_jumps(&self, items: &[Item]) -> bool {
-        let mut pending_jumps: HashMap<String, usize> = HashMap::new();
-        let mut defined_labels: HashSet<String> = HashSet::new();
+    fn block_keyword(token_type: &TokenType) -> &'static str {
+        match token_type {
+            TokenType::If { .. } => "IF",
+            TokenType::ElseIf { .. } => "ELSEIF",
+            TokenType::Else => "ELSE",
+            TokenType::EndIf => "ENDIF",
+            TokenType::While { .. } => "WHILE",
+            TokenType::EndWhile => "ENDWHILE",
+            TokenType::Repeat { .. } => "REPEAT",
+            TokenType::EndRepeat => "ENDREPEAT",
+            TokenType::Foreach { .. } => "FOREACH",
+            TokenType::EndForeach => "ENDFOREACH",
+            TokenType::Break => "BREAK",
+            TokenType::Continue => "CONTINUE",
+            TokenType::Function { .. } => "FUNCTION",
+            TokenType::EndFunction => "ENDFUNCTION",
+            TokenType::Return { .. } => "RETURN",
+            _ => "",
+        }
+    }
+
+    fn validate_blocks(&self, items: &[Item]) -> Vec<ValidateError> {
+        // open blocks: (opening item, ELSE already seen)
+        let mut open: Vec<(&Item, bool)> = Vec::new();
+        let mut errors = Vec::new();
+
+        for item in items {
+            let keyword = Self::block_keyword(&item.token_type);
+            let location = || Box::new(item.location.span_of(keyword));
+            let top = open.last().map(|(opening, _)| &opening.token_type);
 
-        for item in items.iter() {
             match &item.token_type {
-                TokenType::IfGoTo { label, .. } => {
-                    // Increment pending jump for this label
-                    *pending_jumps.entry(label.clone()).or_insert(0) += 1;
+                TokenType::If { .. }
+                | TokenType::While { .. }
+                | TokenType::Repeat { .. }
+                | TokenType::Foreach { .. } => {
+                    open.push((item, false));
                 }
 
-                TokenType::Label { label } => {
-                    if let Some(count) = pending_jumps.get_mut(label) {
-                        if *count > 0 {
-                            // Jump exists → label is valid
-                            *count -= 1;
-                            defined_labels.insert(label.clone());
-                        } else {
-                            // No jump pending → invalid label
-                            eprintln!("❌ Invalid label '{}' without preceding jump", label);
-                            return false;
+                // functions cannot be nested in blocks or in other functions
+                TokenType::Function { .. } => {
+                    if !open.is_empty() {
+                        errors.push(ValidateError::MisplacedStatement {
+                            keyword: keyword.to_string(),
+                            location: location(),
+                        });
+                    }
+                    open.push((item, false));
+                }
+
+                TokenType::Return { .. } => {
+                    let in_function = open.iter().any(|(opening, _)| {
+                        matches!(opening.token_type, TokenType::Function { .. })
+                    });
+                    if !in_function {
+                        errors.push(ValidateError::MisplacedStatement {
+                            keyword: keyword.to_string(),
+                            location: location(),
+                        });
+                    }
+                }
+
+                TokenType::ElseIf { .. } | TokenType::Else => match open.last_mut() {
+                    Some((opening, else_seen))
+                        if matches!(opening.token_type, TokenType::If { .. }) =>
+                    {
+                        if *else_seen {
+                            errors.push(ValidateError::MisplacedStatement {
+                                keyword: keyword.to_string(),
+                                location: location(),
+                            });
                         }
+                        *else_seen |= matches!(item.token_type, TokenType::Else);
+                    }
+                    _ => errors.push(ValidateError::UnmatchedBlock {
+                        keyword: keyword.to_string(),
+                        location: location(),
+                    }),
+                },
+
+                TokenType::EndIf
+                | TokenType::EndWhile
+                | TokenType::EndRepeat
+                | TokenType::EndForeach
+                | TokenType::EndFunction => {
+                    let matched = matches!(
+                        (&item.token_type, top),
+                        (TokenType::EndIf, Some(TokenType::If { .. }))
+                            | (TokenType::EndWhile, Some(TokenType::While { .. }))
+                            | (TokenType::EndRepeat, Some(TokenType::Repeat { .. }))
+                            | (TokenType::EndForeach, Some(TokenType::Foreach { .. }))
+                            | (TokenType::EndFunction, Some(TokenType::Function { .. }))
+                    );
+                    if matched {
+                        open.pop();
                     } else {
-                        // Label appeared without any jump
-                        eprintln!("❌ Label '{}' without any jump", label);
-                        return false;
+                        errors.push(ValidateError::UnmatchedBlock {
+                            keyword: keyword.to_string(),
+                            location: location(),
+                        });
+                    }
+                }
+
+                TokenType::Break | TokenType::Continue => {
+                    let in_loop = open.iter().any(|(opening, _)| {
+                        matches!(
+                            opening.token_type,
+                            TokenType::While { .. }
+                                | TokenType::Repeat { .. }
+                                | TokenType::Foreach { .. }
+                        )
+                    });
+                    if !in_loop {
+                        errors.push(ValidateError::MisplacedStatement {
+                            keyword: keyword.to_string(),
+                            location: location(),
+                        });
                     }
                 }
 
@@ -168,18 +659,438 @@ impl ScriptValidator {
             }
         }
 
-        // Check if any jump left without a label
-        for (label, count) in pending_jumps.iter() {
-            if *count > 0 {
-                eprintln!("❌ Jump(s) to '{}' without corresponding label", label);
-                return false;
+        errors.extend(open.into_iter().map(|(opening, _)| {
+            let keyword = Self::block_keyword(&opening.token_type);
+            ValidateError::UnclosedBlock {
+                keyword: keyword.to_string(),
+                location: Box::new(opening.location.span_of(keyword)),
+            }
+        }));
+        errors
+    }
+
+    /// Walk the control flow from the first statement and mark every
+    /// statement that can be executed.
+    fn reachable_items(items: &[Item], control_flow: &ControlFlow) -> Vec<bool> {
+        let mut reachable = vec![false; items.len()];
+        let mut pending: Vec<usize> = vec![0];
+
+        while let Some(index) = pending.pop() {
+            if index >= items.len() || reachable[index] {
+                continue;
             }
+            reachable[index] = true;
+            pending.extend(control_flow.successors(items, index));
         }
+        reachable
+    }
+
+    fn validate_jumps(&self, items: &[Item]) -> Vec<ValidateError> {
+        // Labels can be defined anywhere, before or after their jumps
+        let control_flow = ControlFlow::resolve(items);
+        let labels = &control_flow.labels;
+        let targeted: HashSet<&str> = items
+            .iter()
+            .filter_map(|item| match &item.token_type {
+                TokenType::Goto { label } | TokenType::IfGoTo { label, .. } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut errors = Vec::new();
+
+        let reachable = Self::reachable_items(items, &control_flow);
 
-        true
+        for (index, item) in items.iter().enumerate() {
+            match &item.token_type {
+                TokenType::Goto { label } | TokenType::IfGoTo { label, .. }
+                    if !labels.contains_key(label.as_str()) =>
+                {
+                    errors.push(ValidateError::JumpWithoutLabel {
+                        label: label.clone(),
+                        location: Box::new(item.location.span_of(label)),
+                    });
+                }
+                TokenType::Label { label } => {
+                    let first = labels[label.as_str()];
+                    if first != index {
+                        errors.push(ValidateError::DuplicateLabel {
+                            label: label.clone(),
+                            location: Box::new(item.location.span_of(label)),
+                            first: Box::new(items[first].location.span_of(label)),
+                        });
+                    } else if !targeted.contains(label.as_str()) {
+                        errors.push(ValidateError::LabelWithoutJump {
+                            label: label.clone(),
+                            location: Box::new(item.location.span_of(label)),
+                        });
+                    } else if !reachable[index] {
+                        errors.push(ValidateError::UnreachableLabel {
+                            label: label.clone(),
+                            location: Box::new(item.location.span_of(label)),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        errors
+    }
+
+    fn validate_functions(&self, items: &[Item]) -> Vec<ValidateError> {
+        let functions = ControlFlow::resolve(items).functions;
+        let mut errors = Vec::new();
+
+        for (index, item) in items.iter().enumerate() {
+            match &item.token_type {
+                TokenType::Function { name, .. } if functions[name.as_str()] != index => {
+                    errors.push(ValidateError::DuplicateFunction {
+                        function: name.clone(),
+                        location: Box::new(item.location.span_of(name)),
+                        first: Box::new(items[functions[name.as_str()]].location.span_of(name)),
+                    });
+                }
+                TokenType::Call { function, args, .. } => {
+                    let Some(&start) = functions.get(function.as_str()) else {
+                        errors.push(ValidateError::UndefinedFunction {
+                            function: function.clone(),
+                            location: Box::new(item.location.span_of(function)),
+                        });
+                        continue;
+                    };
+                    let TokenType::Function { params, .. } = &items[start].token_type else {
+                        continue;
+                    };
+                    // split as written, a macro is one argument whatever its value
+                    let found = string_utils::split_args(args).map_or(0, |args| args.len());
+                    if found != params.len() {
+                        errors.push(ValidateError::FunctionArity {
+                            function: function.clone(),
+                            expected: params.len(),
+                            found,
+                            location: Box::new(item.location.span_of(function)),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        errors
     }
 
-    fn validate_plugins_version(&self, items: &[Item], plugin_manager: &mut PluginManager) -> bool {
+    /// The parts of `expression` outside quoted strings, inside them `$` is literal.
+    fn unquoted_parts(expression: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        let mut quoted = false;
+        let mut escaped = false;
+
+        for (offset, c) in expression.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' if quoted => {
+                    quoted = false;
+                    start = offset + 1;
+                }
+                '"' => {
+                    parts.push(&expression[start..offset]);
+                    quoted = true;
+                }
+                _ => {}
+            }
+        }
+        if !quoted {
+            parts.push(&expression[start..]);
+        }
+        parts
+    }
+
+    /// Names of the macros a statement reads when it is executed.
+    fn macro_references(token_type: &TokenType) -> Vec<MacroReference<'_>> {
+        match token_type {
+            TokenType::VariableMacro { args, .. }
+            | TokenType::Command { args, .. }
+            | TokenType::Call { args, .. }
+            | TokenType::Return { value: args } => string_utils::macro_references(args),
+            TokenType::Repeat { count } => string_utils::macro_references(count),
+            TokenType::Foreach { list, .. } => string_utils::macro_references(list),
+            TokenType::Assignment { expression, .. }
+            | TokenType::Local { expression, .. }
+            | TokenType::IfGoTo {
+                condition: expression,
+                ..
+            }
+            | TokenType::If {
+                condition: expression,
+            }
+            | TokenType::ElseIf {
+                condition: expression,
+            }
+            | TokenType::While {
+                condition: expression,
+            } => Self::unquoted_parts(expression)
+                .into_iter()
+                .flat_map(string_utils::macro_references)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The functions of the script by the index of their FUNCTION statement.
+    fn function_bodies<'a>(
+        items: &'a [Item],
+        control_flow: &ControlFlow,
+    ) -> HashMap<usize, FunctionBody<'a>> {
+        let mut functions = HashMap::new();
+        for &start in control_flow.functions.values() {
+            let TokenType::Function { params, .. } = &items[start].token_type else {
+                continue;
+            };
+            let end = control_flow
+                .blocks
+                .end
+                .get(&start)
+                .copied()
+                .unwrap_or(start);
+            let mut function = FunctionBody {
+                start,
+                body: start + 1..=end,
+                locals: params.iter().map(String::as_str).collect(),
+                unset: HashSet::new(),
+                calls: Vec::new(),
+            };
+            for item in &items[start + 1..=end] {
+                match &item.token_type {
+                    TokenType::Local { vmacro, .. } | TokenType::Foreach { vmacro, .. } => {
+                        function.locals.insert(vmacro);
+                    }
+                    TokenType::Unset { vmacro } => {
+                        function.unset.insert(vmacro);
+                    }
+                    _ => {}
+                }
+            }
+            functions.insert(start, function);
+        }
+        for (index, item) in items.iter().enumerate() {
+            if let TokenType::Call { function, .. } = &item.token_type {
+                if let Some(function) = control_flow
+                    .functions
+                    .get(function.as_str())
+                    .and_then(|start| functions.get_mut(start))
+                {
+                    function.calls.push(index);
+                }
+            }
+        }
+        functions
+    }
+
+    /// For each statement, the macros assigned on every path reaching it,
+    /// `None` for statements that are never reached.
+    fn assigned_macros<'a>(
+        items: &'a [Item],
+        control_flow: &ControlFlow,
+    ) -> Vec<Option<HashSet<&'a str>>> {
+        let mut assigned_before: Vec<Option<HashSet<&str>>> = vec![None; items.len()];
+        if items.is_empty() {
+            return assigned_before;
+        }
+        // constants are defined before the script starts
+        let constants = items.iter().filter_map(|item| match &item.token_type {
+            TokenType::ConstantMacro { cmacro, .. } => Some(cmacro.as_str()),
+            _ => None,
+        });
+        assigned_before[0] = Some(constants.collect());
+        let mut pending: Vec<usize> = vec![0];
+
+        let functions = Self::function_bodies(items, control_flow);
+        // per function, the globals assigned on every path returning from it,
+        // absent until it is known to return
+        let mut returned: HashMap<usize, HashSet<&str>> = HashMap::new();
+
+        // the sets only shrink when paths merge, so this reaches a fixed point
+        while let Some(index) = pending.pop() {
+            let Some(assigned_in) = assigned_before[index].clone() else {
+                continue;
+            };
+
+            if let TokenType::Return { .. } | TokenType::EndFunction = &items[index].token_type {
+                let Some(function) = functions.values().find(|f| f.body.contains(&index)) else {
+                    continue;
+                };
+                let globals: HashSet<&str> =
+                    assigned_in.difference(&function.locals).copied().collect();
+                let merged = match returned.get(&function.start) {
+                    Some(current) => current.intersection(&globals).copied().collect(),
+                    None => globals,
+                };
+                if returned.get(&function.start) != Some(&merged) {
+                    returned.insert(function.start, merged);
+                    // the statements after its CALLs see the new set
+                    pending.extend(&function.calls);
+                }
+                continue;
+            }
+
+            for next in control_flow.successors(items, index) {
+                let mut assigned = assigned_in.clone();
+                match &items[index].token_type {
+                    TokenType::VariableMacro { vmacro, .. }
+                    | TokenType::Assignment { vmacro, .. }
+                    | TokenType::Local { vmacro, .. } => {
+                        assigned.insert(vmacro);
+                    }
+                    // the loop variable is only set inside the body
+                    TokenType::Foreach { vmacro, .. } if next == index + 1 => {
+                        assigned.insert(vmacro);
+                    }
+                    TokenType::Unset { vmacro } => {
+                        assigned.remove(vmacro.as_str());
+                    }
+                    // after returning: the globals assigned by the function and the result
+                    TokenType::Call {
+                        function, vmacro, ..
+                    } if next == index + 1 => {
+                        if let Some(function) = control_flow
+                            .functions
+                            .get(function.as_str())
+                            .and_then(|start| functions.get(start))
+                        {
+                            let Some(globals) = returned.get(&function.start) else {
+                                // not known to return yet
+                                continue;
+                            };
+                            assigned.retain(|name| !function.unset.contains(name));
+                            assigned.extend(globals);
+                        }
+                        assigned.extend(vmacro.as_deref());
+                    }
+                    TokenType::Call { .. } => {
+                        if let TokenType::Function { params, .. } = &items[next - 1].token_type {
+                            assigned.extend(params.iter().map(String::as_str));
+                        }
+                    }
+                    _ => {}
+                }
+
+                let Some(current) = assigned_before.get(next) else {
+                    continue;
+                };
+                let merged = match current {
+                    Some(current) => current.intersection(&assigned).copied().collect(),
+                    None => assigned.clone(),
+                };
+                if current.as_ref() != Some(&merged) {
+                    assigned_before[next] = Some(merged);
+                    pending.push(next);
+                }
+            }
+        }
+        assigned_before
+    }
+
+    /// Check that every `$NAME` is assigned on all the paths leading to its use.
+    /// Constant macros are mostly replaced by the parser already, a reference
+    /// left over names a variable macro or a list access with a variable index.
+    fn validate_macros(&self, items: &[Item]) -> Vec<ValidateError> {
+        let assigned_before = Self::assigned_macros(items, &ControlFlow::resolve(items));
+        let defined: HashSet<&str> = items
+            .iter()
+            .flat_map(|item| match &item.token_type {
+                TokenType::ConstantMacro { cmacro: name, .. }
+                | TokenType::VariableMacro { vmacro: name, .. }
+                | TokenType::Assignment { vmacro: name, .. }
+                | TokenType::Local { vmacro: name, .. }
+                | TokenType::Foreach { vmacro: name, .. }
+                | TokenType::Call {
+                    vmacro: Some(name), ..
+                } => vec![name.as_str()],
+                TokenType::Function { params, .. } => params.iter().map(String::as_str).collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        let mut errors = Vec::new();
+
+        for (item, assigned) in items.iter().zip(&assigned_before) {
+            let Some(assigned) = assigned else {
+                continue;
+            };
+            let mut reported: HashSet<&str> = HashSet::new();
+
+            for reference in Self::macro_references(&item.token_type) {
+                if assigned.contains(reference.name)
+                    || self.constants.contains(reference.name)
+                    || !reported.insert(reference.name)
+                {
+                    continue;
+                }
+                let location = Box::new(item.location.span_of(reference.text));
+                let name = reference.name.to_string();
+                errors.push(if defined.contains(name.as_str()) {
+                    ValidateError::MacroUsedBeforeDefinition { name, location }
+                } else {
+                    ValidateError::UndefinedMacro { name, location }
+                });
+            }
+        }
+        errors
+    }
+
+    /// Check that constants are defined once and never written afterwards.
+    fn validate_constants(&self, items: &[Item]) -> Vec<ValidateError> {
+        let mut constants: HashMap<&str, &Item> = HashMap::new();
+        let mut errors = Vec::new();
+
+        for item in items {
+            if let TokenType::ConstantMacro { cmacro, .. } = &item.token_type {
+                match constants.get(cmacro.as_str()) {
+                    Some(first) => errors.push(ValidateError::ConstantRedefined {
+                        name: cmacro.clone(),
+                        location: Box::new(item.location.span_of(cmacro)),
+                        first: Box::new(first.location.span_of(cmacro)),
+                    }),
+                    None => {
+                        constants.insert(cmacro, item);
+                    }
+                }
+            }
+        }
+
+        for item in items {
+            let written: Vec<&String> = match &item.token_type {
+                TokenType::VariableMacro { vmacro, .. }
+                | TokenType::Assignment { vmacro, .. }
+                | TokenType::Local { vmacro, .. }
+                | TokenType::Foreach { vmacro, .. }
+                | TokenType::Unset { vmacro }
+                | TokenType::Call {
+                    vmacro: Some(vmacro),
+                    ..
+                } => vec![vmacro],
+                TokenType::Function { params, .. } => params.iter().collect(),
+                _ => Vec::new(),
+            };
+            for name in written {
+                if constants.contains_key(name.as_str()) {
+                    errors.push(ValidateError::ConstantModified {
+                        name: name.clone(),
+                        location: Box::new(item.location.span_of(name)),
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    fn validate_plugins_version(
+        &self,
+        items: &[Item],
+        plugin_manager: &mut PluginManager,
+    ) -> Vec<ValidateError> {
+        let mut errors = Vec::new();
+
         for item in items {
             if let TokenType::LoadPlugin { plugin, rule, vers } = &item.token_type {
                 if rule.is_empty() || vers.is_empty() {
@@ -190,60 +1101,96 @@ impl ScriptValidator {
                     continue;
                 };
 
-                let mut params = ParamsGet::default();
-                if let Some(handle) = unsafe { descriptor.handle.as_mut() } {
-                    unsafe {
-                        (handle.get_params)(handle.ptr, &mut params);
-                    }
-                }
+                let params = unsafe { plugin_get_params(descriptor.handle) };
 
-                if let Some(version) = params.get(PARAMS_GET_VERS_KEY) {
-                    let plugin_reported_version = version[0];
+                if let Some(plugin_reported_version) = params
+                    .get(PARAMS_GET_VERS_KEY)
+                    .and_then(|version| version.first())
+                {
                     if !string_utils::compare_versions(plugin_reported_version, rule, vers) {
-                        println!(
-                            "❌ Plugin `{}` version mismatch: reported {} (expected {} {})",
-                            plugin, plugin_reported_version, rule, vers
-                        );
-                        return false;
+                        errors.push(ValidateError::PluginVersionIncompatible {
+                            plugin: plugin.clone(),
+                            reported: plugin_reported_version.to_string(),
+                            rule: rule.clone(),
+                            vers: vers.clone(),
+                            location: Box::new(item.location.span_of(vers)),
+                        });
                     }
                 }
             }
         }
 
-        true
+        errors
+    }
+
+    /// In stop-at-first mode, turn the first collected error into the result.
+    fn stop_on_error(&self, errors: &mut Vec<ValidateError>) -> Result<(), ValidateError> {
+        if self.collect_all || errors.is_empty() {
+            return Ok(());
+        }
+        Err(errors.remove(0))
+    }
+
+    fn into_result(mut errors: Vec<ValidateError>) -> Result<(), ValidateError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ValidateError::Multiple(errors)),
+        }
     }
 
     pub fn validate_script(
         &self,
-        items: &mut Vec<Item>,
+        items: &mut [Item],
         plugin_manager: &mut PluginManager,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), ValidateError> {
         let mut used_plugins: HashSet<String> = HashSet::new();
-        let mut plugin_commands: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut errors: Vec<ValidateError> = Vec::new();
 
         println!("Validating script ...");
 
-        if !self.validate_jumps(items) {
-            return Err(Box::new(ValidateError::JumpsLabelMismatch));
-        }
+        errors.extend(self.validate_blocks(items));
+        self.stop_on_error(&mut errors)?;
 
-        if !self.validate_plugins_availability(items, &mut used_plugins, &mut plugin_commands) {
-            return Err(Box::new(ValidateError::PluginNotSetForLoading));
-        }
+        errors.extend(self.validate_jumps(items));
+        self.stop_on_error(&mut errors)?;
 
-        if !self.validate_plugins_loading(&used_plugins, plugin_manager) {
-            return Err(Box::new(ValidateError::PluginLoadingFailed));
-        }
+        errors.extend(self.validate_functions(items));
+        self.stop_on_error(&mut errors)?;
 
-        if !self.validate_plugins_version(items, plugin_manager) {
-            return Err(Box::new(ValidateError::PluginVersionIncompatible));
+        errors.extend(self.validate_constants(items));
+        self.stop_on_error(&mut errors)?;
+
+        let undefined_macros = self.validate_macros(items);
+        match self.undefined_macros {
+            UndefinedMacros::Strict => {
+                errors.extend(undefined_macros);
+                self.stop_on_error(&mut errors)?;
+            }
+            UndefinedMacros::Warn => {
+                for warning in undefined_macros {
+                    println!("⚠️ {}", warning);
+                }
+            }
         }
 
-        if !self.validate_plugins_commands(&mut plugin_commands, plugin_manager) {
-            return Err(Box::new(ValidateError::PluginCommandAvailability));
+        errors.extend(self.validate_plugins_availability(items, &mut used_plugins));
+        self.stop_on_error(&mut errors)?;
+
+        // the remaining checks need the plugins loaded
+        if let Err(error) = self.validate_plugins_loading(&used_plugins, plugin_manager) {
+            errors.push(error);
+            return Self::into_result(errors);
         }
 
-        Ok(())
+        errors.extend(self.validate_plugins_version(items, plugin_manager));
+        self.stop_on_error(&mut errors)?;
+
+        errors.extend(self.validate_plugins_commands(items, plugin_manager));
+        self.stop_on_error(&mut errors)?;
+
+        errors.extend(self.validate_plugins_privileges(items, plugin_manager));
+        Self::into_result(errors)
     }
 }
 
@@ -252,3 +1199,437 @@ impl Default for ScriptValidator {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A plugin manager with an empty settings file, for scripts using no
+    /// plugins.
+    fn manager(name: &str) -> PluginManager {
+        manager_with(name, "[COMMON]\n")
+    }
+
+    /// A plugin manager reading the settings `ini`.
+    fn manager_with(name: &str, ini_text: &str) -> PluginManager {
+        let ini =
+            std::env::temp_dir().join(format!("validator_{}_{}.ini", name, std::process::id()));
+        fs::write(&ini, ini_text).unwrap();
+        let manager = PluginManager::new(Vec::new(), &ini);
+        fs::remove_file(ini).unwrap();
+        manager
+    }
+
+    fn items(tokens: Vec<TokenType>) -> Vec<Item> {
+        tokens
+            .into_iter()
+            .map(|token_type| Item {
+                token_type,
+                ..Item::default()
+            })
+            .collect()
+    }
+
+    fn assign(vmacro: &str, expression: &str) -> TokenType {
+        TokenType::Assignment {
+            vmacro: vmacro.to_string(),
+            expression: expression.to_string(),
+        }
+    }
+
+    fn print(args: &str) -> TokenType {
+        TokenType::Command {
+            plugin: "MATH".to_string(),
+            command: "MPRINT".to_string(),
+            args: args.to_string(),
+        }
+    }
+
+    #[test]
+    fn macro_defined_in_every_branch_is_defined_after_endif() {
+        let items = items(vec![
+            TokenType::If {
+                condition: "1 == 1".to_string(),
+            },
+            assign("X", "10"),
+            TokenType::Else,
+            assign("X", "20"),
+            TokenType::EndIf,
+            print("$X"),
+        ]);
+        assert!(ScriptValidator::new().validate_macros(&items).is_empty());
+    }
+
+    #[test]
+    fn macro_missing_from_one_branch_may_be_undefined() {
+        let items = items(vec![
+            TokenType::If {
+                condition: "1 == 2".to_string(),
+            },
+            assign("X", "10"),
+            TokenType::ElseIf {
+                condition: "1 == 1".to_string(),
+            },
+            assign("Y", "1"),
+            TokenType::Else,
+            assign("X", "20"),
+            TokenType::EndIf,
+            print("$X"),
+        ]);
+        let errors = ScriptValidator::new().validate_macros(&items);
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidateError::MacroUsedBeforeDefinition { name, .. }] if name == "X"
+        ));
+    }
+
+    #[test]
+    fn elseif_condition_is_checked_on_the_false_path() {
+        let items = items(vec![
+            TokenType::If {
+                condition: "1 == 2".to_string(),
+            },
+            assign("X", "10"),
+            TokenType::ElseIf {
+                condition: "$X == 10".to_string(),
+            },
+            TokenType::EndIf,
+        ]);
+        let errors = ScriptValidator::new().validate_macros(&items);
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidateError::MacroUsedBeforeDefinition { name, .. }] if name == "X"
+        ));
+    }
+
+    #[test]
+    fn collect_all_reports_every_problem() {
+        let tokens = || {
+            items(vec![
+                TokenType::Goto {
+                    label: "NOWHERE".to_string(),
+                },
+                TokenType::Label {
+                    label: "UNUSED".to_string(),
+                },
+                TokenType::EndIf,
+            ])
+        };
+
+        let err = ScriptValidator::new()
+            .validate_script(&mut tokens(), &mut manager("first"))
+            .unwrap_err();
+        assert!(matches!(err, ValidateError::UnmatchedBlock { .. }));
+
+        let mut validator = ScriptValidator::new();
+        validator.set_collect_all(true);
+        let err = validator
+            .validate_script(&mut tokens(), &mut manager("all"))
+            .unwrap_err();
+        assert!(matches!(
+            err.errors().as_slice(),
+            [
+                ValidateError::UnmatchedBlock { .. },
+                ValidateError::JumpWithoutLabel { .. },
+                ValidateError::LabelWithoutJump { .. },
+            ]
+        ));
+    }
+
+    fn label(label: &str) -> TokenType {
+        TokenType::Label {
+            label: label.to_string(),
+        }
+    }
+
+    fn goto(label: &str) -> TokenType {
+        TokenType::Goto {
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn backward_jumps_are_valid() {
+        let items = items(vec![
+            label("AGAIN"),
+            TokenType::IfGoTo {
+                condition: "1 == 2".to_string(),
+                label: "AGAIN".to_string(),
+            },
+        ]);
+        assert!(ScriptValidator::new().validate_jumps(&items).is_empty());
+    }
+
+    #[test]
+    fn duplicate_label_points_to_the_first() {
+        let items = items(vec![goto("TWICE"), label("TWICE"), label("TWICE")]);
+        let errors = ScriptValidator::new().validate_jumps(&items);
+        assert!(matches!(
+            errors.as_slice(),
+            [ValidateError::DuplicateLabel { label, .. }] if label == "TWICE"
+        ));
+    }
+
+    #[test]
+    fn blocks_must_be_balanced() {
+        let unclosed = items(vec![TokenType::While {
+            condition: "1 == 1".to_string(),
+        }]);
+        assert!(matches!(
+            ScriptValidator::new().validate_blocks(&unclosed).as_slice(),
+            [ValidateError::UnclosedBlock { keyword, .. }] if keyword == "WHILE"
+        ));
+
+        let unmatched = items(vec![TokenType::EndRepeat]);
+        assert!(matches!(
+            ScriptValidator::new().validate_blocks(&unmatched).as_slice(),
+            [ValidateError::UnmatchedBlock { keyword, .. }] if keyword == "ENDREPEAT"
+        ));
+    }
+
+    #[test]
+    fn break_needs_an_enclosing_loop() {
+        let items = items(vec![
+            TokenType::If {
+                condition: "1 == 1".to_string(),
+            },
+            TokenType::Break,
+            TokenType::EndIf,
+        ]);
+        assert!(matches!(
+            ScriptValidator::new().validate_blocks(&items).as_slice(),
+            [ValidateError::MisplacedStatement { keyword, .. }] if keyword == "BREAK"
+        ));
+    }
+
+    #[test]
+    fn else_after_else_is_misplaced() {
+        let items = items(vec![
+            TokenType::If {
+                condition: "1 == 1".to_string(),
+            },
+            TokenType::Else,
+            TokenType::Else,
+            TokenType::EndIf,
+        ]);
+        assert!(matches!(
+            ScriptValidator::new().validate_blocks(&items).as_slice(),
+            [ValidateError::MisplacedStatement { keyword, .. }] if keyword == "ELSE"
+        ));
+    }
+
+    #[test]
+    fn goto_never_falls_through() {
+        let items = items(vec![
+            goto("END"),
+            label("SKIPPED"),
+            TokenType::IfGoTo {
+                condition: "1 == 1".to_string(),
+                label: "SKIPPED".to_string(),
+            },
+            label("END"),
+        ]);
+        assert!(matches!(
+            ScriptValidator::new().validate_jumps(&items).as_slice(),
+            [ValidateError::UnreachableLabel { label, .. }] if label == "SKIPPED"
+        ));
+    }
+
+    #[test]
+    fn undefined_macros_fail_validation_in_strict_mode() {
+        let tokens = || items(vec![assign("X", "$MISSING")]);
+        assert!(ScriptValidator::new()
+            .validate_script(&mut tokens(), &mut manager("warn"))
+            .is_ok());
+
+        let mut validator = ScriptValidator::new();
+        validator.set_undefined_macros(UndefinedMacros::Strict);
+        let err = validator
+            .validate_script(&mut tokens(), &mut manager("strict"))
+            .unwrap_err();
+        assert!(matches!(err, ValidateError::UndefinedMacro { name, .. } if name == "MISSING"));
+
+        validator.add_constant("MISSING");
+        assert!(validator
+            .validate_script(&mut tokens(), &mut manager("constant"))
+            .is_ok());
+    }
+
+    fn function(name: &str, params: &[&str]) -> TokenType {
+        TokenType::Function {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+        }
+    }
+
+    fn call(function: &str, args: &str) -> TokenType {
+        TokenType::Call {
+            function: function.to_string(),
+            args: args.to_string(),
+            vmacro: None,
+        }
+    }
+
+    #[test]
+    fn calls_must_match_a_function() {
+        let items = items(vec![
+            function("F", &["A", "B"]),
+            TokenType::EndFunction,
+            function("F", &[]),
+            TokenType::EndFunction,
+            call("F", "1 \"2 3\""),
+            call("F", "1"),
+            call("G", ""),
+        ]);
+        assert!(matches!(
+            ScriptValidator::new().validate_functions(&items).as_slice(),
+            [
+                ValidateError::DuplicateFunction { .. },
+                ValidateError::FunctionArity {
+                    expected: 2,
+                    found: 1,
+                    ..
+                },
+                ValidateError::UndefinedFunction { function, .. },
+            ] if function == "G"
+        ));
+    }
+
+    const WASMDEMO: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../plugin/plugin_impl/wasmdemo_plugin/wasmdemo_plugin.wat"
+    );
+
+    /// Privileged wasm plugin reporting `PING` and no privileged command.
+    const PING_WAT: &str = r#"(module
+        (memory (export "memory") 1)
+        (data (i32.const 16) "cmds=PING\0a")
+        (func (export "plugin_abi_version") (result i32) (i32.const 1))
+        (func (export "plugin_alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "plugin_get_params") (result i64) (i64.const 68719476746))
+        (func (export "plugin_set_params") (param i32 i32) (result i32) (i32.const 1))
+        (func (export "plugin_init")))"#;
+
+    fn command(plugin: &str, command: &str) -> TokenType {
+        TokenType::Command {
+            plugin: plugin.to_string(),
+            command: command.to_string(),
+            args: String::new(),
+        }
+    }
+
+    #[test]
+    fn privileged_plugins_need_to_be_allowed() {
+        let ping = std::env::temp_dir().join(format!("validator_ping_{}.wat", std::process::id()));
+        fs::write(&ping, PING_WAT).unwrap();
+        let mut manager = manager_with(
+            "privileges",
+            &format!(
+                "[COMMON]\n[WASMDEMO]\nLIBRARY = {}\nPRIVILEGED = TRUE\nCAPABILITIES = LOG, TIME, FS\n\
+                 FS_DIR = {}\n[PING]\nLIBRARY = {}\nPRIVILEGED = TRUE\nCAPABILITIES =\n",
+                WASMDEMO,
+                std::env::temp_dir().display(),
+                ping.display()
+            ),
+        );
+        manager
+            .load_plugins(&HashSet::from(["WASMDEMO".to_string(), "PING".to_string()]))
+            .unwrap();
+        fs::remove_file(ping).unwrap();
+
+        let items = items(vec![
+            command("WASMDEMO", "WECHO"),
+            command("WASMDEMO", "WREAD"),
+            command("WASMDEMO", "WREAD"),
+            command("PING", "PING"),
+            command("PING", "PING"),
+        ]);
+        let mut validator = ScriptValidator::new();
+        // only the privileged commands of a plugin reporting some, once each
+        assert!(matches!(
+            validator.validate_plugins_privileges(&items, &mut manager).as_slice(),
+            [
+                ValidateError::PrivilegeNotAllowed {
+                    plugin: wasmdemo,
+                    command: Some(command),
+                    ..
+                },
+                ValidateError::PrivilegeNotAllowed {
+                    plugin: ping,
+                    command: None,
+                    ..
+                },
+            ] if wasmdemo == "WASMDEMO" && command == "WREAD" && ping == "PING"
+        ));
+
+        // names are allowed whatever their case
+        validator.allow_privileged("wasmdemo");
+        validator.allow_privileged("Ping");
+        assert!(validator
+            .validate_plugins_privileges(&items, &mut manager)
+            .is_empty());
+    }
+
+    #[test]
+    fn macro_arguments_count_once() {
+        let items = items(vec![
+            function("F", &["P"]),
+            TokenType::EndFunction,
+            call("F", "$L"),
+            call("F", "\"$L $L\""),
+            call("F", "$L $L"),
+        ]);
+        assert!(matches!(
+            ScriptValidator::new().validate_functions(&items).as_slice(),
+            [ValidateError::FunctionArity {
+                expected: 1,
+                found: 2,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn globals_assigned_by_a_function_are_defined_after_its_calls() {
+        let tokens = || {
+            items(vec![
+                function("SETUP", &["P"]),
+                TokenType::Local {
+                    vmacro: "L".to_string(),
+                    expression: "$P".to_string(),
+                },
+                assign("G", "$L"),
+                TokenType::If {
+                    condition: "$P == 1".to_string(),
+                },
+                TokenType::Return {
+                    value: String::new(),
+                },
+                TokenType::EndIf,
+                assign("ONLY_SOMETIMES", "1"),
+                TokenType::EndFunction,
+                call("SETUP", "1"),
+                assign("X", "$G"),
+                assign("Y", "$L $P $ONLY_SOMETIMES"),
+            ])
+        };
+
+        let mut validator = ScriptValidator::new();
+        validator.set_undefined_macros(UndefinedMacros::Strict);
+        validator.set_collect_all(true);
+        let err = validator
+            .validate_script(&mut tokens(), &mut manager("function_globals"))
+            .unwrap_err();
+        let mut names: Vec<&str> = err
+            .errors()
+            .iter()
+            .map(|err| match err {
+                ValidateError::MacroUsedBeforeDefinition { name, .. } => name.as_str(),
+                other => panic!("unexpected error {}", other),
+            })
+            .collect();
+        names.sort();
+        assert_eq!(names, ["L", "ONLY_SOMETIMES", "P"]);
+    }
+}